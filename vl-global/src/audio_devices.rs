@@ -1,6 +1,8 @@
 use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
 
 use indexmap::IndexMap;
+use regex::{Regex, RegexBuilder};
 use serde::{Deserialize, Serialize};
 
 #[derive(
@@ -9,11 +11,249 @@ use serde::{Deserialize, Serialize};
 pub struct AudioDevices {
     pub input_devices: Vec<String>,
     pub output_devices: Vec<String>,
+    /// Input devices selected by a rule instead of by name
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub input_rules: Vec<DeviceRule>,
+    /// Output devices selected by a rule instead of by name
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub output_rules: Vec<DeviceRule>,
+}
+
+/// Selects every available device it matches, so streams that come
+/// and go under new names stay selected. Text is compared ignoring
+/// case.
+#[derive(
+    Debug, PartialEq, Eq, Hash, Serialize, Deserialize, Clone,
+)]
+#[serde(tag = "match", content = "value", rename_all = "snake_case")]
+pub enum DeviceRule {
+    Name(String),
+    ApplicationName(String),
+    /// Description with `*` and `?` wildcards
+    DescriptionGlob(String),
+    DescriptionRegex(DescriptionRegex),
+    MediaClass(String),
+}
+
+/// Regex on the description, compiled once when the rule is created.
+/// An invalid pattern in the config is loaded with a warning, so the
+/// rest of the config still applies.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub struct DescriptionRegex {
+    pattern: String,
+    regex: Result<Regex, regex::Error>,
+}
+
+impl DescriptionRegex {
+    /// Compile `pattern`. An invalid pattern is kept, so it can still
+    /// be edited, but never matches.
+    pub fn new(pattern: String) -> Self {
+        let regex = RegexBuilder::new(&pattern)
+            .case_insensitive(true)
+            .build();
+        Self { pattern, regex }
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.regex.as_ref().is_ok_and(|regex| regex.is_match(text))
+    }
+}
+
+impl From<String> for DescriptionRegex {
+    fn from(pattern: String) -> Self {
+        let regex = Self::new(pattern);
+        if let Err(e) = &regex.regex {
+            log::warn!(
+                "Invalid regex {:?}, the rule never matches: {e}",
+                regex.pattern
+            );
+        }
+        regex
+    }
+}
+
+impl From<DescriptionRegex> for String {
+    fn from(regex: DescriptionRegex) -> Self {
+        regex.pattern
+    }
+}
+
+impl PartialEq for DescriptionRegex {
+    fn eq(&self, other: &Self) -> bool {
+        self.pattern == other.pattern
+    }
+}
+
+impl Eq for DescriptionRegex {}
+
+impl Hash for DescriptionRegex {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.pattern.hash(state);
+    }
+}
+
+impl DeviceRule {
+    pub fn matches(&self, device: &DeviceInfo) -> bool {
+        let equals = |value: Option<&str>, expected: &str| {
+            value.is_some_and(|value| {
+                value.eq_ignore_ascii_case(expected)
+            })
+        };
+        match self {
+            DeviceRule::Name(name) => device.name == *name,
+            DeviceRule::ApplicationName(name) => {
+                equals(device.application_name.as_deref(), name)
+            }
+            DeviceRule::DescriptionGlob(pattern) => {
+                device.description.as_deref().is_some_and(
+                    |description| glob_matches(pattern, description),
+                )
+            }
+            DeviceRule::DescriptionRegex(regex) => {
+                device.description.as_deref().is_some_and(
+                    |description| regex.is_match(description),
+                )
+            }
+            DeviceRule::MediaClass(class) => {
+                equals(device.media_class.as_deref(), class)
+            }
+        }
+    }
+
+    pub fn value(&self) -> &str {
+        match self {
+            DeviceRule::Name(value)
+            | DeviceRule::ApplicationName(value)
+            | DeviceRule::DescriptionGlob(value)
+            | DeviceRule::MediaClass(value) => value,
+            DeviceRule::DescriptionRegex(regex) => &regex.pattern,
+        }
+    }
+
+    /// Replace the value, keeping the kind of rule
+    pub fn set_value(&mut self, value: String) {
+        match self {
+            DeviceRule::Name(old)
+            | DeviceRule::ApplicationName(old)
+            | DeviceRule::DescriptionGlob(old)
+            | DeviceRule::MediaClass(old) => *old = value,
+            DeviceRule::DescriptionRegex(regex) => {
+                *regex = DescriptionRegex::new(value)
+            }
+        }
+    }
+
+    /// Fails when the rule can never match, like an invalid regex
+    pub fn validate(&self) -> Result<(), String> {
+        if self.value().is_empty() {
+            return Err("Empty rule".to_owned());
+        }
+        if let DeviceRule::DescriptionRegex(regex) = self {
+            regex.regex.as_ref().map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+/// Match `text` against a pattern where `*` is any sequence of
+/// characters and `?` any single character
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` and the text position it matched up to
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// A device as reported by the audio server. `name` is the stable
+/// identifier stored in the config, the rest is for display.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
+pub struct DeviceInfo {
+    /// Node ID, changes every time the device reappears
+    pub id: u32,
+    pub name: String,
+    pub description: Option<String>,
+    pub media_class: Option<String>,
+    /// Name of the application that owns the node, if any
+    pub application_name: Option<String>,
+    pub channels: u32,
+}
+
+impl DeviceInfo {
+    /// Name to show to the user
+    pub fn display_name(&self) -> &str {
+        self.description
+            .as_deref()
+            .or(self.application_name.as_deref())
+            .unwrap_or(&self.name)
+    }
+}
+
+/// Devices available right now, as returned by the device module
+#[derive(
+    Debug, PartialEq, Eq, Serialize, Deserialize, Clone, Default,
+)]
+pub struct AvailableDevices {
+    pub input_devices: Vec<DeviceInfo>,
+    pub output_devices: Vec<DeviceInfo>,
+}
+
+impl AvailableDevices {
+    pub fn devices(
+        &self,
+        device_type: &AudioDeviceType,
+    ) -> &[DeviceInfo] {
+        match device_type {
+            AudioDeviceType::INPUT => &self.input_devices,
+            AudioDeviceType::OUTPUT => &self.output_devices,
+        }
+    }
+
+    pub fn find(
+        &self,
+        device_type: &AudioDeviceType,
+        name: &str,
+    ) -> Option<&DeviceInfo> {
+        self.devices(device_type)
+            .iter()
+            .find(|device| device.name == name)
+    }
+
+    /// Whether a node only has output ports, like a microphone, so
+    /// nothing can be linked to it
+    pub fn is_source_only(&self, name: &str) -> bool {
+        self.find(&AudioDeviceType::OUTPUT, name).is_some()
+            && self.find(&AudioDeviceType::INPUT, name).is_none()
+    }
 }
 
 #[derive(Eq, PartialEq, Hash, Clone, Debug)]
 pub enum AudioDeviceStatus {
     SelectedAndAvailable,
+    /// Available and selected by a [`DeviceRule`], not by name
+    MatchedByRule,
     NotSelectedButAvailable,
     SelectedButNotAvailable,
 }
@@ -22,6 +262,7 @@ impl AudioDeviceStatus {
     pub fn is_selected(&self) -> bool {
         match self {
             AudioDeviceStatus::SelectedAndAvailable
+            | AudioDeviceStatus::MatchedByRule
             | AudioDeviceStatus::SelectedButNotAvailable => true,
             AudioDeviceStatus::NotSelectedButAvailable => false,
         }
@@ -43,6 +284,25 @@ pub struct AudioDevicesComparison(
 );
 
 impl AudioDevicesComparison {
+    /// Names of every selected device, available or not. A node can
+    /// be in both lists, so each name comes once.
+    pub fn selected_devices(&self) -> Vec<String> {
+        let mut selected: Vec<String> = vec![];
+        for statuses in self.0.values() {
+            for (status, devices) in statuses {
+                if !status.is_selected() {
+                    continue;
+                }
+                for device in devices {
+                    if !selected.contains(device) {
+                        selected.push(device.clone());
+                    }
+                }
+            }
+        }
+        selected
+    }
+
     pub fn are_there_reconnected_devices(
         old: &Self,
         new: &Self,
@@ -65,6 +325,20 @@ impl AudioDevicesComparison {
             {
                 return true;
             }
+
+            // Devices that showed up and match a rule
+            let were_matched = old_status
+                .get(&AudioDeviceStatus::MatchedByRule)
+                .unwrap();
+            let are_matched_now = new_status
+                .get(&AudioDeviceStatus::MatchedByRule)
+                .unwrap();
+            if are_matched_now
+                .iter()
+                .any(|device| !were_matched.contains(device))
+            {
+                return true;
+            }
         }
         false
     }
@@ -73,25 +347,47 @@ impl AudioDevicesComparison {
 impl AudioDevices {
     fn _compare_lists(
         data: &mut IndexMap<AudioDeviceStatus, Vec<String>>,
-        available: &[String],
+        available: &[DeviceInfo],
         selected: &[String],
+        rules: &[DeviceRule],
     ) {
-        let available_set: HashSet<_> = available.iter().collect();
+        let available_set: HashSet<_> =
+            available.iter().map(|device| &device.name).collect();
         let selected_set: HashSet<_> = selected.iter().collect();
 
         let selected_and_available: Vec<String> = selected_set
             .intersection(&available_set)
             .map(|&s| s.clone())
             .collect();
+
+        // Two nodes can share a name, so keep each name once
+        let mut matched_by_rule: Vec<String> = vec![];
+        let mut not_selected_but_available: Vec<String> = vec![];
+        for device in available {
+            if selected_set.contains(&device.name)
+                || matched_by_rule.contains(&device.name)
+                || not_selected_but_available.contains(&device.name)
+            {
+                continue;
+            }
+            if rules.iter().any(|rule| rule.matches(device)) {
+                matched_by_rule.push(device.name.clone());
+            } else {
+                not_selected_but_available.push(device.name.clone());
+            }
+        }
+        // A name matched by a rule on one node wins over the others
+        not_selected_but_available
+            .retain(|name| !matched_by_rule.contains(name));
+
         data.insert(
             AudioDeviceStatus::SelectedAndAvailable,
             selected_and_available,
         );
-
-        let not_selected_but_available: Vec<String> = available_set
-            .difference(&selected_set)
-            .map(|&s| s.clone())
-            .collect();
+        data.insert(
+            AudioDeviceStatus::MatchedByRule,
+            matched_by_rule,
+        );
         data.insert(
             AudioDeviceStatus::NotSelectedButAvailable,
             not_selected_but_available,
@@ -109,7 +405,7 @@ impl AudioDevices {
     }
 
     pub fn compare_lists(
-        available_list: &AudioDevices,
+        available_list: &AvailableDevices,
         selected_list: &AudioDevices,
     ) -> AudioDevicesComparison {
         let mut result: IndexMap<
@@ -128,6 +424,7 @@ impl AudioDevices {
             &mut input,
             &available_list.input_devices,
             &selected_list.input_devices,
+            &selected_list.input_rules,
         );
 
         result.insert(AudioDeviceType::INPUT, input);
@@ -143,10 +440,162 @@ impl AudioDevices {
             &mut output,
             &available_list.output_devices,
             &selected_list.output_devices,
+            &selected_list.output_rules,
         );
 
         result.insert(AudioDeviceType::OUTPUT, output);
 
         AudioDevicesComparison(result)
     }
+
+    /// Rules of the devices of `device_type`
+    pub fn rules_mut(
+        &mut self,
+        device_type: &AudioDeviceType,
+    ) -> &mut Vec<DeviceRule> {
+        match device_type {
+            AudioDeviceType::INPUT => &mut self.input_rules,
+            AudioDeviceType::OUTPUT => &mut self.output_rules,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device() -> DeviceInfo {
+        DeviceInfo {
+            id: 42,
+            name: "Firefox".to_owned(),
+            description: Some("Firefox - YouTube".to_owned()),
+            media_class: Some("Stream/Output/Audio".to_owned()),
+            application_name: Some("Firefox".to_owned()),
+            channels: 2,
+        }
+    }
+
+    fn regex(pattern: &str) -> DeviceRule {
+        DeviceRule::DescriptionRegex(DescriptionRegex::new(
+            pattern.to_owned(),
+        ))
+    }
+
+    #[test]
+    fn glob_matches_wildcards() {
+        assert!(glob_matches("firefox*", "Firefox - YouTube"));
+        assert!(glob_matches("*YouTube", "Firefox - YouTube"));
+        assert!(glob_matches("*-*", "Firefox - YouTube"));
+        assert!(glob_matches("F?refox", "Firefox"));
+        assert!(glob_matches("*", ""));
+        assert!(glob_matches("a*b*c", "aXbYbZc"));
+        assert!(!glob_matches("firefox", "Firefox - YouTube"));
+        assert!(!glob_matches("?", ""));
+        assert!(!glob_matches("a*b", "aXc"));
+    }
+
+    #[test]
+    fn glob_ignores_case() {
+        assert!(glob_matches("CHROMIUM*", "chromium input"));
+        assert!(glob_matches("çã*", "ÇÃo"));
+    }
+
+    #[test]
+    fn rules_match_their_field() {
+        let device = device();
+        assert!(
+            DeviceRule::Name("Firefox".to_owned()).matches(&device)
+        );
+        assert!(
+            !DeviceRule::Name("firefox".to_owned()).matches(&device)
+        );
+        assert!(DeviceRule::ApplicationName("FIREFOX".to_owned())
+            .matches(&device));
+        assert!(DeviceRule::DescriptionGlob("*youtube".to_owned())
+            .matches(&device));
+        assert!(regex("^firefox - .+$").matches(&device));
+        assert!(!regex("^YouTube").matches(&device));
+        assert!(DeviceRule::MediaClass(
+            "stream/output/audio".to_owned()
+        )
+        .matches(&device));
+        assert!(!DeviceRule::MediaClass("Audio/Sink".to_owned())
+            .matches(&device));
+    }
+
+    #[test]
+    fn rules_never_match_a_missing_field() {
+        let device = DeviceInfo {
+            description: None,
+            application_name: None,
+            media_class: None,
+            ..device()
+        };
+        assert!(!DeviceRule::ApplicationName("Firefox".to_owned())
+            .matches(&device));
+        assert!(!DeviceRule::DescriptionGlob("*".to_owned())
+            .matches(&device));
+        assert!(!regex(".*").matches(&device));
+        assert!(!DeviceRule::MediaClass(
+            "Stream/Output/Audio".to_owned()
+        )
+        .matches(&device));
+    }
+
+    #[test]
+    fn invalid_regex_never_matches() {
+        let rule = regex("(firefox");
+        assert!(!rule.matches(&device()));
+        assert!(rule.validate().is_err());
+    }
+
+    #[test]
+    fn invalid_regex_is_kept_when_loaded() {
+        let valid: Result<AudioDevices, _> = toml::from_str(
+            r#"
+            input_devices = []
+            output_devices = []
+            input_rules = [
+                { match = "description_regex", value = "^fire" },
+            ]
+            "#,
+        );
+        assert_eq!(valid.unwrap().input_rules, vec![regex("^fire")]);
+
+        let invalid: Result<AudioDevices, _> = toml::from_str(
+            r#"
+            input_devices = []
+            output_devices = []
+            input_rules = [
+                { match = "description_regex", value = "(fire" },
+            ]
+            "#,
+        );
+        let rules = invalid.unwrap().input_rules;
+        assert_eq!(rules, vec![regex("(fire")]);
+        assert!(!rules[0].matches(&device()));
+    }
+
+    #[test]
+    fn source_only_devices_have_no_input_ports() {
+        let named = |name: &str| DeviceInfo {
+            name: name.to_owned(),
+            ..device()
+        };
+        let devices = AvailableDevices {
+            input_devices: vec![named("headphones")],
+            output_devices: vec![named("headphones"), named("mic")],
+        };
+        assert!(devices.is_source_only("mic"));
+        assert!(!devices.is_source_only("headphones"));
+        assert!(!devices.is_source_only("gone"));
+    }
+
+    #[test]
+    fn set_value_recompiles_the_regex() {
+        let mut rule = regex("(");
+        rule.set_value("^firefox".to_owned());
+        assert!(rule.validate().is_ok());
+        assert!(rule.matches(&device()));
+    }
 }
@@ -0,0 +1,296 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use crate::vl_config::{ConfigError, ConfigManager};
+
+/// Overrides the token clients authenticate with
+pub const TOKEN_ENV: &str = "VOICELIFT_TOKEN";
+
+/// Name of the token created on first run
+pub const ADMIN_TOKEN_NAME: &str = "admin";
+
+#[derive(Error, Debug)]
+pub enum AuthError {
+    #[error("Config file error.")]
+    ConfigError(#[from] ConfigError),
+    #[error("Error while trying to write or read to system")]
+    IoError(#[from] std::io::Error),
+    #[error("Failed to serialize TOML")]
+    TomlSerializeError(#[from] toml::ser::Error),
+    #[error("Failed to deserialize TOML")]
+    TomlDeserializeError(#[from] toml::de::Error),
+    #[error("Failed to generate a token: {0}")]
+    RandomError(String),
+    #[error("No admin token in the auth file")]
+    NoAdminToken,
+    #[error(
+        "{0} must only be readable by the user, chmod it to 0600"
+    )]
+    InsecureFile(String),
+}
+
+/// What a client may do on the backend. Each scope includes the ones
+/// before it.
+#[derive(
+    Serialize,
+    Deserialize,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+)]
+pub enum Scope {
+    /// Speak, control the speech queue and list the voices
+    Speak,
+    /// Also list the devices and change the links
+    Devices,
+    /// Everything, including reloading voices, the phrase cache and
+    /// writing audio files
+    Admin,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ClientToken {
+    /// Who the token was given to, only shown in the logs
+    pub name: String,
+    pub token: String,
+    pub scope: Scope,
+}
+
+/// Tokens accepted by the backend, kept in `auth.toml` next to the
+/// config file and only readable by the user
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct AuthTokens {
+    #[serde(default)]
+    pub tokens: Vec<ClientToken>,
+}
+
+impl AuthTokens {
+    pub fn path() -> Result<PathBuf, AuthError> {
+        let config_path = ConfigManager::_get_config_path()?;
+        Ok(config_path.with_file_name("auth.toml"))
+    }
+
+    /// Read the tokens. On first run the file is created with an
+    /// admin token.
+    pub fn load_or_create() -> Result<Self, AuthError> {
+        let path = Self::path()?;
+        match Self::read(&path) {
+            Err(AuthError::IoError(e))
+                if e.kind() == ErrorKind::NotFound => {}
+            result => return result,
+        }
+
+        let tokens = Self {
+            tokens: vec![ClientToken {
+                name: ADMIN_TOKEN_NAME.to_owned(),
+                token: generate_token()?,
+                scope: Scope::Admin,
+            }],
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        match options.open(&path) {
+            Ok(mut file) => {
+                file.write_all(
+                    toml::to_string_pretty(&tokens)?.as_bytes(),
+                )?;
+                Ok(tokens)
+            }
+            // Another process created it first
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                Self::read(&path)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Refuse the file if others can read or write it, the tokens may
+    /// have leaked or been added by someone else
+    fn read(path: &Path) -> Result<Self, AuthError> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            if fs::metadata(path)?.permissions().mode() & 0o077 != 0 {
+                return Err(AuthError::InsecureFile(
+                    path.display().to_string(),
+                ));
+            }
+        }
+        Ok(toml::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// Client a token belongs to
+    pub fn find(&self, token: &str) -> Option<&ClientToken> {
+        self.tokens
+            .iter()
+            .find(|client| constant_time_eq(&client.token, token))
+    }
+
+    pub fn admin_token(&self) -> Option<&str> {
+        self.tokens
+            .iter()
+            .find(|client| client.scope == Scope::Admin)
+            .map(|client| client.token.as_str())
+    }
+}
+
+/// Token local clients authenticate with: [`TOKEN_ENV`], then the
+/// admin token of the auth file
+pub fn client_token() -> Result<String, AuthError> {
+    if let Ok(token) = std::env::var(TOKEN_ENV) {
+        return Ok(token);
+    }
+    AuthTokens::load_or_create()?
+        .admin_token()
+        .map(str::to_owned)
+        .ok_or(AuthError::NoAdminToken)
+}
+
+/// 32 random bytes, in hex
+pub fn generate_token() -> Result<String, AuthError> {
+    let mut bytes = [0u8; 32];
+    getrandom::fill(&mut bytes)
+        .map_err(|e| AuthError::RandomError(e.to_string()))?;
+    Ok(bytes.iter().map(|byte| format!("{byte:02x}")).collect())
+}
+
+/// Name to register on the broker with, `prefix` and a random part.
+/// Names must be unique on the broker, so several clients with the
+/// same prefix can run at once.
+pub fn client_name(prefix: &str) -> Result<String, AuthError> {
+    let random = generate_token()?;
+    Ok(format!("{prefix}.{}", &random[..16]))
+}
+
+/// Compare without stopping at the first difference, so the time
+/// taken doesn't tell how much of a guess was right
+pub fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(name: &str, token: &str, scope: Scope) -> ClientToken {
+        ClientToken {
+            name: name.to_owned(),
+            token: token.to_owned(),
+            scope,
+        }
+    }
+
+    #[test]
+    fn constant_time_eq_compares_whole_strings() {
+        assert!(constant_time_eq("", ""));
+        assert!(constant_time_eq("abc123", "abc123"));
+        assert!(!constant_time_eq("abc123", "abc124"));
+        assert!(!constant_time_eq("abc123", "xbc123"));
+        assert!(!constant_time_eq("abc", "abc123"));
+        assert!(!constant_time_eq("abc123", "abc"));
+        assert!(!constant_time_eq("abc", ""));
+    }
+
+    #[test]
+    fn each_scope_includes_the_ones_before() {
+        assert!(Scope::Speak < Scope::Devices);
+        assert!(Scope::Devices < Scope::Admin);
+        let mut scopes =
+            vec![Scope::Admin, Scope::Speak, Scope::Devices];
+        scopes.sort();
+        assert_eq!(
+            scopes,
+            [Scope::Speak, Scope::Devices, Scope::Admin]
+        );
+    }
+
+    #[test]
+    fn finds_the_client_of_a_token() {
+        let tokens = AuthTokens {
+            tokens: vec![
+                token("bot", "1111", Scope::Speak),
+                token(ADMIN_TOKEN_NAME, "2222", Scope::Admin),
+            ],
+        };
+        assert_eq!(
+            tokens.find("1111").map(|c| c.scope),
+            Some(Scope::Speak)
+        );
+        assert_eq!(tokens.find("111"), None);
+        assert_eq!(tokens.find(""), None);
+        assert_eq!(tokens.admin_token(), Some("2222"));
+    }
+
+    #[test]
+    fn tokens_are_random_hex() {
+        let first = generate_token().unwrap();
+        let second = generate_token().unwrap();
+        assert_eq!(first.len(), 64);
+        assert!(first.bytes().all(|byte| byte.is_ascii_hexdigit()));
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn client_names_are_unique() {
+        let first = client_name("voicelift.test").unwrap();
+        let second = client_name("voicelift.test").unwrap();
+        assert!(first.starts_with("voicelift.test."));
+        assert_eq!(first.len(), "voicelift.test.".len() + 16);
+        assert_ne!(first, second);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn auth_file_must_not_be_open_to_others() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!(
+            "voicelift_auth_{}.toml",
+            std::process::id()
+        ));
+        let content = r#"
+            [[tokens]]
+            name = "bot"
+            token = "1111"
+            scope = "Speak"
+        "#;
+        fs::write(&path, content).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600))
+            .unwrap();
+        let tokens = AuthTokens::read(&path).unwrap();
+        assert_eq!(
+            tokens.tokens,
+            [token("bot", "1111", Scope::Speak)]
+        );
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644))
+            .unwrap();
+        assert!(matches!(
+            AuthTokens::read(&path),
+            Err(AuthError::InsecureFile(_))
+        ));
+        fs::remove_file(&path).unwrap();
+    }
+}
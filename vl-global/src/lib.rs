@@ -1,2 +1,3 @@
 pub mod audio_devices;
+pub mod auth;
 pub mod vl_config;
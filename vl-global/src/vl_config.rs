@@ -2,7 +2,7 @@ use config::Config;
 use serde::Deserialize;
 use serde::Serialize;
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
 use std::io::Write;
 use std::path::Path;
@@ -62,13 +62,29 @@ impl ConfigManager {
     }
 
     fn load_settings(config_path: &Path) -> Config {
+        Self::build_settings(config_path).unwrap()
+    }
+
+    fn build_settings(
+        config_path: &Path,
+    ) -> Result<Config, config::ConfigError> {
         Config::builder()
             .add_source(config::File::with_name(
                 config_path.to_str().unwrap(),
             ))
             .add_source(config::Environment::with_prefix("APP"))
             .build()
-            .unwrap()
+    }
+
+    /// Read the config file again without writing it back, so
+    /// changes made by other processes are picked up.
+    pub fn reload(&mut self) -> Result<(), ConfigError> {
+        self.settings = Self::build_settings(&self.config_path)?;
+        Ok(())
+    }
+
+    pub fn get_config_path(&self) -> &Path {
+        &self.config_path
     }
 
     pub fn save(&mut self) -> anyhow::Result<()> {
@@ -94,6 +110,19 @@ impl ConfigManager {
         Ok(())
     }
 
+    /// Read the config file without creating or rewriting it, so
+    /// processes watching it don't reload. A missing file gives the
+    /// defaults.
+    pub fn read_only() -> Result<VlConfig, ConfigError> {
+        let config_path = Self::_get_config_path()?;
+        if !config_path.exists() {
+            return Ok(VlConfig::default());
+        }
+        let config: VlConfig =
+            Self::build_settings(&config_path)?.try_deserialize()?;
+        Ok(config)
+    }
+
     pub fn read(&self) -> Result<VlConfig, ConfigError> {
         let config: VlConfig =
             self.settings.clone().try_deserialize()?;
@@ -112,7 +141,7 @@ impl ConfigManager {
         Ok(())
     }
 
-    fn _get_config_path() -> Result<PathBuf, ConfigError> {
+    pub(crate) fn _get_config_path() -> Result<PathBuf, ConfigError> {
         let home = my_home()?.ok_or(ConfigError::HomeDirNotExist)?;
 
         let path = home
@@ -160,12 +189,179 @@ impl Default for VlConfig {
     Clone,
 )]
 pub struct LinuxConfig {
+    /// Model of the default voice
     pub piper_tts_model: String,
+    /// Alternative voices, each with the engine that speaks it
+    #[serde(default)]
+    pub voices: Vec<VoiceConfig>,
+    /// Speaker of multi-speaker models
+    #[serde(default = "LinuxConfig::default_speaker_id")]
+    pub speaker_id: i64,
     pub pitch: u8,
     pub volume: u8,
+    #[serde(default = "LinuxConfig::default_rate")]
+    pub rate: u8,
+    /// Silence added after each sentence
+    #[serde(default)]
+    pub appended_silence_ms: u32,
+    /// Size limit of the synthesized phrase cache, 0 disables it
+    #[serde(default = "LinuxConfig::default_phrase_cache_max_mb")]
+    pub phrase_cache_max_mb: u32,
+    /// Only play the TTS on the selected devices, not on the default
+    /// sink
+    #[serde(default)]
+    pub detach_from_default_sink: bool,
+    /// Capture device mixed with the TTS into the virtual microphone
+    #[serde(default)]
+    pub mix_microphone: Option<String>,
+    /// Gain of the microphone in the mix, in percent
+    #[serde(default = "LinuxConfig::default_gain")]
+    pub mix_microphone_gain: u16,
+    /// Gain of the TTS in the mix, in percent
+    #[serde(default = "LinuxConfig::default_gain")]
+    pub mix_tts_gain: u16,
+    /// Lower the other apps while the voice plays
+    #[serde(default)]
+    pub ducking: DuckingConfig,
+    /// Lowest volume emergency utterances are spoken with
+    #[serde(default = "LinuxConfig::default_emergency_volume_floor")]
+    pub emergency_volume_floor: u8,
+    /// Times an emergency utterance is spoken again after the first
+    #[serde(default)]
+    pub emergency_repeat: u8,
+    /// Integrated loudness every voice is brought to, in LUFS.
+    /// `None` plays the voices as they come out of the synthesis.
+    #[serde(default)]
+    pub loudness_target_lufs: Option<i8>,
+    /// Socket of the backend, see [`socket_path`]
+    #[serde(default)]
+    pub socket_path: Option<String>,
+    /// Debug FIFO of the backend, see [`fifo_path`]
+    #[serde(default)]
+    pub fifo_path: Option<String>,
+    /// Address the backend also listens on for remote clients, like
+    /// `192.168.0.10:7700`. Only loopback and LAN addresses are
+    /// accepted. `None` disables TCP.
+    #[serde(default)]
+    pub tcp_listen: Option<String>,
+}
+
+/// Synthesis engine of a voice
+#[derive(
+    Debug, PartialEq, Eq, Serialize, Deserialize, Clone, Copy,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum VoiceEngine {
+    Piper,
+    EspeakNg,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
+pub struct VoiceConfig {
+    /// What requests select the voice by
+    pub id: String,
+    pub engine: VoiceEngine,
+    /// Model config path of Piper voices, voice name like `pt-br` of
+    /// espeak-ng voices
+    pub voice: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
+pub struct DuckingConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Volume the other apps are lowered to, in percent
+    #[serde(default = "DuckingConfig::default_level")]
+    pub level: u8,
+    /// Level of specific apps by `application.name`, 100 leaves the
+    /// app alone
+    #[serde(default)]
+    pub apps: BTreeMap<String, u8>,
+}
+
+impl DuckingConfig {
+    fn default_level() -> u8 {
+        30
+    }
+
+    /// Level of an app while ducking, in percent. Names are compared
+    /// ignoring case.
+    pub fn level_for(&self, application_name: Option<&str>) -> u8 {
+        application_name
+            .and_then(|name| {
+                self.apps
+                    .iter()
+                    .find(|(app, _)| app.eq_ignore_ascii_case(name))
+            })
+            .map(|(_, level)| *level)
+            .unwrap_or(self.level)
+            .min(100)
+    }
 }
 
+impl Default for DuckingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            level: Self::default_level(),
+            apps: BTreeMap::new(),
+        }
+    }
+}
+
+/// ID of the voice built from [`LinuxConfig::piper_tts_model`]
+pub const DEFAULT_VOICE_ID: &str = "default";
+
 impl LinuxConfig {
+    fn default_speaker_id() -> i64 {
+        1
+    }
+
+    fn default_rate() -> u8 {
+        50
+    }
+
+    fn default_phrase_cache_max_mb() -> u32 {
+        64
+    }
+
+    fn default_gain() -> u16 {
+        100
+    }
+
+    fn default_emergency_volume_floor() -> u8 {
+        200
+    }
+
+    /// Every configured voice. The default voice is only included
+    /// when a model is set. Fails if two voices share an ID or one
+    /// takes the ID of the default voice.
+    pub fn voices(&self) -> Result<Vec<VoiceConfig>, String> {
+        let mut ids = HashSet::new();
+        for voice in &self.voices {
+            if voice.id == DEFAULT_VOICE_ID {
+                return Err(format!(
+                    "The voice ID {DEFAULT_VOICE_ID} is reserved"
+                ));
+            }
+            if !ids.insert(voice.id.as_str()) {
+                return Err(format!(
+                    "Duplicate voice ID {}",
+                    voice.id
+                ));
+            }
+        }
+        let mut voices = self.voices.clone();
+        if !self.piper_tts_model.is_empty() {
+            voices.push(VoiceConfig {
+                id: DEFAULT_VOICE_ID.to_owned(),
+                engine: VoiceEngine::Piper,
+                voice: self.piper_tts_model.clone(),
+            });
+        }
+        Ok(voices)
+    }
+
     pub fn validate_piper_tts_model(&self, path: &Path) -> bool {
         path.exists()
             && path.is_file()
@@ -178,12 +374,136 @@ impl Default for LinuxConfig {
     fn default() -> Self {
         Self {
             piper_tts_model: String::default(),
+            voices: vec![],
+            speaker_id: Self::default_speaker_id(),
             pitch: 48,
             volume: 128,
+            rate: Self::default_rate(),
+            appended_silence_ms: 0,
+            phrase_cache_max_mb: Self::default_phrase_cache_max_mb(),
+            detach_from_default_sink: false,
+            mix_microphone: None,
+            mix_microphone_gain: Self::default_gain(),
+            mix_tts_gain: Self::default_gain(),
+            ducking: DuckingConfig::default(),
+            emergency_volume_floor:
+                Self::default_emergency_volume_floor(),
+            emergency_repeat: 0,
+            loudness_target_lufs: None,
+            socket_path: None,
+            fifo_path: None,
+            tcp_listen: None,
         }
     }
 }
 
+/// Overrides the socket path of the config
+pub const SOCKET_PATH_ENV: &str = "VOICELIFT_SOCKET";
+/// Overrides the FIFO path of the config
+pub const FIFO_PATH_ENV: &str = "VOICELIFT_FIFO";
+
+/// Per-user directory of the backend socket and FIFO,
+/// `$XDG_RUNTIME_DIR/voicelift`. Without `XDG_RUNTIME_DIR` it falls
+/// back to a directory named after the user in the temp dir, which
+/// the backend refuses unless only the user can access it.
+pub fn runtime_dir() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(runtime_dir) => {
+            PathBuf::from(runtime_dir).join("voicelift")
+        }
+        None => {
+            let user = std::env::var("USER").unwrap_or_default();
+            std::env::temp_dir().join(format!("voicelift-{user}"))
+        }
+    }
+}
+
+/// Socket clients reach the backend on: [`SOCKET_PATH_ENV`], then the
+/// config, then `voicelift.sock` in [`runtime_dir`]
+pub fn socket_path(linux: Option<&LinuxConfig>) -> PathBuf {
+    std::env::var_os(SOCKET_PATH_ENV)
+        .map(PathBuf::from)
+        .or_else(|| linux?.socket_path.as_ref().map(PathBuf::from))
+        .unwrap_or_else(|| runtime_dir().join("voicelift.sock"))
+}
+
+/// Debug FIFO of the backend: [`FIFO_PATH_ENV`], then the config,
+/// then `busrt.fifo` in [`runtime_dir`]
+pub fn fifo_path(linux: Option<&LinuxConfig>) -> PathBuf {
+    std::env::var_os(FIFO_PATH_ENV)
+        .map(PathBuf::from)
+        .or_else(|| linux?.fifo_path.as_ref().map(PathBuf::from))
+        .unwrap_or_else(|| runtime_dir().join("busrt.fifo"))
+}
+
 impl VlConfig {}
 
 //"/usr/share/piper-voices/pt/pt_BR/droidela-v2/medium/droidela-v2.onnx.json",
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn voice(id: &str, engine: VoiceEngine) -> VoiceConfig {
+        VoiceConfig {
+            id: id.to_owned(),
+            engine,
+            voice: "pt-br".to_owned(),
+        }
+    }
+
+    #[test]
+    fn voices_include_the_default_model() {
+        let linux = LinuxConfig {
+            piper_tts_model: "default.onnx.json".to_owned(),
+            voices: vec![voice("pt", VoiceEngine::EspeakNg)],
+            ..Default::default()
+        };
+        let voices = linux.voices().unwrap();
+        assert_eq!(voices.len(), 2);
+        assert_eq!(voices[1].id, DEFAULT_VOICE_ID);
+        assert_eq!(voices[1].engine, VoiceEngine::Piper);
+    }
+
+    #[test]
+    fn duplicate_voice_ids_are_rejected() {
+        let linux = LinuxConfig {
+            voices: vec![
+                voice("pt", VoiceEngine::Piper),
+                voice("pt", VoiceEngine::EspeakNg),
+            ],
+            ..Default::default()
+        };
+        assert!(linux.voices().is_err());
+
+        let linux = LinuxConfig {
+            voices: vec![voice(
+                DEFAULT_VOICE_ID,
+                VoiceEngine::EspeakNg,
+            )],
+            ..Default::default()
+        };
+        assert!(linux.voices().is_err());
+    }
+
+    #[test]
+    fn voices_are_loaded_with_their_engine() {
+        let linux: LinuxConfig = toml::from_str(
+            r#"
+            piper_tts_model = ""
+            pitch = 48
+            volume = 128
+
+            [[voices]]
+            id = "pt"
+            engine = "espeak-ng"
+            voice = "pt-br"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            linux.voices,
+            vec![voice("pt", VoiceEngine::EspeakNg)]
+        );
+    }
+}
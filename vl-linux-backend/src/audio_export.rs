@@ -0,0 +1,99 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::num::{NonZeroU32, NonZeroU8};
+use std::path::Path;
+
+use vl_linux_backend::events::client::{
+    AudioFileFormat, RequestSynthesizeToFile, SynthesizedAudio,
+};
+
+use crate::phrase_cache;
+
+/// Piper models only produce mono audio
+const CHANNELS: u16 = 1;
+
+/// Run the same pipeline used to speak, cache included, writing the
+/// audio to a file instead of the sink.
+///
+/// Synthesis is slow, call this from a blocking context.
+pub fn synthesize_to_file(
+    request: RequestSynthesizeToFile,
+) -> Result<SynthesizedAudio, String> {
+    let path = Path::new(&request.path);
+    if !path.is_absolute() {
+        return Err(format!("Path must be absolute: {path:?}"));
+    }
+
+    let (sample_rate, samples) = phrase_cache::synthesize_cached(
+        &request.phrase,
+        &request.params,
+    )?;
+
+    match request.format {
+        AudioFileFormat::Wav => {
+            write_wav(path, sample_rate, &samples)
+        }
+        AudioFileFormat::Ogg => {
+            write_ogg(path, sample_rate, &samples)
+        }
+    }
+    .map_err(|e| format!("Failed to write {path:?}: {e}"))?;
+
+    let duration_ms =
+        samples.len() as u64 * 1000 / u64::from(sample_rate);
+    log::info!("Wrote {duration_ms} ms of audio to {path:?}");
+
+    Ok(SynthesizedAudio {
+        path: request.path,
+        duration_ms,
+        sample_rate,
+        channels: CHANNELS,
+    })
+}
+
+fn write_wav(
+    path: &Path,
+    sample_rate: u32,
+    samples: &[f32],
+) -> Result<(), String> {
+    let spec = hound::WavSpec {
+        channels: CHANNELS,
+        sample_rate,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::create(path, spec)
+        .map_err(|e| format!("{e}"))?;
+    for sample in samples {
+        let sample = sample.clamp(-1.0, 1.0) * f32::from(i16::MAX);
+        writer
+            .write_sample(sample as i16)
+            .map_err(|e| format!("{e}"))?;
+    }
+    writer.finalize().map_err(|e| format!("{e}"))
+}
+
+fn write_ogg(
+    path: &Path,
+    sample_rate: u32,
+    samples: &[f32],
+) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| format!("{e}"))?;
+    let sample_rate = NonZeroU32::new(sample_rate)
+        .ok_or("Sample rate can't be zero")?;
+    let channels = NonZeroU8::new(CHANNELS as u8)
+        .ok_or("Channel count can't be zero")?;
+
+    let mut encoder = vorbis_rs::VorbisEncoderBuilder::new(
+        sample_rate,
+        channels,
+        BufWriter::new(file),
+    )
+    .and_then(|mut builder| builder.build())
+    .map_err(|e| format!("{e}"))?;
+    encoder
+        .encode_audio_block([samples])
+        .map_err(|e| format!("{e}"))?;
+    encoder.finish().map_err(|e| format!("{e}"))?;
+    Ok(())
+}
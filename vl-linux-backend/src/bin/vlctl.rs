@@ -0,0 +1,416 @@
+//! Command-line client of vl-linux-backend, for scripts and hotkeys.
+//! Every command prints its result as JSON.
+use std::process::ExitCode;
+use std::sync::{Arc, OnceLock};
+
+use busrt::async_trait;
+use busrt::broker::BROKER_NAME;
+use busrt::ipc::{Client, Config};
+use busrt::rpc::{
+    Rpc, RpcClient, RpcError, RpcEvent, RpcHandlers, RpcResult,
+};
+use busrt::QoS;
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use vl_global::auth;
+use vl_global::vl_config::{self, ConfigManager, LinuxConfig};
+use vl_linux_backend::events::client::{
+    LinkSource, Priority, QueueMode, RequestAuthenticate,
+    RequestCapabilities, RequestDeviceLinkage,
+    RequestDeviceUnLinkage, RequestDevices, RequestGetLinks,
+    RequestHello, RequestSpeechQueue, RequestStopTTS, RequestTTS,
+    RequestVoiceStatus, ResponseAuthenticate, ResponseCapabilities,
+    ResponseDeviceLinkage, ResponseDeviceUnLinkage, ResponseDevices,
+    ResponseGetLinks, ResponseHello, ResponseSpeechQueue,
+    ResponseStopTTS, ResponseTTS, ResponseVoiceStatus, SpeechParams,
+    SpeechQueueAction, Unauthorized, METHOD_AUTHENTICATE,
+    METHOD_CAPABILITIES, METHOD_GET_DEVICES, METHOD_GET_LINKS,
+    METHOD_HELLO, METHOD_LINK_DEVICES, METHOD_SPEAK,
+    METHOD_SPEECH_QUEUE, METHOD_STOP_SPEAK, METHOD_UNLINK_DEVICES,
+    METHOD_VOICE_STATUS, PROTOCOL_VERSION,
+    RPC_ERROR_CODE_UNAUTHORIZED,
+};
+use vl_linux_backend::events::server::{
+    ResponseSession, METHOD_SESSION, SESSIONS_CLIENT,
+};
+
+#[derive(Parser)]
+#[command(name = "vlctl", about = "Control the VoiceLift backend")]
+struct Cli {
+    /// Socket of the backend, or `host:port` of its TCP listener.
+    /// Defaults to `VOICELIFT_SOCKET`, then the config.
+    #[arg(long, global = true)]
+    socket: Option<String>,
+    /// Token to authenticate with. Defaults to `VOICELIFT_TOKEN`,
+    /// then the admin token of the auth file.
+    #[arg(long, global = true)]
+    token: Option<String>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Speak a phrase, with the speech settings of the config
+    Speak {
+        phrase: String,
+        /// Voice to speak with, the default voice when not set
+        #[arg(long)]
+        voice: Option<String>,
+        #[arg(long, value_enum, default_value_t = Mode::Enqueue)]
+        mode: Mode,
+        #[arg(long, value_enum, default_value_t = SpeechPriority::Normal)]
+        priority: SpeechPriority,
+        #[arg(long)]
+        speaker: Option<i64>,
+        #[arg(long)]
+        pitch: Option<u8>,
+        #[arg(long)]
+        volume: Option<u8>,
+        #[arg(long)]
+        rate: Option<u8>,
+    },
+    /// Stop speaking and drop the queue
+    Stop,
+    /// List the audio devices
+    Devices,
+    /// Link the speech to a device
+    Link {
+        target_device: String,
+        #[arg(long, value_enum, default_value_t = Source::Tts)]
+        source: Source,
+    },
+    /// Unlink the speech from a device
+    Unlink {
+        target_device: String,
+        #[arg(long, value_enum, default_value_t = Source::Tts)]
+        source: Source,
+    },
+    /// List the configured voices
+    Voices,
+    /// Show the speech queue and the links
+    Status,
+    /// Show the backend version, its engines and its methods
+    Capabilities,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Mode {
+    Interrupt,
+    Enqueue,
+    DropIfBusy,
+}
+
+impl From<Mode> for QueueMode {
+    fn from(mode: Mode) -> Self {
+        match mode {
+            Mode::Interrupt => QueueMode::Interrupt,
+            Mode::Enqueue => QueueMode::Enqueue,
+            Mode::DropIfBusy => QueueMode::DropIfBusy,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum SpeechPriority {
+    Normal,
+    High,
+    Emergency,
+}
+
+impl From<SpeechPriority> for Priority {
+    fn from(priority: SpeechPriority) -> Self {
+        match priority {
+            SpeechPriority::Normal => Priority::Normal,
+            SpeechPriority::High => Priority::High,
+            SpeechPriority::Emergency => Priority::Emergency,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Source {
+    Tts,
+    Mix,
+}
+
+impl From<Source> for LinkSource {
+    fn from(source: Source) -> Self {
+        match source {
+            Source::Tts => LinkSource::Tts,
+            Source::Mix => LinkSource::Mix,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Status {
+    queue: serde_json::Value,
+    links: serde_json::Value,
+}
+
+fn rpc_error_message(method: &str, e: &RpcError) -> String {
+    if e.code() == RPC_ERROR_CODE_UNAUTHORIZED {
+        let unauthorized = e.data().and_then(|data| {
+            rmp_serde::from_slice::<Unauthorized>(data).ok()
+        });
+        if let Some(unauthorized) = unauthorized {
+            return format!(
+                "{method} needs the {:?} scope, the token has {:?}",
+                unauthorized.required, unauthorized.granted
+            );
+        }
+    }
+    let data =
+        e.data().map(String::from_utf8_lossy).unwrap_or_default();
+    format!("Failed to call {method}: error {} {data}", e.code())
+}
+
+/// Send a request and return the payload of the response
+async fn call<Req: Serialize, Resp: DeserializeOwned>(
+    rpc: &RpcClient,
+    method: &str,
+    request: &Req,
+) -> Result<Resp, String> {
+    let payload = rmp_serde::to_vec_named(request)
+        .map_err(|e| format!("{e}"))?;
+    let result = rpc
+        .call(BROKER_NAME, method, payload.into(), QoS::Processed)
+        .await
+        .map_err(|e| rpc_error_message(method, &e))?;
+    rmp_serde::from_slice(result.payload())
+        .map_err(|e| format!("Failed to deserialize response: {e}"))
+}
+
+fn to_json<T: Serialize>(
+    value: T,
+) -> Result<serde_json::Value, String> {
+    serde_json::to_value(value).map_err(|e| format!("{e}"))
+}
+
+/// Linux section of the config. It is only read: writing it would
+/// make the backend reload it.
+fn linux_config() -> Result<LinuxConfig, String> {
+    let config =
+        ConfigManager::read_only().map_err(|e| format!("{e}"))?;
+    config.linux.ok_or_else(|| {
+        "The linux section of the config was not found".to_owned()
+    })
+}
+
+/// Speech settings of the config, with the overrides of the command
+fn speech_params(
+    linux: Result<LinuxConfig, String>,
+    voice_id: Option<String>,
+    speaker: Option<i64>,
+    pitch: Option<u8>,
+    volume: Option<u8>,
+    rate: Option<u8>,
+) -> Result<SpeechParams, String> {
+    let linux = linux?;
+    Ok(SpeechParams {
+        voice_id,
+        speaker_id: speaker.unwrap_or(linux.speaker_id),
+        pitch: pitch.unwrap_or(linux.pitch),
+        volume: volume.unwrap_or(linux.volume),
+        rate: rate.unwrap_or(linux.rate),
+        appended_silence_ms: linux.appended_silence_ms,
+    })
+}
+
+/// Answers the backend asking if we are the client that
+/// authenticated
+#[derive(Default, Clone)]
+struct SessionHandlers {
+    secret: Arc<OnceLock<String>>,
+}
+
+#[async_trait]
+impl RpcHandlers for SessionHandlers {
+    async fn handle_call(&self, event: RpcEvent) -> RpcResult {
+        if event.sender() != SESSIONS_CLIENT
+            || event.parse_method()? != METHOD_SESSION
+        {
+            return Err(RpcError::method(None));
+        }
+        let response = ResponseSession {
+            secret: self.secret.get().cloned(),
+        };
+        Ok(Some(rmp_serde::to_vec_named(&response)?))
+    }
+}
+
+/// Connect to the backend. Names must be unique on the broker, the
+/// random one lets several vlctl run at once.
+async fn connect(
+    socket: &str,
+    handlers: SessionHandlers,
+) -> Result<RpcClient, String> {
+    let name = auth::client_name("voicelift.vlctl")
+        .map_err(|e| format!("{e}"))?;
+    let client =
+        Client::connect(&Config::new(socket, &name)).await.map_err(
+            |e| format!("Failed to connect to {socket}: {e}"),
+        )?;
+    Ok(RpcClient::new(client, handlers))
+}
+
+async fn run(
+    rpc: &RpcClient,
+    session: &SessionHandlers,
+    token: Option<String>,
+    linux: Result<LinuxConfig, String>,
+    command: Command,
+) -> Result<serde_json::Value, String> {
+    let hello = RequestHello {
+        protocol_version: PROTOCOL_VERSION,
+    };
+    let response: ResponseHello =
+        call(rpc, METHOD_HELLO, &hello).await?;
+    let backend = response.result?;
+    if backend.protocol_version != PROTOCOL_VERSION {
+        return Err(format!(
+            "The backend {} speaks protocol {}, vlctl speaks {}",
+            backend.backend_version,
+            backend.protocol_version,
+            PROTOCOL_VERSION
+        ));
+    }
+
+    let token = match token {
+        Some(token) => token,
+        None => auth::client_token().map_err(|e| format!("{e}"))?,
+    };
+    let response: ResponseAuthenticate = call(
+        rpc,
+        METHOD_AUTHENTICATE,
+        &RequestAuthenticate { token },
+    )
+    .await?;
+    _ = session.secret.set(response.result?.secret);
+
+    match command {
+        Command::Speak {
+            phrase,
+            voice,
+            mode,
+            priority,
+            speaker,
+            pitch,
+            volume,
+            rate,
+        } => {
+            let request = RequestTTS {
+                phrase,
+                params: speech_params(
+                    linux, voice, speaker, pitch, volume, rate,
+                )?,
+                mode: mode.into(),
+                priority: priority.into(),
+            };
+            let response: ResponseTTS =
+                call(rpc, METHOD_SPEAK, &request).await?;
+            to_json(response.result?)
+        }
+        Command::Stop => {
+            let response: ResponseStopTTS =
+                call(rpc, METHOD_STOP_SPEAK, &RequestStopTTS {})
+                    .await?;
+            to_json(response.result?)
+        }
+        Command::Devices => {
+            let response: ResponseDevices =
+                call(rpc, METHOD_GET_DEVICES, &RequestDevices {})
+                    .await?;
+            to_json(response.result?)
+        }
+        Command::Link {
+            target_device,
+            source,
+        } => {
+            let request = RequestDeviceLinkage {
+                source: source.into(),
+                target_device,
+                port_mapping: None,
+            };
+            let response: ResponseDeviceLinkage =
+                call(rpc, METHOD_LINK_DEVICES, &request).await?;
+            to_json(response.result?)
+        }
+        Command::Unlink {
+            target_device,
+            source,
+        } => {
+            let request = RequestDeviceUnLinkage {
+                source: source.into(),
+                target_device,
+            };
+            let response: ResponseDeviceUnLinkage =
+                call(rpc, METHOD_UNLINK_DEVICES, &request).await?;
+            to_json(response.result?)
+        }
+        Command::Voices => {
+            let response: ResponseVoiceStatus = call(
+                rpc,
+                METHOD_VOICE_STATUS,
+                &RequestVoiceStatus {},
+            )
+            .await?;
+            to_json(response.result?)
+        }
+        Command::Capabilities => {
+            let response: ResponseCapabilities = call(
+                rpc,
+                METHOD_CAPABILITIES,
+                &RequestCapabilities {},
+            )
+            .await?;
+            to_json(response.result?)
+        }
+        Command::Status => {
+            let request = RequestSpeechQueue {
+                action: SpeechQueueAction::List,
+            };
+            let queue: ResponseSpeechQueue =
+                call(rpc, METHOD_SPEECH_QUEUE, &request).await?;
+            let links: ResponseGetLinks =
+                call(rpc, METHOD_GET_LINKS, &RequestGetLinks {})
+                    .await?;
+            to_json(Status {
+                queue: to_json(queue.result?)?,
+                links: to_json(links.result?)?,
+            })
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    let linux = linux_config();
+    let socket = cli.socket.unwrap_or_else(|| {
+        vl_config::socket_path(linux.as_ref().ok())
+            .to_string_lossy()
+            .into_owned()
+    });
+    let session = SessionHandlers::default();
+    let result = match connect(&socket, session.clone()).await {
+        Ok(rpc) => {
+            run(&rpc, &session, cli.token, linux, cli.command).await
+        }
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(output) => {
+            println!("{output}");
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            println!("{}", serde_json::json!({ "error": e }));
+            ExitCode::FAILURE
+        }
+    }
+}
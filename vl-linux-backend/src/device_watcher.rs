@@ -0,0 +1,103 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use busrt::rpc::{Rpc, RpcClient};
+use busrt::QoS;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::time::sleep;
+use vl_global::audio_devices::AvailableDevices;
+use vl_linux_backend::events::client::Scope;
+use vl_linux_backend::events::server::{
+    RequestDeviceListUpdated, METHOD_DEVICE_LIST_UPDATED,
+};
+
+use crate::event_handlers::client::get_devices::get_audio_devices;
+use crate::event_handlers::handler::ConnectedClients;
+use crate::sessions;
+
+/// Registry events usually come in bursts (a node and all of its
+/// ports), wait for them to settle before sending the new list.
+const DEBOUNCE: Duration = Duration::from_millis(250);
+
+async fn notify_clients(
+    rpc: &RpcClient,
+    sessions: &RpcClient,
+    clients: &ConnectedClients,
+    devices: &AvailableDevices,
+) {
+    let payload =
+        match rmp_serde::to_vec_named(&RequestDeviceListUpdated {
+            devices: devices.clone(),
+        }) {
+            Ok(payload) => payload,
+            Err(e) => {
+                log::error!("Failed to serialize device list: {e}");
+                return;
+            }
+        };
+
+    let targets: Vec<(String, String)> = match clients.read() {
+        // Only clients allowed to list the devices
+        Ok(clients) => clients
+            .iter()
+            .filter(|(_, session)| session.scope >= Scope::Devices)
+            .map(|(name, session)| {
+                (name.clone(), session.secret.clone())
+            })
+            .collect(),
+        Err(e) => {
+            log::error!("Failed to lock clients: {e}");
+            return;
+        }
+    };
+
+    for (target, secret) in targets {
+        if !sessions::verify(sessions, &target, &secret).await {
+            log::info!("{target} lost its session, not notifying it");
+            sessions::forget(clients, &target, &secret);
+            continue;
+        }
+        let result = rpc
+            .call(
+                &target,
+                METHOD_DEVICE_LIST_UPDATED,
+                payload.clone().into(),
+                QoS::Processed,
+            )
+            .await;
+        if let Err(e) = result {
+            // The client most likely disconnected
+            log::warn!("Failed to notify {target}: {e}");
+            sessions::forget(clients, &target, &secret);
+        }
+    }
+}
+
+/// Push the new device list to the connected clients whenever the
+/// PipeWire graph reports a node or a port `changes`
+pub async fn watch_devices(
+    mut changes: UnboundedReceiver<()>,
+    rpc: RpcClient,
+    sessions: Arc<RpcClient>,
+    clients: ConnectedClients,
+) {
+    let mut last_devices: Option<AvailableDevices> = None;
+    while changes.recv().await.is_some() {
+        sleep(DEBOUNCE).await;
+        while changes.try_recv().is_ok() {}
+
+        let devices = match get_audio_devices() {
+            Ok(devices) => devices,
+            Err(e) => {
+                log::error!("Failed to get devices: {e}");
+                continue;
+            }
+        };
+        if last_devices.as_ref() == Some(&devices) {
+            continue;
+        }
+        log::debug!("Device list changed, notifying clients");
+        notify_clients(&rpc, &sessions, &clients, &devices).await;
+        last_devices = Some(devices);
+    }
+}
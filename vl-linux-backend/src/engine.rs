@@ -0,0 +1,69 @@
+use serde::{Deserialize, Serialize};
+
+use crate::error::EngineError;
+use crate::events::client::SpeechParams;
+
+/// Mono audio of a sentence: the sample rate and the samples
+pub type SentenceAudio = (u32, Vec<f32>);
+
+/// Sentences of a text, synthesized as the iterator advances.
+/// Dropping it cancels the rest.
+pub type Sentences =
+    Box<dyn Iterator<Item = Result<SentenceAudio, EngineError>>>;
+
+/// What an engine supports, for clients to adapt their options
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct EngineCapabilities {
+    pub name: String,
+    /// Sentences come out one at a time, so playback starts before
+    /// the whole text is synthesized
+    pub streaming: bool,
+    pub multi_speaker: bool,
+    pub pitch: bool,
+    pub rate: bool,
+}
+
+/// A configured voice an engine can speak with
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct EngineVoice {
+    /// What the voice is selected by in the config
+    pub id: String,
+    /// Model config path of Piper voices, voice name of espeak-ng
+    /// voices
+    pub source: String,
+    pub language: Option<String>,
+    /// Voices are loaded the first time they are used
+    pub loaded: bool,
+}
+
+/// Turns text into audio. Voices are selected by the ID the engine
+/// gives them in [`SynthesisEngine::list_voices`].
+pub trait SynthesisEngine: Send + Sync {
+    fn synthesize(
+        &self,
+        voice_id: &str,
+        text: &str,
+        params: &SpeechParams,
+    ) -> Result<Sentences, EngineError>;
+
+    fn list_voices(&self) -> Result<Vec<EngineVoice>, EngineError>;
+
+    fn capabilities(&self) -> EngineCapabilities;
+
+    /// Synthesize every sentence and join them
+    fn synthesize_all(
+        &self,
+        voice_id: &str,
+        text: &str,
+        params: &SpeechParams,
+    ) -> Result<SentenceAudio, EngineError> {
+        let mut sample_rate = 22050;
+        let mut samples: Vec<f32> = Vec::new();
+        for sentence in self.synthesize(voice_id, text, params)? {
+            let (rate, sentence) = sentence?;
+            sample_rate = rate;
+            samples.extend(sentence);
+        }
+        Ok((sample_rate, samples))
+    }
+}
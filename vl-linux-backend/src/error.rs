@@ -1,4 +1,5 @@
 use thiserror::Error;
+use vl_global::auth::AuthError;
 use vl_global::vl_config::ConfigError;
 
 #[derive(Error, Debug)]
@@ -9,6 +10,33 @@ pub enum LinuxBackendError {
     ConfigSectionNotFound,
     #[error("Config file error.")]
     ConfigError(#[from] ConfigError),
+    #[error("Auth file error: {0}")]
+    AuthError(#[from] AuthError),
+    #[error(
+        "Only loopback and LAN addresses can be listened on: {0}"
+    )]
+    InvalidTcpAddress(String),
+    #[error(
+        "{0} must be a directory owned by the user with mode 0700"
+    )]
+    InsecureRuntimeDir(String),
+    #[cfg(feature = "busrt")]
+    #[error("Failed to start the broker: {0}")]
+    BrokerError(#[from] busrt::Error),
+    #[error("Failed to open the audio output: {0}")]
+    AudioOutput(String),
+    #[error("Error while trying to write or read to system")]
+    IoError(#[from] std::io::Error),
     #[error("Unknown Error")]
     UnknownError(#[from] anyhow::Error),
 }
+
+#[derive(Error, Debug, Clone)]
+pub enum EngineError {
+    #[error("Unknown voice: {0}")]
+    UnknownVoice(String),
+    #[error("Failed to load voice: {0}")]
+    LoadVoice(String),
+    #[error("Synthesis failed: {0}")]
+    Synthesis(String),
+}
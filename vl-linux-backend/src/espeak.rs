@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::process::{Command, Stdio};
+use std::sync::RwLock;
+
+use vl_linux_backend::engine::{
+    EngineCapabilities, EngineVoice, Sentences, SynthesisEngine,
+};
+use vl_linux_backend::error::EngineError;
+use vl_linux_backend::events::client::{
+    SpeechParams, DEFAULT_VOLUME,
+};
+
+const ESPEAK_NG: &str = "espeak-ng";
+
+/// Voices spoken by the `espeak-ng` program. It has a voice for far
+/// more languages than Piper and needs no model, at the cost of a
+/// robotic sound.
+#[derive(Default)]
+pub struct EspeakEngine {
+    /// espeak-ng voice name of every configured voice, keyed by voice
+    /// ID
+    voices: RwLock<HashMap<String, String>>,
+}
+
+impl EspeakEngine {
+    pub fn set_voices(&self, voices: HashMap<String, String>) {
+        match self.voices.write() {
+            Ok(mut current) => *current = voices,
+            Err(e) => {
+                log::error!("Failed to lock espeak voices: {e}")
+            }
+        }
+    }
+
+    /// espeak-ng voice name of a configured voice
+    pub fn voice_name(&self, voice_id: &str) -> Option<String> {
+        self.voices.read().ok()?.get(voice_id).cloned()
+    }
+
+    pub fn voices(&self) -> HashMap<String, String> {
+        self.voices
+            .read()
+            .map(|voices| voices.clone())
+            .unwrap_or_default()
+    }
+}
+
+/// Map the 0-100 rate of [`SpeechParams`] to 80-450 words per minute,
+/// 50 being the espeak-ng default of 175
+fn words_per_minute(rate: u8) -> u32 {
+    let rate = u32::from(rate.min(100));
+    if rate <= 50 {
+        80 + rate * (175 - 80) / 50
+    } else {
+        175 + (rate - 50) * (450 - 175) / 50
+    }
+}
+
+impl SynthesisEngine for EspeakEngine {
+    fn synthesize(
+        &self,
+        voice_id: &str,
+        text: &str,
+        params: &SpeechParams,
+    ) -> Result<Sentences, EngineError> {
+        let voice_name = self
+            .voice_name(voice_id)
+            .ok_or(EngineError::UnknownVoice(voice_id.to_owned()))?;
+        // espeak-ng takes an amplitude of 0-200, 100 being the
+        // default, like the default volume
+        let amplitude = u32::from(params.volume) * 100
+            / u32::from(DEFAULT_VOLUME);
+        let output = Command::new(ESPEAK_NG)
+            .arg("-v")
+            .arg(&voice_name)
+            .arg("-s")
+            .arg(words_per_minute(params.rate).to_string())
+            .arg("-p")
+            .arg(params.pitch.min(99).to_string())
+            .arg("-a")
+            .arg(amplitude.min(200).to_string())
+            .arg("-g")
+            .arg((params.appended_silence_ms / 10).to_string())
+            .arg("--stdout")
+            .arg("--")
+            .arg(text)
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| {
+                EngineError::Synthesis(format!(
+                    "Failed to run {ESPEAK_NG}: {e}"
+                ))
+            })?;
+        if !output.status.success() {
+            return Err(EngineError::Synthesis(
+                String::from_utf8_lossy(&output.stderr).into_owned(),
+            ));
+        }
+
+        let reader =
+            hound::WavReader::new(Cursor::new(output.stdout))
+                .map_err(|e| {
+                    EngineError::Synthesis(format!("{e}"))
+                })?;
+        let sample_rate = reader.spec().sample_rate;
+        let samples = reader
+            .into_samples::<i16>()
+            .map(|sample| {
+                sample.map(|sample| {
+                    f32::from(sample) / f32::from(i16::MAX)
+                })
+            })
+            .collect::<Result<Vec<f32>, _>>()
+            .map_err(|e| EngineError::Synthesis(format!("{e}")))?;
+
+        // The whole text is rendered at once
+        Ok(Box::new(std::iter::once(Ok((sample_rate, samples)))))
+    }
+
+    /// The configured voices, espeak-ng voice names are language
+    /// codes like `pt-br`
+    fn list_voices(&self) -> Result<Vec<EngineVoice>, EngineError> {
+        let mut voices: Vec<EngineVoice> = self
+            .voices()
+            .into_iter()
+            .map(|(voice_id, voice_name)| EngineVoice {
+                id: voice_id,
+                language: Some(voice_name.clone()),
+                source: voice_name,
+                // Nothing to load
+                loaded: true,
+            })
+            .collect();
+        voices.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(voices)
+    }
+
+    fn capabilities(&self) -> EngineCapabilities {
+        EngineCapabilities {
+            name: ESPEAK_NG.to_owned(),
+            streaming: false,
+            multi_speaker: false,
+            pitch: true,
+            rate: true,
+        }
+    }
+}
@@ -0,0 +1,67 @@
+// Client Sent Events
+use busrt::rpc::{RpcEvent, RpcResult};
+use events::client::{
+    GrantedSession, RequestAuthenticate, ResponseAuthenticate,
+};
+use vl_global::auth::{generate_token, AuthTokens};
+use vl_linux_backend::events;
+
+use crate::event_handlers::handler::{
+    ClientSession, ConnectedClients,
+};
+
+fn _evt_authenticate(
+    event: &RpcEvent,
+    clients: &ConnectedClients,
+) -> Result<GrantedSession, String> {
+    let request: RequestAuthenticate =
+        rmp_serde::from_slice(event.payload()).map_err(|err| {
+            format!("Failed to deserialize request: {err}")
+        })?;
+
+    // A failed handshake must not leave an earlier scope in place
+    clients
+        .write()
+        .map_err(|_| "Failed to lock clients")?
+        .remove(event.sender());
+
+    // Read on every handshake, so tokens added to the file work
+    // without a restart
+    let tokens =
+        AuthTokens::load_or_create().map_err(|e| format!("{e}"))?;
+    let client =
+        tokens.find(&request.token).ok_or("Invalid token")?;
+    log::info!(
+        "{} authenticated as {} with {:?}",
+        event.sender(),
+        client.name,
+        client.scope
+    );
+
+    let session = ClientSession {
+        scope: client.scope,
+        secret: generate_token().map_err(|e| format!("{e}"))?,
+    };
+    clients
+        .write()
+        .map_err(|_| "Failed to lock clients")?
+        .insert(event.sender().to_owned(), session.clone());
+    Ok(GrantedSession {
+        scope: session.scope,
+        secret: session.secret,
+    })
+}
+
+pub fn evt_authenticate(
+    event: RpcEvent,
+    clients: &ConnectedClients,
+) -> RpcResult {
+    let result = _evt_authenticate(&event, clients);
+    if let Err(e) = result.clone() {
+        log::warn!("Failed to authenticate {}: {e}", event.sender());
+    }
+    let response =
+        rmp_serde::to_vec(&ResponseAuthenticate { result })?;
+
+    Ok(Some(response))
+}
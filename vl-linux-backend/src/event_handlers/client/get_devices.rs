@@ -1,58 +1,28 @@
-use crate::PIPEWIRE_MANAGER;
+use crate::PW_GRAPH;
 use busrt::rpc::{RpcEvent, RpcResult};
-use easy_pw::port::PortDirection;
-use vl_global::audio_devices::AudioDevices;
+use vl_global::audio_devices::AvailableDevices;
 
 use events::client::{RequestDevices, ResponseDevices};
 use vl_linux_backend::events;
 
-fn _evt_get_devices(event: RpcEvent) -> Result<AudioDevices, String> {
+/// Build the device lists from the nodes in the PipeWire graph
+pub fn get_audio_devices() -> Result<AvailableDevices, String> {
+    PW_GRAPH
+        .get()
+        .ok_or("PipeWire graph not initialized")?
+        .devices()
+}
+
+fn _evt_get_devices(
+    event: RpcEvent,
+) -> Result<AvailableDevices, String> {
     // Verify if the event payload is of type RequestDevices
     let _: RequestDevices = rmp_serde::from_slice(event.payload())
         .map_err(|err| {
             format!("Failed to deserialize request: {err}")
         })?;
 
-    // Get PipeWire Manager Instance
-    let manager = PIPEWIRE_MANAGER
-        .get()
-        .ok_or("PipeWireManager not initialized")?
-        .read()
-        .map_err(|e| {
-            format!("Failed to lock PipeWireManager: {e}")
-        })?;
-
-    let objects = manager.get_objects();
-
-    // Create Response Struct
-    let mut audio_device = AudioDevices {
-        input_devices: vec![],
-        output_devices: vec![],
-    };
-
-    let objects = objects.read().map_err(|e| {
-        format!("Failed to lock PipeWireObjects: {e}")
-    })?;
-
-    // Fill Response Struct
-    for node in objects.nodes.iter() {
-        for port in node.ports.iter() {
-            if port.direction == PortDirection::In {
-                if audio_device.input_devices.contains(&node.name) {
-                    continue;
-                }
-                audio_device.input_devices.push(node.name.clone());
-            } else if port.direction == PortDirection::Out {
-                if audio_device.output_devices.contains(&node.name) {
-                    continue;
-                }
-                audio_device.output_devices.push(node.name.clone());
-            }
-        }
-    }
-    drop(objects);
-    drop(manager);
-    Ok(audio_device)
+    get_audio_devices()
 }
 
 pub fn evt_get_devices(event: RpcEvent) -> RpcResult {
@@ -0,0 +1,76 @@
+// Client Sent Events
+use busrt::rpc::{RpcEvent, RpcResult};
+use events::client::{
+    BackendCapabilities, BackendInfo, RequestCapabilities,
+    RequestHello, ResponseCapabilities, ResponseHello, METHODS,
+    PROTOCOL_VERSION,
+};
+use vl_linux_backend::events;
+
+use crate::voice_loader;
+
+const BACKEND_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+fn _evt_hello(event: &RpcEvent) -> Result<BackendInfo, String> {
+    let request: RequestHello =
+        rmp_serde::from_slice(event.payload()).map_err(|err| {
+            format!("Failed to deserialize request: {err}")
+        })?;
+
+    // The client decides whether it can talk to us, it may know
+    // older versions
+    if request.protocol_version != PROTOCOL_VERSION {
+        log::warn!(
+            "{} speaks protocol {}, the backend speaks {PROTOCOL_VERSION}",
+            event.sender(),
+            request.protocol_version
+        );
+    }
+
+    Ok(BackendInfo {
+        protocol_version: PROTOCOL_VERSION,
+        backend_version: BACKEND_VERSION.to_owned(),
+    })
+}
+
+pub fn evt_hello(event: RpcEvent) -> RpcResult {
+    let result = _evt_hello(&event);
+    if let Err(e) = result.clone() {
+        log::error!("Failed to answer hello: {e}");
+    }
+    // Named fields, so the layout doesn't depend on the version
+    let response =
+        rmp_serde::to_vec_named(&ResponseHello { result })?;
+
+    Ok(Some(response))
+}
+
+fn _evt_capabilities(
+    event: RpcEvent,
+) -> Result<BackendCapabilities, String> {
+    let _: RequestCapabilities =
+        rmp_serde::from_slice(event.payload()).map_err(|err| {
+            format!("Failed to deserialize request: {err}")
+        })?;
+
+    Ok(BackendCapabilities {
+        protocol_version: PROTOCOL_VERSION,
+        backend_version: BACKEND_VERSION.to_owned(),
+        engines: voice_loader::loaded_engines()?,
+        methods: METHODS
+            .iter()
+            .map(|method| method.to_string())
+            .collect(),
+    })
+}
+
+pub fn evt_capabilities(event: RpcEvent) -> RpcResult {
+    let result = _evt_capabilities(event);
+    if let Err(e) = result.clone() {
+        log::error!("Failed to list capabilities: {e}");
+    }
+    let response =
+        rmp_serde::to_vec(&ResponseCapabilities { result })?;
+
+    Ok(Some(response))
+}
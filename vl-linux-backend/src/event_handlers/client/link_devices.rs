@@ -1,49 +1,44 @@
-use crate::{piper::PiperTTSManager, PIPEWIRE_MANAGER};
+use crate::{piper::PiperTTSManager, PW_GRAPH};
 use busrt::rpc::{RpcEvent, RpcResult};
 
-use events::client::{RequestDeviceLinkage, ResponseDeviceLinkage};
+use events::client::{
+    DesiredLink, LinkedPorts, RequestDeviceLinkage,
+    ResponseDeviceLinkage,
+};
 use vl_linux_backend::events;
 
-fn _evt_link_devices(event: RpcEvent) -> Result<(), String> {
+fn _evt_link_devices(
+    event: RpcEvent,
+) -> Result<Vec<LinkedPorts>, String> {
     // Verify if the event payload is of type RequestDevices
     let event: RequestDeviceLinkage =
         rmp_serde::from_slice(event.payload()).map_err(|err| {
             format!("Failed to deserialize request: {err}")
         })?;
-    // Get PipeWire Manager Instance
-    let manager = PIPEWIRE_MANAGER
-        .get()
-        .ok_or("PipeWireManager not initialized")?
-        .read()
-        .map_err(|e| {
-            format!("Failed to lock PipeWireManager: {e}")
-        })?;
-
-    let objects = manager.get_objects();
-
-    let objects = objects.read().map_err(|e| {
-        format!("Failed to lock PipeWireObjects: {e}")
-    })?;
 
-    // Find Objects
+    let graph =
+        PW_GRAPH.get().ok_or("PipeWire graph not initialized")?;
     let first_name = PiperTTSManager::get_handle_name();
-    let second_name = event.target_device;
-    let err_description = format!(
-        "while trying to link {first_name} <==> {second_name}"
-    );
-    let first_device = objects
-        .find_node_id_by_name(&first_name)
-        .ok_or("First device not found")?;
-    let second_device =
-        objects.find_node_id_by_name(&second_name).ok_or(format!(
-            "Second device not found {err_description}"
-        ))?;
-    drop(objects);
-
-    manager.link_nodes(first_device, second_device);
-    drop(manager);
+    let second_name = event.target_device.clone();
+    // The backend keeps the link alive from now on
+    let linked = graph
+        .add_desired_link(DesiredLink {
+            source: event.source,
+            target_device: event.target_device,
+            port_mapping: event.port_mapping,
+        })
+        .map_err(|e| {
+            format!("{e} while trying to link {first_name} <==> {second_name}")
+        })?;
 
-    Ok(())
+    for ports in &linked {
+        log::debug!(
+            "Linked {} -> {}",
+            ports.output_port,
+            ports.input_port
+        );
+    }
+    Ok(linked)
 }
 
 pub fn evt_link_devices(event: RpcEvent) -> RpcResult {
@@ -0,0 +1,56 @@
+use crate::PW_GRAPH;
+use busrt::rpc::{RpcEvent, RpcResult};
+use events::client::{
+    LinkState, RequestGetLinks, RequestSetDesiredLinks,
+    ResponseGetLinks, ResponseSetDesiredLinks,
+};
+use vl_linux_backend::events;
+
+fn _evt_get_links(event: RpcEvent) -> Result<Vec<LinkState>, String> {
+    // Verify if the event payload is of type RequestGetLinks
+    let _: RequestGetLinks = rmp_serde::from_slice(event.payload())
+        .map_err(|err| {
+        format!("Failed to deserialize request: {err}")
+    })?;
+
+    PW_GRAPH
+        .get()
+        .ok_or("PipeWire graph not initialized")?
+        .links()
+}
+
+pub fn evt_get_links(event: RpcEvent) -> RpcResult {
+    let result = _evt_get_links(event);
+    if let Err(e) = result.clone() {
+        log::error!("Failed to get links: {e}");
+    }
+    let response = rmp_serde::to_vec(&ResponseGetLinks { result })?;
+
+    Ok(Some(response))
+}
+
+fn _evt_set_desired_links(
+    event: RpcEvent,
+) -> Result<Vec<LinkState>, String> {
+    // Verify if the event payload is of type RequestSetDesiredLinks
+    let event: RequestSetDesiredLinks =
+        rmp_serde::from_slice(event.payload()).map_err(|err| {
+            format!("Failed to deserialize request: {err}")
+        })?;
+
+    PW_GRAPH
+        .get()
+        .ok_or("PipeWire graph not initialized")?
+        .set_desired_links(event.links)
+}
+
+pub fn evt_set_desired_links(event: RpcEvent) -> RpcResult {
+    let result = _evt_set_desired_links(event);
+    if let Err(e) = result.clone() {
+        log::error!("Failed to set desired links: {e}");
+    }
+    let response =
+        rmp_serde::to_vec(&ResponseSetDesiredLinks { result })?;
+
+    Ok(Some(response))
+}
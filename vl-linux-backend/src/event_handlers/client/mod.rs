@@ -1,7 +1,15 @@
+pub mod auth;
 pub mod get_devices;
+pub mod hello;
 pub mod link_devices;
+pub mod links;
+pub mod pause_tts;
+pub mod phrase_cache;
+pub mod speech_queue;
 pub mod stop_tts;
+pub mod synthesize_to_file;
 pub mod tts;
 pub mod unlink_devices;
+pub mod voice;
 
 // Client Sent Events
@@ -0,0 +1,51 @@
+use crate::UTTERANCE_QUEUE;
+use busrt::rpc::{RpcEvent, RpcResult};
+use events::client::{
+    RequestPauseTTS, RequestResumeTTS, ResponsePauseTTS,
+    ResponseResumeTTS,
+};
+use vl_linux_backend::events;
+
+fn _evt_pause_tts(event: RpcEvent) -> Result<(), String> {
+    let queue =
+        UTTERANCE_QUEUE.get().ok_or("UTTERANCE_QUEUE not set")?;
+
+    let _: RequestPauseTTS = rmp_serde::from_slice(event.payload())
+        .map_err(|err| {
+        format!("Failed to deserialize request: {err}")
+    })?;
+
+    queue.pause()
+}
+
+pub fn evt_pause_tts(event: RpcEvent) -> RpcResult {
+    let result = _evt_pause_tts(event);
+    if let Err(e) = result.clone() {
+        log::error!("Failed to pause speaking: {e}");
+    }
+    let response = rmp_serde::to_vec(&ResponsePauseTTS { result })?;
+
+    Ok(Some(response))
+}
+
+fn _evt_resume_tts(event: RpcEvent) -> Result<(), String> {
+    let queue =
+        UTTERANCE_QUEUE.get().ok_or("UTTERANCE_QUEUE not set")?;
+
+    let _: RequestResumeTTS = rmp_serde::from_slice(event.payload())
+        .map_err(|err| {
+            format!("Failed to deserialize request: {err}")
+        })?;
+
+    queue.resume()
+}
+
+pub fn evt_resume_tts(event: RpcEvent) -> RpcResult {
+    let result = _evt_resume_tts(event);
+    if let Err(e) = result.clone() {
+        log::error!("Failed to resume speaking: {e}");
+    }
+    let response = rmp_serde::to_vec(&ResponseResumeTTS { result })?;
+
+    Ok(Some(response))
+}
@@ -0,0 +1,58 @@
+use crate::{phrase_cache, PHRASE_CACHE};
+use busrt::rpc::{RpcEvent, RpcResult};
+use events::client::{
+    CacheStats, RequestClearCache, RequestPrewarmCache,
+    ResponseClearCache, ResponsePrewarmCache,
+};
+use vl_linux_backend::events;
+
+async fn _evt_prewarm_cache(
+    event: RpcEvent,
+) -> Result<CacheStats, String> {
+    // Verify if the event payload is of type RequestPrewarmCache
+    let event: RequestPrewarmCache =
+        rmp_serde::from_slice(event.payload()).map_err(|err| {
+            format!("Failed to deserialize request: {err}")
+        })?;
+
+    // Synthesis takes a while, keep it off the RPC worker
+    tokio::task::spawn_blocking(move || {
+        phrase_cache::prewarm(&event.phrases, &event.params)
+    })
+    .await
+    .map_err(|e| format!("{e}"))?
+}
+
+pub async fn evt_prewarm_cache(event: RpcEvent) -> RpcResult {
+    let result = _evt_prewarm_cache(event).await;
+    if let Err(e) = result.clone() {
+        log::error!("Failed to prewarm the phrase cache: {e}");
+    }
+    let response =
+        rmp_serde::to_vec(&ResponsePrewarmCache { result })?;
+
+    Ok(Some(response))
+}
+
+fn _evt_clear_cache(event: RpcEvent) -> Result<CacheStats, String> {
+    // Verify if the event payload is of type RequestClearCache
+    let _: RequestClearCache = rmp_serde::from_slice(event.payload())
+        .map_err(|err| {
+            format!("Failed to deserialize request: {err}")
+        })?;
+
+    PHRASE_CACHE
+        .get()
+        .ok_or("The phrase cache is disabled")?
+        .clear()
+}
+
+pub fn evt_clear_cache(event: RpcEvent) -> RpcResult {
+    let result = _evt_clear_cache(event);
+    if let Err(e) = result.clone() {
+        log::error!("Failed to clear the phrase cache: {e}");
+    }
+    let response = rmp_serde::to_vec(&ResponseClearCache { result })?;
+
+    Ok(Some(response))
+}
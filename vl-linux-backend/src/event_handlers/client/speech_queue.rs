@@ -0,0 +1,44 @@
+use crate::UTTERANCE_QUEUE;
+use busrt::rpc::{RpcEvent, RpcResult};
+use events::client::{
+    RequestSpeechQueue, ResponseSpeechQueue, SpeechQueueAction,
+    SpeechQueueState,
+};
+use vl_linux_backend::events;
+
+fn _evt_speech_queue(
+    event: RpcEvent,
+) -> Result<SpeechQueueState, String> {
+    let queue =
+        UTTERANCE_QUEUE.get().ok_or("UTTERANCE_QUEUE not set")?;
+
+    // Verify if the event payload is of type RequestSpeechQueue
+    let event: RequestSpeechQueue =
+        rmp_serde::from_slice(event.payload()).map_err(|err| {
+            format!("Failed to deserialize request: {err}")
+        })?;
+
+    match event.action {
+        SpeechQueueAction::List => {}
+        SpeechQueueAction::Cancel { utterance_id } => {
+            if !queue.cancel(utterance_id) {
+                return Err(format!(
+                    "Utterance {utterance_id} is not in the queue"
+                ));
+            }
+        }
+    }
+
+    Ok(queue.snapshot())
+}
+
+pub fn evt_speech_queue(event: RpcEvent) -> RpcResult {
+    let result = _evt_speech_queue(event);
+    if let Err(e) = result.clone() {
+        log::error!("Failed to handle speech queue request: {e}");
+    }
+    let response =
+        rmp_serde::to_vec(&ResponseSpeechQueue { result })?;
+
+    Ok(Some(response))
+}
@@ -1,14 +1,11 @@
-use crate::PIPERTTS_MANAGER;
+use crate::UTTERANCE_QUEUE;
 use busrt::rpc::{RpcEvent, RpcResult};
 use events::client::{RequestStopTTS, ResponseStopTTS};
 use vl_linux_backend::events;
 
 fn _evt_stop_tts(event: RpcEvent) -> Result<(), String> {
-    let manager = PIPERTTS_MANAGER
-        .get()
-        .ok_or("PIPERTTS_MANAGER not set")?
-        .read()
-        .map_err(|_| "Failed to lock PIPERTTS_MANAGER")?;
+    let queue =
+        UTTERANCE_QUEUE.get().ok_or("UTTERANCE_QUEUE not set")?;
 
     // Verify if the event payload is of type RequestDevices
     let _: RequestStopTTS = rmp_serde::from_slice(event.payload())
@@ -16,8 +13,8 @@ fn _evt_stop_tts(event: RpcEvent) -> Result<(), String> {
             format!("Failed to deserialize request: {err}")
         })?;
 
-    manager.stop_speak().map_err(|e| format!("{e}"))?;
-    drop(manager);
+    // Stopping also throws away whatever was waiting to be spoken
+    queue.clear();
 
     Ok(())
 }
@@ -0,0 +1,39 @@
+use crate::audio_export;
+use busrt::rpc::{RpcEvent, RpcResult};
+use events::client::{
+    RequestSynthesizeToFile, ResponseSynthesizeToFile,
+    SynthesizedAudio,
+};
+use vl_linux_backend::events;
+
+async fn _evt_synthesize_to_file(
+    event: RpcEvent,
+) -> Result<SynthesizedAudio, String> {
+    // Verify if the event payload is of type RequestSynthesizeToFile
+    let event: RequestSynthesizeToFile =
+        rmp_serde::from_slice(event.payload()).map_err(|err| {
+            format!("Failed to deserialize request: {err}")
+        })?;
+
+    if let Some(voice_id) = &event.params.voice_id {
+        crate::voice_loader::check_voice(voice_id)?;
+    }
+
+    // Synthesis takes a while, keep it off the RPC worker
+    tokio::task::spawn_blocking(move || {
+        audio_export::synthesize_to_file(event)
+    })
+    .await
+    .map_err(|e| format!("{e}"))?
+}
+
+pub async fn evt_synthesize_to_file(event: RpcEvent) -> RpcResult {
+    let result = _evt_synthesize_to_file(event).await;
+    if let Err(e) = result.clone() {
+        log::error!("Failed to synthesize to file: {e}");
+    }
+    let response =
+        rmp_serde::to_vec(&ResponseSynthesizeToFile { result })?;
+
+    Ok(Some(response))
+}
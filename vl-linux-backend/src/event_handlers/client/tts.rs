@@ -1,14 +1,12 @@
-use crate::PIPERTTS_MANAGER;
+use crate::voice_loader;
+use crate::UTTERANCE_QUEUE;
 use busrt::rpc::{RpcEvent, RpcResult};
-use events::client::{RequestTTS, ResponseTTS};
+use events::client::{RequestTTS, ResponseTTS, UtteranceInfo};
 use vl_linux_backend::events;
 
-fn _evt_tts(event: RpcEvent) -> Result<(), String> {
-    let manager = PIPERTTS_MANAGER
-        .get()
-        .ok_or("PIPERTTS_MANAGER not set")?
-        .read()
-        .map_err(|_| "Failed to lock PIPERTTS_MANAGER")?;
+fn _evt_tts(event: RpcEvent) -> Result<Option<u64>, String> {
+    let queue =
+        UTTERANCE_QUEUE.get().ok_or("UTTERANCE_QUEUE not set")?;
 
     // Verify if the event payload is of type RequestDevices
     let event: RequestTTS = rmp_serde::from_slice(event.payload())
@@ -16,12 +14,27 @@ fn _evt_tts(event: RpcEvent) -> Result<(), String> {
             format!("Failed to deserialize request: {err}")
         })?;
 
-    manager
-        .speak(event.phrase, event.pitch, event.volume)
-        .map_err(|e| format!("{e}"))?;
-    drop(manager);
+    if let Some(voice_id) = &event.params.voice_id {
+        voice_loader::check_voice(voice_id)?;
+    }
+
+    let utterance_id = queue.push(
+        UtteranceInfo {
+            id: 0,
+            phrase: event.phrase,
+            params: event.params,
+            priority: event.priority,
+        },
+        event.mode,
+    );
+    match utterance_id {
+        Some(id) => log::debug!("Queued utterance {id}"),
+        None => {
+            log::debug!("Speech queue is busy, utterance dropped")
+        }
+    }
 
-    Ok(())
+    Ok(utterance_id)
 }
 
 pub fn evt_tts(event: RpcEvent) -> RpcResult {
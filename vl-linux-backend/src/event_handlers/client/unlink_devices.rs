@@ -1,4 +1,4 @@
-use crate::{piper::PiperTTSManager, PIPEWIRE_MANAGER};
+use crate::PW_GRAPH;
 use busrt::rpc::{RpcEvent, RpcResult};
 use events::client::{
     RequestDeviceUnLinkage, ResponseDeviceUnLinkage,
@@ -12,34 +12,10 @@ fn _evt_unlink_devices(event: RpcEvent) -> Result<(), String> {
             format!("Failed to deserialize request: {err}")
         })?;
 
-    // Get PipeWire Manager Instance
-    let manager = PIPEWIRE_MANAGER
+    PW_GRAPH
         .get()
-        .ok_or("PipeWireManager not initialized")?
-        .read()
-        .map_err(|e| {
-            format!("Failed to lock PipeWireManager: {e}")
-        })?;
-
-    let objects = manager.get_objects();
-
-    let objects = objects.read().map_err(|e| {
-        format!("Failed to lock PipeWireObjects: {e}")
-    })?;
-
-    // Find Objects
-    let first_device = objects
-        .find_node_id_by_name(&PiperTTSManager::get_handle_name())
-        .ok_or("First device not found")?;
-    let second_device = objects
-        .find_node_id_by_name(&event.target_device)
-        .ok_or("Second device not found")?;
-    drop(objects);
-
-    manager.unlink_nodes(first_device, second_device);
-    drop(manager);
-
-    Ok(())
+        .ok_or("PipeWire graph not initialized")?
+        .remove_desired_link(event.source, event.target_device)
 }
 
 pub fn evt_unlink_devices(event: RpcEvent) -> RpcResult {
@@ -0,0 +1,98 @@
+use crate::voice_loader;
+use busrt::rpc::{RpcEvent, RpcResult};
+use events::client::{
+    RequestListSpeakers, RequestReloadVoice, RequestVoiceStatus,
+    ResponseListSpeakers, ResponseReloadVoice, ResponseVoiceStatus,
+    SpeakerInfo, VoiceStatus,
+};
+use vl_global::vl_config::{
+    ConfigManager, VoiceConfig, VoiceEngine, DEFAULT_VOICE_ID,
+};
+use vl_linux_backend::events;
+
+async fn _evt_reload_voice(
+    event: RpcEvent,
+) -> Result<VoiceStatus, String> {
+    // Verify if the event payload is of type RequestReloadVoice
+    let event: RequestReloadVoice =
+        rmp_serde::from_slice(event.payload()).map_err(|err| {
+            format!("Failed to deserialize request: {err}")
+        })?;
+
+    let config_manager =
+        ConfigManager::new().map_err(|e| format!("{e}"))?;
+    let linux = voice_loader::linux_config(&config_manager)?;
+    let mut voices = linux.voices()?;
+    if let Some(model_path) = event.model_path {
+        voices.retain(|voice| voice.id != DEFAULT_VOICE_ID);
+        voices.push(VoiceConfig {
+            id: DEFAULT_VOICE_ID.to_owned(),
+            engine: VoiceEngine::Piper,
+            voice: model_path,
+        });
+    }
+
+    // Loading a model takes a while, keep it off the RPC worker
+    tokio::task::spawn_blocking(move || {
+        voice_loader::apply_voices(voices)
+    })
+    .await
+    .map_err(|e| format!("{e}"))?
+}
+
+pub async fn evt_reload_voice(event: RpcEvent) -> RpcResult {
+    let result = _evt_reload_voice(event).await;
+    if let Err(e) = result.clone() {
+        log::error!("Failed to reload voice: {e}");
+    }
+    let response =
+        rmp_serde::to_vec(&ResponseReloadVoice { result })?;
+
+    Ok(Some(response))
+}
+
+fn _evt_voice_status(event: RpcEvent) -> Result<VoiceStatus, String> {
+    // Verify if the event payload is of type RequestVoiceStatus
+    let _: RequestVoiceStatus =
+        rmp_serde::from_slice(event.payload()).map_err(|err| {
+            format!("Failed to deserialize request: {err}")
+        })?;
+
+    voice_loader::voice_status()
+}
+
+pub fn evt_voice_status(event: RpcEvent) -> RpcResult {
+    let result = _evt_voice_status(event);
+    if let Err(e) = result.clone() {
+        log::error!("Failed to get voice status: {e}");
+    }
+    let response =
+        rmp_serde::to_vec(&ResponseVoiceStatus { result })?;
+
+    Ok(Some(response))
+}
+
+fn _evt_list_speakers(
+    event: RpcEvent,
+) -> Result<Vec<SpeakerInfo>, String> {
+    // Verify if the event payload is of type RequestListSpeakers
+    let event: RequestListSpeakers =
+        rmp_serde::from_slice(event.payload()).map_err(|err| {
+            format!("Failed to deserialize request: {err}")
+        })?;
+
+    let voice_id =
+        event.voice_id.as_deref().unwrap_or(DEFAULT_VOICE_ID);
+    voice_loader::list_speakers(voice_id)
+}
+
+pub fn evt_list_speakers(event: RpcEvent) -> RpcResult {
+    let result = _evt_list_speakers(event);
+    if let Err(e) = result.clone() {
+        log::error!("Failed to list speakers: {e}");
+    }
+    let response =
+        rmp_serde::to_vec(&ResponseListSpeakers { result })?;
+
+    Ok(Some(response))
+}
@@ -1,17 +1,104 @@
 use crate::event_handlers;
-use busrt::rpc::RpcError;
+use busrt::rpc::{RpcClient, RpcError};
 use busrt::Frame;
 use busrt::{
     async_trait,
     rpc::{RpcEvent, RpcHandlers, RpcResult},
 };
 use events::client::{
-    METHOD_GET_DEVICES, METHOD_LINK_DEVICES, METHOD_SPEAK,
-    METHOD_STOP_SPEAK, METHOD_UNLINK_DEVICES,
+    required_scope, Scope, Unauthorized, METHOD_AUTHENTICATE,
+    METHOD_CAPABILITIES, METHOD_CLEAR_CACHE, METHOD_GET_DEVICES,
+    METHOD_GET_LINKS, METHOD_HELLO, METHOD_LINK_DEVICES,
+    METHOD_LIST_SPEAKERS, METHOD_PAUSE_SPEAK, METHOD_PREWARM_CACHE,
+    METHOD_RELOAD_VOICE, METHOD_RESUME_SPEAK,
+    METHOD_SET_DESIRED_LINKS, METHOD_SPEAK, METHOD_SPEECH_QUEUE,
+    METHOD_STOP_SPEAK, METHOD_SYNTHESIZE_TO_FILE,
+    METHOD_UNLINK_DEVICES, METHOD_VOICE_STATUS,
+    RPC_ERROR_CODE_UNAUTHORIZED,
 };
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
 use vl_linux_backend::events;
 
-pub(crate) struct EventHandler {}
+use crate::sessions;
+
+/// What a client got when it authenticated
+#[derive(Clone)]
+pub(crate) struct ClientSession {
+    pub(crate) scope: Scope,
+    /// The client answers with it when asked, see
+    /// [`sessions::verify`]
+    pub(crate) secret: String,
+}
+
+/// Sessions of the clients that authenticated, by client name. Used
+/// to authorize their calls and to push server events back to them.
+pub(crate) type ConnectedClients =
+    Arc<RwLock<HashMap<String, ClientSession>>>;
+
+pub(crate) struct EventHandler {
+    pub(crate) clients: ConnectedClients,
+    /// Asks the clients for their session. Calls answered on the
+    /// client of the broker can't wait on it.
+    pub(crate) sessions: Arc<RpcClient>,
+}
+
+impl EventHandler {
+    /// Fail with [`RPC_ERROR_CODE_UNAUTHORIZED`] unless the sender
+    /// authenticated with at least the `required` scope and still
+    /// holds the session
+    async fn authorize(
+        &self,
+        event: &RpcEvent,
+        method: &str,
+        required: Scope,
+    ) -> Result<(), RpcError> {
+        let sender = event.sender();
+        let session = match self.clients.read() {
+            Ok(clients) => clients.get(sender).cloned(),
+            Err(e) => {
+                log::error!("Failed to lock clients: {e}");
+                None
+            }
+        };
+        let mut granted =
+            session.as_ref().map(|session| session.scope);
+        if let Some(session) = &session {
+            if !sessions::verify(
+                &self.sessions,
+                sender,
+                &session.secret,
+            )
+            .await
+            {
+                log::warn!("{sender} lost its session");
+                sessions::forget(
+                    &self.clients,
+                    sender,
+                    &session.secret,
+                );
+                granted = None;
+            }
+        }
+        if granted.is_some_and(|granted| granted >= required) {
+            return Ok(());
+        }
+
+        log::warn!(
+            "Refused {method} from {}, it needs {required:?}",
+            event.sender()
+        );
+        let unauthorized = Unauthorized {
+            method: method.to_owned(),
+            required,
+            granted,
+        };
+        Err(RpcError::new(
+            RPC_ERROR_CODE_UNAUTHORIZED,
+            rmp_serde::to_vec_named(&unauthorized).ok(),
+        ))
+    }
+}
 
 #[async_trait]
 impl RpcHandlers for EventHandler {
@@ -21,7 +108,22 @@ impl RpcHandlers for EventHandler {
         let parse_method = event.parse_method()?;
         let event_name = parse_method.to_owned();
         log::debug!("Handling Event: {}", event_name);
+        if parse_method == METHOD_HELLO {
+            return event_handlers::client::hello::evt_hello(event);
+        }
+        if parse_method == METHOD_AUTHENTICATE {
+            return event_handlers::client::auth::evt_authenticate(
+                event,
+                &self.clients,
+            );
+        }
+        if let Some(required) = required_scope(parse_method) {
+            self.authorize(&event, parse_method, required).await?;
+        }
         let result = match parse_method {
+            METHOD_CAPABILITIES => {
+                event_handlers::client::hello::evt_capabilities(event)
+            }
             METHOD_GET_DEVICES => {
                 event_handlers::client::get_devices::evt_get_devices(event)
             }
@@ -33,10 +135,52 @@ impl RpcHandlers for EventHandler {
                     event,
                 )
             }
+            METHOD_GET_LINKS => {
+                event_handlers::client::links::evt_get_links(event)
+            }
+            METHOD_SET_DESIRED_LINKS => {
+                event_handlers::client::links::evt_set_desired_links(event)
+            }
             METHOD_SPEAK => event_handlers::client::tts::evt_tts(event),
             METHOD_STOP_SPEAK => {
                 event_handlers::client::stop_tts::evt_stop_tts(event)
             }
+            METHOD_PAUSE_SPEAK => {
+                event_handlers::client::pause_tts::evt_pause_tts(event)
+            }
+            METHOD_RESUME_SPEAK => {
+                event_handlers::client::pause_tts::evt_resume_tts(event)
+            }
+            METHOD_SYNTHESIZE_TO_FILE => {
+                event_handlers::client::synthesize_to_file::evt_synthesize_to_file(
+                    event,
+                )
+                .await
+            }
+            METHOD_RELOAD_VOICE => {
+                event_handlers::client::voice::evt_reload_voice(event)
+                    .await
+            }
+            METHOD_VOICE_STATUS => {
+                event_handlers::client::voice::evt_voice_status(event)
+            }
+            METHOD_LIST_SPEAKERS => {
+                event_handlers::client::voice::evt_list_speakers(event)
+            }
+            METHOD_PREWARM_CACHE => {
+                event_handlers::client::phrase_cache::evt_prewarm_cache(
+                    event,
+                )
+                .await
+            }
+            METHOD_CLEAR_CACHE => {
+                event_handlers::client::phrase_cache::evt_clear_cache(event)
+            }
+            METHOD_SPEECH_QUEUE => {
+                event_handlers::client::speech_queue::evt_speech_queue(
+                    event,
+                )
+            }
             _ => Err(RpcError::method(Some(
                 "Event not implemented".as_bytes().to_vec(),
             ))),
@@ -1,12 +1,157 @@
 use serde::Deserialize;
 use serde::Serialize;
-use vl_global::audio_devices::AudioDevices;
+use vl_global::audio_devices::AvailableDevices;
+pub use vl_global::auth::Scope;
+use vl_global::vl_config::DEFAULT_VOICE_ID;
+
+use crate::engine::EngineCapabilities;
+
+/// Version of the requests and responses of this module. Bump it
+/// whenever one of them changes in a way older peers can't read.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+pub const METHOD_HELLO: &str = "hello";
+pub const METHOD_CAPABILITIES: &str = "capabilities";
+pub const METHOD_AUTHENTICATE: &str = "authenticate";
 
 pub const METHOD_GET_DEVICES: &str = "get_devices";
 pub const METHOD_LINK_DEVICES: &str = "link_devices";
 pub const METHOD_UNLINK_DEVICES: &str = "unlink_devices";
 pub const METHOD_SPEAK: &str = "speak";
 pub const METHOD_STOP_SPEAK: &str = "stop_speak";
+pub const METHOD_PAUSE_SPEAK: &str = "pause_speak";
+pub const METHOD_RESUME_SPEAK: &str = "resume_speak";
+pub const METHOD_SYNTHESIZE_TO_FILE: &str = "synthesize_to_file";
+pub const METHOD_SPEECH_QUEUE: &str = "speech_queue";
+pub const METHOD_RELOAD_VOICE: &str = "reload_voice";
+pub const METHOD_VOICE_STATUS: &str = "voice_status";
+pub const METHOD_LIST_SPEAKERS: &str = "list_speakers";
+pub const METHOD_PREWARM_CACHE: &str = "prewarm_cache";
+pub const METHOD_CLEAR_CACHE: &str = "clear_cache";
+pub const METHOD_GET_LINKS: &str = "get_links";
+pub const METHOD_SET_DESIRED_LINKS: &str = "set_desired_links";
+
+/// Every method of the backend
+pub const METHODS: &[&str] = &[
+    METHOD_HELLO,
+    METHOD_CAPABILITIES,
+    METHOD_AUTHENTICATE,
+    METHOD_GET_DEVICES,
+    METHOD_LINK_DEVICES,
+    METHOD_UNLINK_DEVICES,
+    METHOD_SPEAK,
+    METHOD_STOP_SPEAK,
+    METHOD_PAUSE_SPEAK,
+    METHOD_RESUME_SPEAK,
+    METHOD_SYNTHESIZE_TO_FILE,
+    METHOD_SPEECH_QUEUE,
+    METHOD_RELOAD_VOICE,
+    METHOD_VOICE_STATUS,
+    METHOD_LIST_SPEAKERS,
+    METHOD_PREWARM_CACHE,
+    METHOD_CLEAR_CACHE,
+    METHOD_GET_LINKS,
+    METHOD_SET_DESIRED_LINKS,
+];
+
+/// Code of the RPC error calls get when the client did not
+/// authenticate or its scope is too narrow. The error data is an
+/// [`Unauthorized`].
+pub const RPC_ERROR_CODE_UNAUTHORIZED: i16 = 401;
+
+/// Scope a client needs to call a method, `None` for
+/// [`METHOD_HELLO`], [`METHOD_AUTHENTICATE`] and unknown methods
+pub fn required_scope(method: &str) -> Option<Scope> {
+    match method {
+        METHOD_CAPABILITIES | METHOD_SPEAK | METHOD_STOP_SPEAK
+        | METHOD_PAUSE_SPEAK | METHOD_RESUME_SPEAK
+        | METHOD_SPEECH_QUEUE | METHOD_VOICE_STATUS
+        | METHOD_LIST_SPEAKERS => Some(Scope::Speak),
+        METHOD_GET_DEVICES
+        | METHOD_LINK_DEVICES
+        | METHOD_UNLINK_DEVICES
+        | METHOD_GET_LINKS
+        | METHOD_SET_DESIRED_LINKS => Some(Scope::Devices),
+        METHOD_SYNTHESIZE_TO_FILE
+        | METHOD_RELOAD_VOICE
+        | METHOD_PREWARM_CACHE
+        | METHOD_CLEAR_CACHE => Some(Scope::Admin),
+        _ => None,
+    }
+}
+
+// Hello
+/// First call of every client, before authenticating. Its request
+/// and response never change, so peers of any version can tell
+/// whether they understand each other. Sent with named fields.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RequestHello {
+    pub protocol_version: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BackendInfo {
+    pub protocol_version: u32,
+    pub backend_version: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ResponseHello {
+    pub result: Result<BackendInfo, String>,
+}
+
+// Capabilities
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RequestCapabilities {}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BackendCapabilities {
+    pub protocol_version: u32,
+    pub backend_version: String,
+    /// Engines with at least one configured voice
+    pub engines: Vec<EngineCapabilities>,
+    /// See [`METHODS`]
+    pub methods: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ResponseCapabilities {
+    pub result: Result<BackendCapabilities, String>,
+}
+
+// Authenticate
+/// Sent after [`RequestHello`], with a token of the `auth.toml` file
+/// next to the config. The scope of the token applies to the client
+/// as long as it answers
+/// [`METHOD_SESSION`](crate::events::server::METHOD_SESSION) with
+/// the secret of the session.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RequestAuthenticate {
+    pub token: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GrantedSession {
+    /// Scope granted to the client
+    pub scope: Scope,
+    /// Proves the client is the one that authenticated, not another
+    /// process that took its name after it disconnected
+    pub secret: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ResponseAuthenticate {
+    pub result: Result<GrantedSession, String>,
+}
+
+/// Data of [`RPC_ERROR_CODE_UNAUTHORIZED`] errors
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Unauthorized {
+    pub method: String,
+    pub required: Scope,
+    /// `None` when the client did not authenticate
+    pub granted: Option<Scope>,
+}
 
 // Get Devices
 #[derive(Serialize, Deserialize, Debug)]
@@ -14,23 +159,56 @@ pub struct RequestDevices {}
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ResponseDevices {
-    pub result: Result<AudioDevices, String>,
+    pub result: Result<AvailableDevices, String>,
 }
 
 // Link Devices
+/// What is linked to a device
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default,
+)]
+pub enum LinkSource {
+    /// The synthesized speech alone
+    #[default]
+    Tts,
+    /// The virtual source, with the TTS mixed with the microphone
+    Mix,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct RequestDeviceLinkage {
+    #[serde(default)]
+    pub source: LinkSource,
     pub target_device: String,
+    /// Ports to link, by audio channel (`FL`, `FR`, `MONO`...) or port
+    /// name. When `None` the ports are paired automatically.
+    #[serde(default)]
+    pub port_mapping: Option<Vec<PortMapping>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PortMapping {
+    pub output_channel: String,
+    pub input_channel: String,
+}
+
+/// A pair of ports linked together, by port name
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LinkedPorts {
+    pub output_port: String,
+    pub input_port: String,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ResponseDeviceLinkage {
-    pub result: Result<(), String>,
+    pub result: Result<Vec<LinkedPorts>, String>,
 }
 
 // Unlink Devices
 #[derive(Serialize, Deserialize, Debug)]
 pub struct RequestDeviceUnLinkage {
+    #[serde(default)]
+    pub source: LinkSource,
     pub target_device: String,
 }
 
@@ -39,17 +217,159 @@ pub struct ResponseDeviceUnLinkage {
     pub result: Result<(), String>,
 }
 
+// Desired Links
+/// A device the TTS should stay linked to. The backend remembers
+/// these links and remakes them whenever the device comes back.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DesiredLink {
+    #[serde(default)]
+    pub source: LinkSource,
+    pub target_device: String,
+    #[serde(default)]
+    pub port_mapping: Option<Vec<PortMapping>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LinkState {
+    pub link: DesiredLink,
+    /// Whether the target device exists right now
+    pub available: bool,
+    pub linked: Vec<LinkedPorts>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RequestGetLinks {}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ResponseGetLinks {
+    pub result: Result<Vec<LinkState>, String>,
+}
+
+/// Replace every desired link, links to devices not in `links` are
+/// removed
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RequestSetDesiredLinks {
+    pub links: Vec<DesiredLink>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ResponseSetDesiredLinks {
+    pub result: Result<Vec<LinkState>, String>,
+}
+
 // Talk
+/// How a new utterance should be handled when the backend is
+/// already speaking.
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq,
+)]
+pub enum QueueMode {
+    /// Stop the current utterance, drop everything that is queued
+    /// and speak right away.
+    Interrupt,
+    /// Wait for everything already queued.
+    Enqueue,
+    /// Discard the utterance if something is playing or queued.
+    DropIfBusy,
+}
+
+/// Where a new utterance goes in the queue
+#[derive(
+    Serialize,
+    Deserialize,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Default,
+)]
+pub enum Priority {
+    /// Waits behind everything already queued
+    #[default]
+    Normal,
+    /// Waits behind the current utterance only
+    High,
+    /// Stops anything that is not an emergency and plays right away,
+    /// louder and repeated as set in the config
+    Emergency,
+}
+
+/// Volume the voices speak at by default. With loudness
+/// normalization it is the one that plays at the target.
+pub const DEFAULT_VOLUME: u8 = 128;
+
+/// How a phrase is spoken
+#[derive(
+    Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash,
+)]
+pub struct SpeechParams {
+    /// Voice to speak with, the default voice when `None`
+    pub voice_id: Option<String>,
+    /// Speaker of multi-speaker models, see [`METHOD_LIST_SPEAKERS`]
+    pub speaker_id: i64,
+    pub pitch: u8,
+    pub volume: u8,
+    pub rate: u8,
+    /// Silence added after each sentence
+    pub appended_silence_ms: u32,
+}
+
+impl SpeechParams {
+    pub fn voice_id(&self) -> &str {
+        self.voice_id.as_deref().unwrap_or(DEFAULT_VOICE_ID)
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct RequestTTS {
     pub phrase: String,
-    pub pitch: u8,
-    pub volume: u8,
+    pub params: SpeechParams,
+    pub mode: QueueMode,
+    #[serde(default)]
+    pub priority: Priority,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ResponseTTS {
-    pub result: Result<(), String>,
+    /// ID of the queued utterance, or `None` if it was dropped
+    /// because of [`QueueMode::DropIfBusy`].
+    pub result: Result<Option<u64>, String>,
+}
+
+// Synthesize To File
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq,
+)]
+pub enum AudioFileFormat {
+    /// 16 bit PCM
+    Wav,
+    Ogg,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RequestSynthesizeToFile {
+    pub phrase: String,
+    pub params: SpeechParams,
+    /// Absolute path of the file to write, it is overwritten if it
+    /// exists
+    pub path: String,
+    pub format: AudioFileFormat,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SynthesizedAudio {
+    pub path: String,
+    pub duration_ms: u64,
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ResponseSynthesizeToFile {
+    pub result: Result<SynthesizedAudio, String>,
 }
 
 // Talk
@@ -60,3 +380,147 @@ pub struct RequestStopTTS {}
 pub struct ResponseStopTTS {
     pub result: Result<(), String>,
 }
+
+// Pause and resume the current utterance, keeping the queue
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RequestPauseTTS {}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ResponsePauseTTS {
+    pub result: Result<(), String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RequestResumeTTS {}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ResponseResumeTTS {
+    pub result: Result<(), String>,
+}
+
+// Speech Queue
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UtteranceInfo {
+    pub id: u64,
+    pub phrase: String,
+    pub params: SpeechParams,
+    #[serde(default)]
+    pub priority: Priority,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SpeechQueueState {
+    /// Utterance being synthesized or played right now
+    pub current: Option<UtteranceInfo>,
+    pub pending: Vec<UtteranceInfo>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum SpeechQueueAction {
+    List,
+    /// Cancel an utterance, whether it is playing or still queued
+    Cancel {
+        utterance_id: u64,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RequestSpeechQueue {
+    pub action: SpeechQueueAction,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ResponseSpeechQueue {
+    /// State of the queue after the action was applied
+    pub result: Result<SpeechQueueState, String>,
+}
+
+// Voice
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VoiceInfo {
+    pub id: String,
+    /// Name of the engine that speaks with the voice
+    #[serde(default)]
+    pub engine: String,
+    /// Path of the Piper model config, or the espeak-ng voice name
+    pub model_path: String,
+    #[serde(default)]
+    pub language: Option<String>,
+    /// Voices are loaded the first time they are used
+    pub loaded: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VoiceStatus {
+    /// Every configured voice, empty when the backend runs without
+    /// a voice
+    pub voices: Vec<VoiceInfo>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RequestReloadVoice {
+    /// Model of the default voice. When `None` the model in the
+    /// config file is used.
+    pub model_path: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ResponseReloadVoice {
+    pub result: Result<VoiceStatus, String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RequestVoiceStatus {}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ResponseVoiceStatus {
+    pub result: Result<VoiceStatus, String>,
+}
+
+// Speakers
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SpeakerInfo {
+    pub id: i64,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RequestListSpeakers {
+    /// Voice to list the speakers of, the default voice when `None`
+    pub voice_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ResponseListSpeakers {
+    /// Sorted by speaker ID, empty for single speaker models
+    pub result: Result<Vec<SpeakerInfo>, String>,
+}
+
+// Phrase Cache
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CacheStats {
+    pub entries: u64,
+    pub size_bytes: u64,
+    pub max_size_bytes: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RequestPrewarmCache {
+    /// Phrases to synthesize ahead of time, the cached ones are
+    /// skipped
+    pub phrases: Vec<String>,
+    pub params: SpeechParams,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ResponsePrewarmCache {
+    pub result: Result<CacheStats, String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RequestClearCache {}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ResponseClearCache {
+    pub result: Result<CacheStats, String>,
+}
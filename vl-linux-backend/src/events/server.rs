@@ -1,10 +1,52 @@
 use serde::{Deserialize, Serialize};
+use vl_global::audio_devices::AvailableDevices;
 
 pub const METHOD_DEVICE_LIST_UPDATED: &str = "device_list_updated";
+/// Asked before serving or pushing anything to an authenticated
+/// client, see [`ResponseSession`]
+pub const METHOD_SESSION: &str = "session";
+/// Backend client that calls [`METHOD_SESSION`]. The backend takes
+/// the name before anyone can connect, clients must not answer
+/// anyone else.
+pub const SESSIONS_CLIENT: &str = "voicelift.sessions";
+
+/// Every speech lifecycle topic lives under this prefix, clients can
+/// subscribe to all of them with [`TOPIC_UTTERANCE_ALL`].
+pub const TOPIC_UTTERANCE_ALL: &str = "speech/#";
+pub const TOPIC_UTTERANCE_STARTED: &str = "speech/utterance_started";
+pub const TOPIC_UTTERANCE_FINISHED: &str =
+    "speech/utterance_finished";
+pub const TOPIC_UTTERANCE_CANCELLED: &str =
+    "speech/utterance_cancelled";
+pub const TOPIC_UTTERANCE_ERROR: &str = "speech/utterance_error";
+
+/// Sent to every connected client when PipeWire nodes or ports
+/// appear or disappear
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RequestDeviceListUpdated {
+    pub devices: AvailableDevices,
+}
 
 #[derive(Serialize, Deserialize, Debug)]
-pub struct RequestDeviceListUpdated;
+pub struct RequestSession {}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ResponseSession {
+    /// Secret of the session the client got when it authenticated,
+    /// `None` before that
+    pub secret: Option<String>,
+}
 
 /// Generic Response back to server
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ResponseServerEvent;
+
+/// Payload published on the `speech/*` topics. Any client of the
+/// broker can subscribe to them, so they carry no text nor error
+/// details, the backend logs those.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UtteranceEvent {
+    pub utterance_id: u64,
+    /// Milliseconds since the UNIX epoch
+    pub timestamp: u64,
+}
@@ -0,0 +1,56 @@
+use std::fs;
+use std::path::PathBuf;
+
+use vl_linux_backend::events::client::DesiredLink;
+
+/// JSON file with the desired links, so the routing survives
+/// restarts of the backend and of the UI
+pub struct LinkStore {
+    path: PathBuf,
+}
+
+impl LinkStore {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// `$XDG_STATE_HOME/voice_lift/links.json`, falling back to
+    /// `~/.local/state`
+    pub fn default_path() -> Option<PathBuf> {
+        let state_home = std::env::var_os("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME").map(|home| {
+                    PathBuf::from(home).join(".local").join("state")
+                })
+            })?;
+        Some(state_home.join("voice_lift").join("links.json"))
+    }
+
+    /// The saved links, none when the file does not exist yet
+    pub fn load(&self) -> Vec<DesiredLink> {
+        let Ok(content) = fs::read_to_string(&self.path) else {
+            return vec![];
+        };
+        serde_json::from_str(&content).unwrap_or_else(|e| {
+            log::warn!(
+                "Ignoring broken link file {:?}: {e}",
+                self.path
+            );
+            vec![]
+        })
+    }
+
+    pub fn save(&self, links: &[DesiredLink]) -> Result<(), String> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("{e}"))?;
+        }
+        let content = serde_json::to_string_pretty(links)
+            .map_err(|e| format!("{e}"))?;
+        // Written under a temporary name, so a crash never leaves a
+        // partial file behind
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, content).map_err(|e| format!("{e}"))?;
+        fs::rename(&tmp_path, &self.path).map_err(|e| format!("{e}"))
+    }
+}
@@ -0,0 +1,160 @@
+use std::fs::{self, DirBuilder};
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::path::Path;
+
+use vl_global::vl_config;
+
+use crate::error::LinuxBackendError;
+
+/// Create the directory of a socket or FIFO, only accessible by the
+/// user. Directories that already exist are left as they are, but
+/// the runtime directory must be private: it can be in the shared
+/// temp dir, where another user could have made it first.
+pub fn create_parent_dir(
+    path: &Path,
+) -> Result<(), LinuxBackendError> {
+    let Some(parent) = path.parent() else {
+        return Ok(());
+    };
+    DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(parent)?;
+    if parent == vl_config::runtime_dir() {
+        check_private_dir(parent)?;
+    }
+    Ok(())
+}
+
+/// Fail unless `dir` is a directory, not a link, owned by the user
+/// and only accessible by them
+fn check_private_dir(dir: &Path) -> Result<(), LinuxBackendError> {
+    let metadata = fs::symlink_metadata(dir)?;
+    let uid = unsafe { libc::geteuid() };
+    if !metadata.is_dir()
+        || metadata.uid() != uid
+        || metadata.mode() & 0o777 != 0o700
+    {
+        return Err(LinuxBackendError::InsecureRuntimeDir(
+            dir.display().to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Run `spawn` with a umask that keeps everything it creates to the
+/// user, so a socket or FIFO is never open to others, not even
+/// between its creation and a chmod
+pub async fn owner_only<T>(spawn: impl Future<Output = T>) -> T {
+    let umask = unsafe { libc::umask(0o077) };
+    let result = spawn.await;
+    unsafe { libc::umask(umask) };
+    result
+}
+
+fn is_lan(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            ip.is_loopback() || ip.is_private() || ip.is_link_local()
+        }
+        IpAddr::V6(ip) => {
+            let first = ip.segments()[0];
+            // Unique local and link local
+            ip.is_loopback()
+                || first & 0xfe00 == 0xfc00
+                || first & 0xffc0 == 0xfe80
+        }
+    }
+}
+
+/// Parse the TCP address to listen on. Unspecified and public
+/// addresses are refused, the backend must not be reachable from the
+/// internet.
+pub fn check_tcp_address(
+    address: &str,
+) -> Result<SocketAddr, LinuxBackendError> {
+    let socket_address: SocketAddr =
+        address.parse().map_err(|_| {
+            LinuxBackendError::InvalidTcpAddress(address.to_owned())
+        })?;
+    if !is_lan(socket_address.ip()) {
+        return Err(LinuxBackendError::InvalidTcpAddress(
+            address.to_owned(),
+        ));
+    }
+    Ok(socket_address)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+
+    #[test]
+    fn accepts_loopback_and_lan_addresses() {
+        for address in [
+            "127.0.0.1:7777",
+            "10.0.0.5:7777",
+            "172.16.1.1:7777",
+            "192.168.1.20:7777",
+            "169.254.10.1:7777",
+            "[::1]:7777",
+            "[fd12:3456::1]:7777",
+            "[fe80::1]:7777",
+        ] {
+            let parsed = check_tcp_address(address);
+            assert_eq!(
+                parsed.ok(),
+                Some(address.parse().unwrap()),
+                "{address}"
+            );
+        }
+    }
+
+    #[test]
+    fn refuses_unspecified_and_public_addresses() {
+        for address in [
+            "0.0.0.0:7777",
+            "[::]:7777",
+            "8.8.8.8:7777",
+            "172.32.0.1:7777",
+            "192.169.0.1:7777",
+            "[2001:db8::1]:7777",
+            "[2606:4700::1111]:7777",
+        ] {
+            assert!(
+                matches!(
+                    check_tcp_address(address),
+                    Err(LinuxBackendError::InvalidTcpAddress(_))
+                ),
+                "{address}"
+            );
+        }
+    }
+
+    #[test]
+    fn refuses_what_is_not_an_address() {
+        for address in ["localhost:7777", "127.0.0.1", "", "[::1]"] {
+            assert!(check_tcp_address(address).is_err(), "{address}");
+        }
+    }
+
+    #[test]
+    fn private_dir_must_not_be_open_to_others() {
+        let dir = std::env::temp_dir().join(format!(
+            "voicelift_listeners_{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        DirBuilder::new().mode(0o700).create(&dir).unwrap();
+        assert!(check_private_dir(&dir).is_ok());
+
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o755))
+            .unwrap();
+        assert!(check_private_dir(&dir).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
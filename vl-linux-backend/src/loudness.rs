@@ -0,0 +1,425 @@
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+use rodio::buffer::SamplesBuffer;
+use rodio::Source;
+
+/// Level no sample goes over after the limiter
+const PEAK_CEILING_DB: f32 = -1.0;
+/// Quiet voices are not raised more than this
+const MAX_GAIN_DB: f64 = 20.0;
+/// The limiter starts lowering the gain this long before a peak
+const LIMITER_LOOKAHEAD_MS: u32 = 5;
+/// Time the limiter takes to go back to unity gain
+const LIMITER_RELEASE_MS: f32 = 100.0;
+
+/// Blocks are 400 ms long and overlap by 75 %, so a new block starts
+/// every step of 100 ms
+const STEPS_PER_BLOCK: usize = 4;
+const STEPS_PER_SECOND: u32 = 10;
+/// Blocks under this level are silence
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+/// Blocks this far under the mean are pauses between words
+const RELATIVE_GATE_LU: f64 = -10.0;
+
+/// Second order IIR filter, transposed direct form II
+#[derive(Default, Clone, Copy)]
+struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    z1: f64,
+    z2: f64,
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b0 * x + self.z1;
+        self.z1 = self.b1 * x - self.a1 * y + self.z2;
+        self.z2 = self.b2 * x - self.a2 * y;
+        y
+    }
+}
+
+/// The two stages of the K-weighting of ITU-R BS.1770, a high shelf
+/// for the head and a high pass, for any sample rate
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let fs = f64::from(sample_rate);
+
+    let f0 = 1681.974450955533;
+    let gain_db = 3.999843853973347;
+    let q = 0.7071752369554196;
+    let k = (PI * f0 / fs).tan();
+    let vh = 10f64.powf(gain_db / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b0: (vh + vb * k / q + k * k) / a0,
+        b1: 2.0 * (k * k - vh) / a0,
+        b2: (vh - vb * k / q + k * k) / a0,
+        a1: 2.0 * (k * k - 1.0) / a0,
+        a2: (1.0 - k / q + k * k) / a0,
+        ..Default::default()
+    };
+
+    let f0 = 38.13547087602444;
+    let q = 0.5003270373238773;
+    let k = (PI * f0 / fs).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b0: 1.0,
+        b1: -2.0,
+        b2: 1.0,
+        a1: 2.0 * (k * k - 1.0) / a0,
+        a2: (1.0 - k / q + k * k) / a0,
+        ..Default::default()
+    };
+
+    [shelf, high_pass]
+}
+
+fn loudness(mean_square: f64) -> f64 {
+    -0.691 + 10.0 * mean_square.log10()
+}
+
+/// Brings an utterance to a target integrated loudness, as defined by
+/// EBU R128, scales it by a volume and keeps its peaks under
+/// [`PEAK_CEILING_DB`].
+///
+/// Sentences are played while the next ones are synthesized, so the
+/// gain is measured on the first sentences until one has sound, then
+/// held for the rest of the utterance. Use one normalizer per
+/// utterance.
+pub struct LoudnessNormalizer {
+    target_lufs: f64,
+    /// Applied over the normalized audio, 1.0 plays at the target
+    volume: f32,
+    /// Gain of the utterance, `None` until it is measured
+    held_gain: Option<f32>,
+    sample_rate: u32,
+    filters: [Biquad; 2],
+    /// Weighted energy of the step being filled
+    step_energy: f64,
+    step_samples: usize,
+    /// Mean square of the last full steps
+    steps: VecDeque<f64>,
+    /// Mean square of every 400 ms block
+    blocks: Vec<f64>,
+    /// Everything measured, for audio shorter than a block
+    total_energy: f64,
+    total_samples: usize,
+    limiter_gain: f32,
+}
+
+impl LoudnessNormalizer {
+    pub fn new(target_lufs: f64, volume: f32) -> Self {
+        Self {
+            target_lufs,
+            volume,
+            held_gain: None,
+            sample_rate: 0,
+            filters: [Biquad::default(); 2],
+            step_energy: 0.0,
+            step_samples: 0,
+            steps: VecDeque::with_capacity(STEPS_PER_BLOCK),
+            blocks: vec![],
+            total_energy: 0.0,
+            total_samples: 0,
+            limiter_gain: 1.0,
+        }
+    }
+
+    /// Measure `buf` if the gain is not known yet, then return it
+    /// with the gain of the utterance and the volume, limited
+    pub fn process(
+        &mut self,
+        buf: SamplesBuffer<f32>,
+    ) -> SamplesBuffer<f32> {
+        let channels = buf.channels();
+        let sample_rate = buf.sample_rate();
+        if sample_rate != self.sample_rate {
+            self.sample_rate = sample_rate;
+            self.filters = k_weighting(sample_rate);
+        }
+        let mut samples: Vec<f32> = buf.collect();
+
+        // Piper voices are mono, other layouts are measured as if
+        // their samples were a single channel
+        if self.held_gain.is_none() {
+            self.measure(&samples);
+            self.held_gain =
+                self.integrated_loudness().map(|_| self.gain());
+        }
+        let gain = self.held_gain.unwrap_or(1.0) * self.volume;
+        for sample in &mut samples {
+            *sample *= gain;
+        }
+        self.limit(&mut samples, usize::from(channels));
+
+        SamplesBuffer::new(channels, sample_rate, samples)
+    }
+
+    fn measure(&mut self, samples: &[f32]) {
+        let step_len =
+            (self.sample_rate / STEPS_PER_SECOND).max(1) as usize;
+        for sample in samples {
+            let weighted = self
+                .filters
+                .iter_mut()
+                .fold(f64::from(*sample), |x, filter| {
+                    filter.process(x)
+                });
+            let energy = weighted * weighted;
+            self.step_energy += energy;
+            self.step_samples += 1;
+            self.total_energy += energy;
+            self.total_samples += 1;
+
+            if self.step_samples < step_len {
+                continue;
+            }
+            if self.steps.len() == STEPS_PER_BLOCK {
+                self.steps.pop_front();
+            }
+            self.steps.push_back(
+                self.step_energy / self.step_samples as f64,
+            );
+            self.step_energy = 0.0;
+            self.step_samples = 0;
+            if self.steps.len() == STEPS_PER_BLOCK {
+                let block = self.steps.iter().sum::<f64>()
+                    / STEPS_PER_BLOCK as f64;
+                self.blocks.push(block);
+            }
+        }
+    }
+
+    /// Gated loudness of everything measured, `None` for silence
+    fn integrated_loudness(&self) -> Option<f64> {
+        if self.blocks.is_empty() {
+            let mean_square =
+                self.total_energy / self.total_samples.max(1) as f64;
+            return Some(loudness(mean_square))
+                .filter(|lufs| *lufs > ABSOLUTE_GATE_LUFS);
+        }
+
+        let mean_over = |threshold: f64| -> Option<f64> {
+            let gated: Vec<f64> = self
+                .blocks
+                .iter()
+                .copied()
+                .filter(|block| loudness(*block) > threshold)
+                .collect();
+            if gated.is_empty() {
+                return None;
+            }
+            Some(gated.iter().sum::<f64>() / gated.len() as f64)
+        };
+        let ungated = mean_over(ABSOLUTE_GATE_LUFS)?;
+        let relative_gate = loudness(ungated) + RELATIVE_GATE_LU;
+        mean_over(relative_gate.max(ABSOLUTE_GATE_LUFS)).map(loudness)
+    }
+
+    fn gain(&self) -> f32 {
+        let Some(integrated) = self.integrated_loudness() else {
+            return 1.0;
+        };
+        let gain_db =
+            (self.target_lufs - integrated).min(MAX_GAIN_DB);
+        10f64.powf(gain_db / 20.0) as f32
+    }
+
+    /// Lower the gain ahead of the samples over the ceiling and bring
+    /// it back slowly after them
+    fn limit(&mut self, samples: &mut [f32], channels: usize) {
+        let ceiling = 10f32.powf(PEAK_CEILING_DB / 20.0);
+        let frame_rate = self.sample_rate as f32;
+        let lookahead = (self.sample_rate * LIMITER_LOOKAHEAD_MS
+            / 1000)
+            .max(1) as usize
+            * channels.max(1);
+        let release =
+            1.0 / (frame_rate * LIMITER_RELEASE_MS / 1000.0);
+
+        // Gain each sample needs to stay under the ceiling
+        let needed: Vec<f32> = samples
+            .iter()
+            .map(|sample| {
+                let peak = sample.abs();
+                if peak > ceiling {
+                    ceiling / peak
+                } else {
+                    1.0
+                }
+            })
+            .collect();
+
+        let mut gain = self.limiter_gain;
+        for (index, sample) in samples.iter_mut().enumerate() {
+            let end = (index + lookahead).min(needed.len());
+            let target = needed[index..end]
+                .iter()
+                .fold(1.0f32, |lowest, needed| lowest.min(*needed));
+            gain = if target < gain {
+                target
+            } else {
+                (gain + release).min(target)
+            };
+            *sample *= gain;
+        }
+        self.limiter_gain = gain;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    /// Mono 1 kHz sine. At full scale it measures -3.01 LUFS.
+    fn sine(amplitude: f32, seconds: f32) -> Vec<f32> {
+        let len = (SAMPLE_RATE as f32 * seconds) as usize;
+        (0..len)
+            .map(|index| {
+                let time = index as f32 / SAMPLE_RATE as f32;
+                amplitude
+                    * (2.0 * std::f32::consts::PI * 1000.0 * time)
+                        .sin()
+            })
+            .collect()
+    }
+
+    fn silence(seconds: f32) -> Vec<f32> {
+        vec![0.0; (SAMPLE_RATE as f32 * seconds) as usize]
+    }
+
+    fn buffer(samples: Vec<f32>) -> SamplesBuffer<f32> {
+        SamplesBuffer::new(1, SAMPLE_RATE, samples)
+    }
+
+    fn measure(samples: &[f32]) -> Option<f64> {
+        let mut normalizer = LoudnessNormalizer::new(-23.0, 1.0);
+        normalizer.process(buffer(samples.to_vec()));
+        normalizer.integrated_loudness()
+    }
+
+    fn assert_near(actual: Option<f64>, expected: f64) {
+        let actual = actual.expect("no loudness measured");
+        assert!(
+            (actual - expected).abs() < 0.5,
+            "{actual} LUFS instead of {expected}"
+        );
+    }
+
+    #[test]
+    fn measures_a_sine() {
+        assert_near(measure(&sine(0.1, 3.0)), -23.0);
+    }
+
+    #[test]
+    fn measures_audio_shorter_than_a_block() {
+        assert_near(measure(&sine(0.1, 0.2)), -23.0);
+    }
+
+    #[test]
+    fn silence_is_not_measured_nor_raised() {
+        let mut normalizer = LoudnessNormalizer::new(-23.0, 1.0);
+        let output: Vec<f32> =
+            normalizer.process(buffer(silence(2.0))).collect();
+        assert_eq!(normalizer.integrated_loudness(), None);
+        assert_eq!(normalizer.gain(), 1.0);
+        assert!(output.iter().all(|sample| *sample == 0.0));
+    }
+
+    #[test]
+    fn silence_is_gated_out() {
+        let mut samples = sine(0.1, 2.0);
+        samples.extend(silence(4.0));
+        assert_near(measure(&samples), -23.0);
+    }
+
+    #[test]
+    fn pauses_are_gated_out() {
+        // 40 dB under the speech, over the absolute gate
+        let mut samples = sine(0.1, 2.0);
+        samples.extend(sine(0.001, 4.0));
+        assert_near(measure(&samples), -23.0);
+    }
+
+    #[test]
+    fn brings_the_audio_to_the_target() {
+        let mut normalizer = LoudnessNormalizer::new(-23.0, 1.0);
+        let output: Vec<f32> =
+            normalizer.process(buffer(sine(0.02, 3.0))).collect();
+        assert_near(measure(&output), -23.0);
+    }
+
+    #[test]
+    fn gain_is_held_after_the_first_sentence() {
+        let mut normalizer = LoudnessNormalizer::new(-23.0, 1.0);
+        normalizer.process(buffer(sine(0.02, 3.0)));
+        // 14 dB louder than the first sentence
+        let output: Vec<f32> =
+            normalizer.process(buffer(sine(0.1, 3.0))).collect();
+        assert_near(measure(&output), -9.0);
+    }
+
+    #[test]
+    fn silence_does_not_hold_the_gain() {
+        let mut normalizer = LoudnessNormalizer::new(-23.0, 1.0);
+        normalizer.process(buffer(silence(1.0)));
+        let output: Vec<f32> =
+            normalizer.process(buffer(sine(0.02, 3.0))).collect();
+        assert_near(measure(&output), -23.0);
+    }
+
+    #[test]
+    fn volume_applies_over_the_target() {
+        let mut normalizer = LoudnessNormalizer::new(-23.0, 0.5);
+        let output: Vec<f32> =
+            normalizer.process(buffer(sine(0.02, 3.0))).collect();
+        assert_near(measure(&output), -29.0);
+    }
+
+    #[test]
+    fn gain_is_capped() {
+        let mut normalizer = LoudnessNormalizer::new(-10.0, 1.0);
+        normalizer.process(buffer(sine(0.001, 1.0)));
+        let max_gain = 10f64.powf(MAX_GAIN_DB / 20.0) as f32;
+        assert!((normalizer.gain() - max_gain).abs() < 1e-3);
+    }
+
+    #[test]
+    fn limiter_keeps_peaks_under_the_ceiling() {
+        let mut normalizer = LoudnessNormalizer::new(-23.0, 1.0);
+        normalizer.sample_rate = SAMPLE_RATE;
+        let mut samples = vec![0.5; SAMPLE_RATE as usize];
+        samples[24000] = 2.0;
+        samples[24001] = -1.5;
+
+        normalizer.limit(&mut samples, 1);
+
+        let ceiling = 10f32.powf(PEAK_CEILING_DB / 20.0);
+        assert!(samples.iter().all(|sample| sample.abs() <= ceiling));
+        // Untouched before the lookahead, back to unity after the
+        // release
+        assert_eq!(samples[0], 0.5);
+        assert_eq!(samples[SAMPLE_RATE as usize - 1], 0.5);
+        assert!(samples[23999] < 0.5);
+    }
+
+    #[test]
+    fn limiter_leaves_quiet_audio_alone() {
+        let mut normalizer = LoudnessNormalizer::new(-23.0, 1.0);
+        normalizer.sample_rate = SAMPLE_RATE;
+        let mut samples = sine(0.5, 0.5);
+        let original = samples.clone();
+
+        normalizer.limit(&mut samples, 1);
+
+        assert_eq!(samples, original);
+    }
+}
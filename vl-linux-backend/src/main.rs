@@ -3,40 +3,85 @@ use busrt::client::AsyncClient;
 use busrt::rpc::Rpc;
 use busrt::rpc::RpcClient;
 use busrt::QoS;
+use clap::Parser;
 use log::LevelFilter;
 use piper::PiperTTSManager;
 use simplelog::{
     ColorChoice, CombinedLogger, ConfigBuilder, TermLogger,
     TerminalMode,
 };
-use std::path::Path;
+use std::path::PathBuf;
 use std::sync::{Arc, OnceLock, RwLock};
 use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc;
 use tokio::time::sleep;
-use vl_global::vl_config::ConfigManager;
+use vl_global::auth::AuthTokens;
+use vl_global::vl_config::{self, ConfigManager};
+use vl_linux_backend::events::server::SESSIONS_CLIENT;
+mod audio_export;
+mod device_watcher;
 mod error;
+mod espeak;
 mod event_handlers;
+mod link_store;
+mod listeners;
 use crate::error::LinuxBackendError;
 
-mod piper;
-use easy_pw::manager::{self, PipeWireManager};
+mod loudness;
 
-static PIPEWIRE_MANAGER: OnceLock<RwLock<PipeWireManager>> =
-    OnceLock::new();
+mod phrase_cache;
+mod piper;
+mod pw_graph;
+mod sessions;
+mod speech_events;
+mod utterance_queue;
+mod virtual_source;
+mod voice_loader;
+use espeak::EspeakEngine;
+use link_store::LinkStore;
+use phrase_cache::PhraseCache;
+use pw_graph::{PwGraph, RoutingConfig};
+use utterance_queue::{EmergencyPolicy, UtteranceQueue};
 
 static PIPERTTS_MANAGER: OnceLock<Arc<RwLock<PiperTTSManager>>> =
     OnceLock::new();
 
+static ESPEAK_ENGINE: OnceLock<EspeakEngine> = OnceLock::new();
+
+/// Owns the virtual source and the links created by the backend
+static PW_GRAPH: OnceLock<PwGraph> = OnceLock::new();
+
+static UTTERANCE_QUEUE: OnceLock<UtteranceQueue> = OnceLock::new();
+
+/// Not set when the cache is disabled
+static PHRASE_CACHE: OnceLock<PhraseCache> = OnceLock::new();
+
+/// Speaks and routes the VoiceLift TTS through PipeWire
+#[derive(Parser)]
+struct Args {
+    /// Socket to listen on, over the config and `VOICELIFT_SOCKET`
+    #[arg(long)]
+    socket: Option<PathBuf>,
+    /// Debug FIFO, over the config and `VOICELIFT_FIFO`
+    #[arg(long)]
+    fifo: Option<PathBuf>,
+    /// Also listen on TCP, on a loopback or LAN address like
+    /// `192.168.0.10:7700`
+    #[arg(long)]
+    tcp: Option<String>,
+}
+
 #[cfg(target_os = "linux")]
 #[tokio::main]
 async fn main() -> Result<(), LinuxBackendError> {
     use crate::event_handlers::handler;
 
+    let args = Args::parse();
+
     CombinedLogger::init(vec![TermLogger::new(
         LevelFilter::Debug,
-        ConfigBuilder::new()
-            //.add_filter_ignore("easy_pw".to_owned())
-            .build(),
+        ConfigBuilder::new().build(),
         TerminalMode::Mixed,
         ColorChoice::Auto,
     )])
@@ -50,43 +95,130 @@ async fn main() -> Result<(), LinuxBackendError> {
     }
 
     let linux = config.linux.as_ref().unwrap();
-    let path = linux.piper_tts_model.clone();
 
-    let piper_model_path = Path::new(&path);
-    if !piper_model_path.exists() {
-        panic!(
-            "Piper model path does not exist: {piper_model_path:?}"
-        )
+    if linux.phrase_cache_max_mb > 0 {
+        let max_size_bytes =
+            u64::from(linux.phrase_cache_max_mb) * 1024 * 1024;
+        match PhraseCache::default_dir() {
+            Some(dir) => {
+                let cache = PhraseCache::new(dir, max_size_bytes);
+                _ = PHRASE_CACHE.set(cache);
+            }
+            None => log::warn!(
+                "No cache directory, the phrase cache is disabled"
+            ),
+        }
     }
 
-    let _ = PIPEWIRE_MANAGER
-        .set(RwLock::new(manager::PipeWireManager::default()));
+    let link_store = LinkStore::default_path().map(LinkStore::new);
+    if link_store.is_none() {
+        log::warn!(
+            "No state directory found, links will not be saved"
+        );
+    }
+    let (device_changes, device_changes_receiver) =
+        mpsc::unbounded_channel();
+    let graph = PW_GRAPH
+        .get_or_init(|| PwGraph::spawn(link_store, device_changes));
+    if let Err(e) =
+        graph.set_routing(RoutingConfig::from_linux(linux))
+    {
+        log::error!("{e}");
+    }
 
-    let piper_tts_manager =
-        piper::PiperTTSManager::new(piper_model_path, 1).unwrap();
+    let piper_tts_manager = piper::PiperTTSManager::new()
+        .map_err(|e| LinuxBackendError::AudioOutput(e.to_string()))?;
 
     let lock_pipertts = Arc::new(RwLock::new(piper_tts_manager));
     _ = PIPERTTS_MANAGER.set(lock_pipertts.clone());
 
+    // A missing model is not fatal, the backend keeps running without
+    // a voice until one is configured
+    _ = ESPEAK_ENGINE.set(EspeakEngine::default());
+    if let Err(e) =
+        linux.voices().and_then(voice_loader::apply_voices)
+    {
+        log::error!("Failed to load voice, running without one: {e}");
+    }
+    tokio::spawn(voice_loader::watch_config(config_manager));
+
+    let speech_events_receiver = speech_events::init();
+    let queue = UTTERANCE_QUEUE.get_or_init(UtteranceQueue::default);
+    queue.set_emergency_policy(EmergencyPolicy::from_linux(linux));
+    queue.set_loudness_target(linux.loudness_target_lufs);
+    utterance_queue::spawn_worker(queue);
+
+    // Clients authenticate with the tokens of this file, the first
+    // run creates it with an admin token
+    AuthTokens::load_or_create()?;
+
     // create a new broker instance
     let mut broker = Broker::new();
-    broker
-        .spawn_unix_server(
-            "/tmp/voicelift.sock",
-            ServerConfig::default(),
-        )
-        .await
-        .unwrap();
-
-    let mut core_client =
-        broker.register_client(BROKER_NAME).await.unwrap();
+    let socket_path = args
+        .socket
+        .unwrap_or_else(|| vl_config::socket_path(Some(linux)));
+    listeners::create_parent_dir(&socket_path)?;
+    listeners::owner_only(broker.spawn_unix_server(
+        &socket_path.to_string_lossy(),
+        ServerConfig::default(),
+    ))
+    .await?;
+    log::info!("Listening on {}", socket_path.display());
+
+    // Remote clients are opt-in
+    if let Some(address) =
+        args.tcp.or_else(|| linux.tcp_listen.clone())
+    {
+        let address = listeners::check_tcp_address(&address)?;
+        broker
+            .spawn_tcp_server(
+                &address.to_string(),
+                ServerConfig::default(),
+            )
+            .await?;
+        log::warn!("Listening on TCP {address}");
+    }
+
+    // publish speech lifecycle events with their own client
+    let speech_events_client =
+        broker.register_client("voicelift.speech_events").await?;
+    tokio::spawn(speech_events::publish_events(
+        speech_events_client,
+        speech_events_receiver,
+    ));
+
+    let mut core_client = broker.register_client(BROKER_NAME).await?;
     log::debug!("NAME: {}", core_client.get_name());
 
     // subscribe the core client to all topics to print publish frames when received
-    core_client.subscribe("#", QoS::No).await.unwrap();
+    core_client.subscribe("#", QoS::No).await?;
+
+    // check the sessions of the clients and forget the ones that
+    // disconnected
+    let clients = handler::ConnectedClients::default();
+    let sessions_client = Arc::new(RpcClient::new0(
+        broker.register_client(SESSIONS_CLIENT).await?,
+    ));
+    tokio::spawn(sessions::sweep_clients(
+        sessions_client.clone(),
+        clients.clone(),
+    ));
+
+    // push device changes to the authenticated clients
+    let device_watcher_client =
+        broker.register_client("voicelift.device_watcher").await?;
+    tokio::spawn(device_watcher::watch_devices(
+        device_changes_receiver,
+        RpcClient::new0(device_watcher_client),
+        sessions_client.clone(),
+        clients.clone(),
+    ));
 
     // create handlers object
-    let handlers = handler::EventHandler {};
+    let handlers = handler::EventHandler {
+        clients,
+        sessions: sessions_client,
+    };
     // create RPC
     let crpc = RpcClient::new(core_client, handlers);
 
@@ -95,19 +227,54 @@ async fn main() -> Result<(), LinuxBackendError> {
     // Arc<Mutex<_>> as it is cloned for each fifo spawned and can be got back with core_rpc_client
     // broker method
     broker.set_core_rpc_client(crpc).await;
-    // test it with echo .broker .hello > /tmp/busrt.fifo
-    broker.spawn_fifo("/tmp/busrt.fifo", 8192).await.unwrap();
+    // test it with
+    // echo .broker .hello > $XDG_RUNTIME_DIR/voicelift/busrt.fifo
+    let fifo_path = args
+        .fifo
+        .unwrap_or_else(|| vl_config::fifo_path(Some(linux)));
+    listeners::create_parent_dir(&fifo_path)?;
+    listeners::owner_only(
+        broker.spawn_fifo(&fifo_path.to_string_lossy(), 8192),
+    )
+    .await?;
+
     // this is the internal client, it will be connected forever
-    while broker
-        .core_rpc_client()
-        .lock()
-        .await
-        .as_ref()
-        .unwrap()
-        .is_connected()
-    {
-        sleep(Duration::from_secs(1)).await;
+    let broker_connected = async {
+        while broker
+            .core_rpc_client()
+            .lock()
+            .await
+            .as_ref()
+            .unwrap()
+            .is_connected()
+        {
+            sleep(Duration::from_secs(1)).await;
+        }
+    };
+    tokio::select! {
+        _ = broker_connected => {}
+        _ = shutdown_signal() => log::info!("Shutting down"),
     }
 
+    if let Some(graph) = PW_GRAPH.get() {
+        graph.shutdown();
+    }
     Ok(())
 }
+
+/// Wait for Ctrl+C or SIGTERM, so the PipeWire objects we created
+/// can be removed before exiting
+async fn shutdown_signal() {
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(e) => {
+            log::error!("Failed to listen to SIGTERM: {e}");
+            _ = tokio::signal::ctrl_c().await;
+            return;
+        }
+    };
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+}
@@ -0,0 +1,399 @@
+use std::fs::{self, File};
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard};
+use std::time::SystemTime;
+
+use rodio::buffer::SamplesBuffer;
+use sha2::{Digest, Sha256};
+use vl_linux_backend::events::client::{CacheStats, SpeechParams};
+
+use crate::{voice_loader, PHRASE_CACHE};
+
+const ENTRY_EXTENSION: &str = "wav";
+
+/// Content-addressed cache of synthesized phrases. Each entry is a
+/// WAV file named after the hash of everything that changes the
+/// audio. The modification time of an entry is its last use, the
+/// least recently used entries are evicted once the cache grows over
+/// `max_size_bytes`.
+pub struct PhraseCache {
+    dir: PathBuf,
+    max_size_bytes: u64,
+    /// Serializes writes and evictions
+    lock: Mutex<()>,
+}
+
+struct CacheEntry {
+    path: PathBuf,
+    size_bytes: u64,
+    last_used: SystemTime,
+}
+
+impl PhraseCache {
+    pub fn new(dir: PathBuf, max_size_bytes: u64) -> Self {
+        Self {
+            dir,
+            max_size_bytes,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// `$XDG_CACHE_HOME/voice_lift/phrases`, falling back to
+    /// `~/.cache`
+    pub fn default_dir() -> Option<PathBuf> {
+        let cache_home = std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME")
+                    .map(|home| PathBuf::from(home).join(".cache"))
+            })?;
+        Some(cache_home.join("voice_lift").join("phrases"))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ()> {
+        self.lock.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Key of a phrase spoken with `params` by the voice with the
+    /// source `voice`, see [`voice_loader::voice_source`]
+    pub fn key(
+        voice: &str,
+        text: &str,
+        params: &SpeechParams,
+    ) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(voice.as_bytes());
+        hasher.update([0]);
+        hasher.update(text.as_bytes());
+        hasher.update([0]);
+        hasher.update(params.speaker_id.to_le_bytes());
+        hasher.update([params.pitch, params.volume, params.rate]);
+        hasher.update(params.appended_silence_ms.to_le_bytes());
+        format!("{:x}", hasher.finalize())
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.dir.join(key).with_extension(ENTRY_EXTENSION)
+    }
+
+    /// Read an entry and mark it as used
+    pub fn get(&self, key: &str) -> Option<(u32, Vec<f32>)> {
+        let path = self.entry_path(key);
+        let reader = hound::WavReader::open(&path).ok()?;
+        let sample_rate = reader.spec().sample_rate;
+        let samples = match reader
+            .into_samples::<f32>()
+            .collect::<Result<Vec<f32>, _>>()
+        {
+            Ok(samples) => samples,
+            Err(e) => {
+                log::warn!(
+                    "Dropping broken cache entry {path:?}: {e}"
+                );
+                let _ = fs::remove_file(&path);
+                return None;
+            }
+        };
+
+        if let Err(e) = File::options()
+            .append(true)
+            .open(&path)
+            .and_then(|file| file.set_modified(SystemTime::now()))
+        {
+            log::warn!("Failed to touch cache entry {path:?}: {e}");
+        }
+        Some((sample_rate, samples))
+    }
+
+    pub fn insert(
+        &self,
+        key: &str,
+        sample_rate: u32,
+        samples: &[f32],
+    ) -> Result<(), String> {
+        let _guard = self.lock();
+        fs::create_dir_all(&self.dir).map_err(|e| format!("{e}"))?;
+
+        // Written under a temporary name, so readers never see a
+        // partial entry
+        let path = self.entry_path(key);
+        let tmp_path = path.with_extension("tmp");
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let mut writer = hound::WavWriter::create(&tmp_path, spec)
+            .map_err(|e| format!("{e}"))?;
+        for sample in samples {
+            writer
+                .write_sample(*sample)
+                .map_err(|e| format!("{e}"))?;
+        }
+        writer.finalize().map_err(|e| format!("{e}"))?;
+        fs::rename(&tmp_path, &path).map_err(|e| format!("{e}"))?;
+
+        self.evict();
+        Ok(())
+    }
+
+    fn entries(&self) -> Vec<CacheEntry> {
+        let Ok(read_dir) = fs::read_dir(&self.dir) else {
+            return vec![];
+        };
+        read_dir
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                if path.extension()? != ENTRY_EXTENSION {
+                    return None;
+                }
+                let metadata = path.metadata().ok()?;
+                Some(CacheEntry {
+                    size_bytes: metadata.len(),
+                    last_used: metadata.modified().ok()?,
+                    path,
+                })
+            })
+            .collect()
+    }
+
+    /// Remove the least recently used entries until the cache fits
+    /// its size limit
+    fn evict(&self) {
+        let mut entries = self.entries();
+        let mut size_bytes: u64 =
+            entries.iter().map(|entry| entry.size_bytes).sum();
+        if size_bytes <= self.max_size_bytes {
+            return;
+        }
+
+        entries.sort_by_key(|entry| entry.last_used);
+        for entry in entries {
+            if size_bytes <= self.max_size_bytes {
+                break;
+            }
+            match fs::remove_file(&entry.path) {
+                Ok(()) => size_bytes -= entry.size_bytes,
+                Err(e) => log::warn!(
+                    "Failed to evict cache entry {:?}: {e}",
+                    entry.path
+                ),
+            }
+        }
+    }
+
+    pub fn clear(&self) -> Result<CacheStats, String> {
+        let _guard = self.lock();
+        for entry in self.entries() {
+            fs::remove_file(&entry.path).map_err(|e| {
+                format!("Failed to remove {:?}: {e}", entry.path)
+            })?;
+        }
+        Ok(self.stats())
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let entries = self.entries();
+        CacheStats {
+            entries: entries.len() as u64,
+            size_bytes: entries
+                .iter()
+                .map(|entry| entry.size_bytes)
+                .sum(),
+            max_size_bytes: self.max_size_bytes,
+        }
+    }
+}
+
+/// Cache key of `text` spoken with `params`, `None` when the cache
+/// is disabled
+pub fn key_for(
+    text: &str,
+    params: &SpeechParams,
+) -> Result<Option<String>, String> {
+    if PHRASE_CACHE.get().is_none() {
+        return Ok(None);
+    }
+    let voice = voice_loader::voice_source(params.voice_id())?;
+    Ok(Some(PhraseCache::key(&voice, text, params)))
+}
+
+/// Cached audio of an entry as a buffer ready for the sink
+pub fn get_buffer(key: &str) -> Option<SamplesBuffer<f32>> {
+    let (sample_rate, samples) = PHRASE_CACHE.get()?.get(key)?;
+    Some(SamplesBuffer::new(1, sample_rate, samples))
+}
+
+pub fn insert(key: &str, sample_rate: u32, samples: &[f32]) {
+    let Some(cache) = PHRASE_CACHE.get() else {
+        return;
+    };
+    if let Err(e) = cache.insert(key, sample_rate, samples) {
+        log::error!("Failed to cache phrase: {e}");
+    }
+}
+
+/// Synthesize the whole `text` at once, going through the cache.
+/// Returns the sample rate and the samples.
+///
+/// Synthesis is slow, call this from a blocking context.
+pub fn synthesize_cached(
+    text: &str,
+    params: &SpeechParams,
+) -> Result<(u32, Vec<f32>), String> {
+    let key = key_for(text, params)?;
+    if let Some(hit) =
+        key.as_deref().and_then(|key| PHRASE_CACHE.get()?.get(key))
+    {
+        return Ok(hit);
+    }
+
+    let voice_id = params.voice_id();
+    let (sample_rate, samples) = voice_loader::engine_for(voice_id)?
+        .synthesize_all(voice_id, text, params)
+        .map_err(|e| format!("{e}"))?;
+
+    if let Some(key) = key {
+        insert(&key, sample_rate, &samples);
+    }
+    Ok((sample_rate, samples))
+}
+
+/// Synthesize every phrase that is not cached yet
+///
+/// Synthesis is slow, call this from a blocking context.
+pub fn prewarm(
+    phrases: &[String],
+    params: &SpeechParams,
+) -> Result<CacheStats, String> {
+    let cache =
+        PHRASE_CACHE.get().ok_or("The phrase cache is disabled")?;
+    for phrase in phrases {
+        synthesize_cached(phrase, params)?;
+    }
+    Ok(cache.stats())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    fn params() -> SpeechParams {
+        SpeechParams {
+            voice_id: None,
+            speaker_id: 1,
+            pitch: 48,
+            volume: 128,
+            rate: 50,
+            appended_silence_ms: 0,
+        }
+    }
+
+    /// Empty directory of its own for each test
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "voice_lift_phrase_cache_{name}_{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_entry(
+        dir: &Path,
+        name: &str,
+        size: usize,
+        age_secs: u64,
+    ) {
+        let path = dir.join(name).with_extension(ENTRY_EXTENSION);
+        fs::write(&path, vec![0u8; size]).unwrap();
+        let last_used = SystemTime::now()
+            - std::time::Duration::from_secs(age_secs);
+        File::options()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(last_used)
+            .unwrap();
+    }
+
+    #[test]
+    fn key_is_stable() {
+        assert_eq!(
+            PhraseCache::key("voice", "Olá", &params()),
+            PhraseCache::key("voice", "Olá", &params())
+        );
+    }
+
+    #[test]
+    fn key_changes_with_everything_that_changes_the_audio() {
+        let key = PhraseCache::key("voice", "Olá", &params());
+        assert_ne!(key, PhraseCache::key("other", "Olá", &params()));
+        assert_ne!(key, PhraseCache::key("voice", "Oi", &params()));
+        let mut changed = params();
+        changed.rate = 60;
+        assert_ne!(key, PhraseCache::key("voice", "Olá", &changed));
+        let mut changed = params();
+        changed.speaker_id = 2;
+        assert_ne!(key, PhraseCache::key("voice", "Olá", &changed));
+    }
+
+    #[test]
+    fn key_separates_voice_and_text() {
+        // Without a separator both would hash "ab" + "c"
+        assert_ne!(
+            PhraseCache::key("ab", "c", &params()),
+            PhraseCache::key("a", "bc", &params())
+        );
+    }
+
+    #[test]
+    fn evict_removes_least_recently_used_first() {
+        let dir = test_dir("evict");
+        write_entry(&dir, "old", 100, 300);
+        write_entry(&dir, "middle", 100, 200);
+        write_entry(&dir, "new", 100, 100);
+        let cache = PhraseCache::new(dir.clone(), 200);
+
+        cache.evict();
+
+        let entry = |name: &str| {
+            dir.join(name).with_extension(ENTRY_EXTENSION).exists()
+        };
+        assert!(!entry("old"));
+        assert!(entry("middle"));
+        assert!(entry("new"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn evict_keeps_everything_under_the_limit() {
+        let dir = test_dir("under_limit");
+        write_entry(&dir, "a", 100, 100);
+        write_entry(&dir, "b", 100, 200);
+        let cache = PhraseCache::new(dir.clone(), 200);
+
+        cache.evict();
+
+        assert_eq!(cache.stats().entries, 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn evict_ignores_other_files() {
+        let dir = test_dir("other_files");
+        write_entry(&dir, "entry", 100, 100);
+        fs::write(dir.join("notes.txt"), vec![0u8; 1000]).unwrap();
+        let cache = PhraseCache::new(dir.clone(), 50);
+
+        cache.evict();
+
+        assert!(dir.join("notes.txt").exists());
+        assert_eq!(cache.stats().entries, 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,10 +1,20 @@
 use piper_rs::synth::{
-    AudioOutputConfig, PiperSpeechStreamParallel,
-    PiperSpeechSynthesizer,
+    AudioOutputConfig, PiperSpeechStreamLazy, PiperSpeechSynthesizer,
 };
+use piper_rs::PiperModel;
 use rodio::buffer::SamplesBuffer;
-use std::path::Path;
+use rodio::Source;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use thiserror::Error;
+use vl_linux_backend::engine::{
+    EngineCapabilities, EngineVoice, Sentences, SynthesisEngine,
+};
+use vl_linux_backend::error::EngineError;
+use vl_linux_backend::events::client::SpeechParams;
+
+use crate::{voice_loader, PIPERTTS_MANAGER};
 const CARGO_PKG_NAME: &str = env!("CARGO_PKG_NAME");
 
 // TODO: Unify errors
@@ -17,10 +27,27 @@ pub enum VlLinuxBackendInternalError {
     RodioPlayer(#[from] rodio::PlayError),
     #[error("Rodio Stream Error")]
     RodioStream(#[from] rodio::StreamError),
+    #[error("Voice not loaded: {0}")]
+    NoVoiceLoaded(String),
+}
+
+/// Voice loaded by [`PiperTTSManager`]
+struct LoadedVoice {
+    model_path: PathBuf,
+    /// Kept to switch speakers, it's shared with the synthesizer
+    model: Arc<dyn PiperModel + Send + Sync>,
+    synthesizer: PiperSpeechSynthesizer,
+    /// The speaker is part of the model state, this is held while a
+    /// sentence is synthesized so concurrent syntheses don't mix
+    /// their speakers up
+    speaker_lock: Arc<Mutex<()>>,
 }
 
 pub struct PiperTTSManager {
-    model: PiperSpeechSynthesizer,
+    /// Model config path of every known voice, keyed by voice ID
+    voice_paths: HashMap<String, PathBuf>,
+    /// Voices loaded so far. They are loaded on first use.
+    voices: HashMap<String, LoadedVoice>,
     rodio_sink: rodio::Sink,
     _rodio_stream: rodio::OutputStream,
     _rodio_handle: rodio::OutputStreamHandle,
@@ -29,20 +56,16 @@ unsafe impl Sync for PiperTTSManager {}
 unsafe impl Send for PiperTTSManager {}
 
 impl PiperTTSManager {
-    pub fn new(
-        model_config_path: &Path,
-        speaker_id: i64,
-    ) -> Result<Self, VlLinuxBackendInternalError> {
-        let model = piper_rs::from_config_path(model_config_path)?;
-        model.set_speaker(speaker_id);
-        let speech_synthesizer = PiperSpeechSynthesizer::new(model)?;
-
+    /// Create a manager without any voice. Speaking fails until
+    /// voices are configured with [`PiperTTSManager::set_voice_paths`].
+    pub fn new() -> Result<Self, VlLinuxBackendInternalError> {
         let (rodio_stream, rodio_handle) =
             rodio::OutputStream::try_default()?;
         let rodio_sink = rodio::Sink::try_new(&rodio_handle)?;
 
         let manager = Self {
-            model: speech_synthesizer,
+            voice_paths: HashMap::new(),
+            voices: HashMap::new(),
             rodio_sink,
             _rodio_stream: rodio_stream,
             _rodio_handle: rodio_handle,
@@ -51,52 +74,231 @@ impl PiperTTSManager {
         Ok(manager)
     }
 
-    pub fn speak(
+    /// Load a Piper model. This is slow, so it is kept apart from
+    /// [`PiperTTSManager::insert_voice`] to avoid locking the manager
+    /// while the model loads.
+    pub fn load_model(
+        model_config_path: &Path,
+    ) -> Result<
+        Arc<dyn PiperModel + Send + Sync>,
+        VlLinuxBackendInternalError,
+    > {
+        Ok(piper_rs::from_config_path(model_config_path)?)
+    }
+
+    /// Replace the known voices. Loaded voices whose path changed or
+    /// that are gone are unloaded.
+    pub fn set_voice_paths(
+        &mut self,
+        voice_paths: HashMap<String, PathBuf>,
+    ) {
+        self.voices.retain(|voice_id, voice| {
+            voice_paths.get(voice_id) == Some(&voice.model_path)
+        });
+        self.voice_paths = voice_paths;
+    }
+
+    /// Store a model loaded from `model_path`. It is dropped, and
+    /// `false` returned, if the voice was reconfigured while the
+    /// model was loading.
+    pub fn insert_voice(
+        &mut self,
+        voice_id: &str,
+        model_path: PathBuf,
+        model: Arc<dyn PiperModel + Send + Sync>,
+    ) -> Result<bool, VlLinuxBackendInternalError> {
+        if self.voice_paths.get(voice_id) != Some(&model_path) {
+            return Ok(false);
+        }
+        let synthesizer = PiperSpeechSynthesizer::new(model.clone())?;
+        self.voices.insert(
+            voice_id.to_owned(),
+            LoadedVoice {
+                model_path,
+                model,
+                synthesizer,
+                speaker_lock: Arc::default(),
+            },
+        );
+        Ok(true)
+    }
+
+    /// Path of the model config of a voice, if it's known
+    pub fn voice_path(&self, voice_id: &str) -> Option<&Path> {
+        self.voice_paths.get(voice_id).map(PathBuf::as_path)
+    }
+
+    pub fn voice_paths(&self) -> &HashMap<String, PathBuf> {
+        &self.voice_paths
+    }
+
+    pub fn is_loaded(&self, voice_id: &str) -> bool {
+        self.voices.contains_key(voice_id)
+    }
+
+    /// Prepare the synthesis of `text`. Nothing is synthesized yet,
+    /// each sentence is rendered when the returned stream advances,
+    /// so playback can start with the first one.
+    pub fn synthesize_sentences(
         &self,
+        voice_id: &str,
         text: String,
-        pitch: u8,
-        volume: u8,
-    ) -> Result<(), VlLinuxBackendInternalError> {
-        let audio = self.model.synthesize_parallel(
-            text.clone(),
+        params: &SpeechParams,
+    ) -> Result<SentenceStream, VlLinuxBackendInternalError> {
+        let voice = self.voices.get(voice_id).ok_or_else(|| {
+            VlLinuxBackendInternalError::NoVoiceLoaded(
+                voice_id.to_owned(),
+            )
+        })?;
+        let stream = voice.synthesizer.synthesize_lazy(
+            text,
             Some(AudioOutputConfig {
-                volume: Some(volume),
-                pitch: Some(pitch),
-                appended_silence_ms: None,
-                rate: None,
+                volume: Some(params.volume),
+                pitch: Some(params.pitch),
+                appended_silence_ms: Some(params.appended_silence_ms),
+                rate: Some(params.rate),
             }),
         )?;
-        let buf = stream_to_sample_buffer(audio)?;
+        Ok(SentenceStream {
+            stream,
+            model: voice.model.clone(),
+            speaker_id: params.speaker_id,
+            speaker_lock: voice.speaker_lock.clone(),
+        })
+    }
 
+    /// Append the buffer to the sink. This does not wait for the
+    /// audio to finish.
+    pub fn play(&self, buf: SamplesBuffer<f32>) {
         self.rodio_sink.append(buf);
-        log::info!("Playing audio... {text}");
-        self.rodio_sink.sleep_until_end();
-        Ok(())
+    }
+
+    pub fn is_playing(&self) -> bool {
+        !self.rodio_sink.empty()
     }
 
     pub fn stop_speak(
         &self,
     ) -> Result<(), VlLinuxBackendInternalError> {
         self.rodio_sink.stop();
+        // The next utterance must not start paused
+        self.rodio_sink.play();
         Ok(())
     }
 
+    pub fn pause_speak(&self) {
+        self.rodio_sink.pause();
+    }
+
+    pub fn resume_speak(&self) {
+        self.rodio_sink.play();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.rodio_sink.is_paused()
+    }
+
     pub fn get_handle_name() -> String {
         format!("alsa_playback.{CARGO_PKG_NAME}")
     }
 }
 
-fn stream_to_sample_buffer(
-    stream: PiperSpeechStreamParallel,
-) -> Result<SamplesBuffer<f32>, VlLinuxBackendInternalError> {
-    // Converts Audio into Vec<f32> samples
-    let mut samples: Vec<f32> = Vec::new();
-    let mut samplerate: u32 = 22050;
-    for audio in stream {
-        let _audio = audio?;
-        samplerate = _audio.info.sample_rate as u32;
-        samples.append(&mut _audio.into_vec());
-    }
-    let buf = SamplesBuffer::new(1, samplerate, samples);
-    Ok(buf)
+/// Audio of a text, one buffer per sentence. Sentences are
+/// synthesized on demand, dropping the stream cancels the rest.
+pub struct SentenceStream {
+    stream: PiperSpeechStreamLazy,
+    model: Arc<dyn PiperModel + Send + Sync>,
+    speaker_id: i64,
+    speaker_lock: Arc<Mutex<()>>,
+}
+
+impl Iterator for SentenceStream {
+    type Item =
+        Result<SamplesBuffer<f32>, VlLinuxBackendInternalError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let _speaker_guard = self
+            .speaker_lock
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if let Some(e) = self.model.set_speaker(self.speaker_id) {
+            return Some(Err(e.into()));
+        }
+        let audio = match self.stream.next()? {
+            Ok(audio) => audio,
+            Err(e) => return Some(Err(e.into())),
+        };
+        let samplerate = audio.info.sample_rate as u32;
+        Some(Ok(SamplesBuffer::new(1, samplerate, audio.into_vec())))
+    }
+}
+
+/// Piper voices of [`PIPERTTS_MANAGER`], loaded on first use
+pub struct PiperEngine;
+
+impl SynthesisEngine for PiperEngine {
+    fn synthesize(
+        &self,
+        voice_id: &str,
+        text: &str,
+        params: &SpeechParams,
+    ) -> Result<Sentences, EngineError> {
+        voice_loader::ensure_loaded(voice_id)
+            .map_err(EngineError::LoadVoice)?;
+        let sentences = PIPERTTS_MANAGER
+            .get()
+            .ok_or(EngineError::Synthesis(
+                "PIPERTTS_MANAGER not set".to_owned(),
+            ))?
+            .read()
+            .map_err(|_| {
+                EngineError::Synthesis(
+                    "Failed to lock PIPERTTS_MANAGER".to_owned(),
+                )
+            })?
+            .synthesize_sentences(voice_id, text.to_owned(), params)
+            .map_err(|e| EngineError::Synthesis(format!("{e}")))?;
+        Ok(Box::new(sentences.map(|buf| {
+            let buf = buf.map_err(|e| {
+                EngineError::Synthesis(format!("{e}"))
+            })?;
+            Ok((buf.sample_rate(), buf.collect()))
+        })))
+    }
+
+    fn list_voices(&self) -> Result<Vec<EngineVoice>, EngineError> {
+        let manager = PIPERTTS_MANAGER
+            .get()
+            .ok_or(EngineError::Synthesis(
+                "PIPERTTS_MANAGER not set".to_owned(),
+            ))?
+            .read()
+            .map_err(|_| {
+                EngineError::Synthesis(
+                    "Failed to lock PIPERTTS_MANAGER".to_owned(),
+                )
+            })?;
+        let mut voices: Vec<EngineVoice> = manager
+            .voice_paths()
+            .iter()
+            .map(|(voice_id, model_path)| EngineVoice {
+                id: voice_id.clone(),
+                source: model_path.display().to_string(),
+                language: voice_loader::model_language(model_path),
+                loaded: manager.is_loaded(voice_id),
+            })
+            .collect();
+        voices.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(voices)
+    }
+
+    fn capabilities(&self) -> EngineCapabilities {
+        EngineCapabilities {
+            name: "piper".to_owned(),
+            streaming: true,
+            multi_speaker: true,
+            pitch: true,
+            rate: true,
+        }
+    }
 }
@@ -0,0 +1,1489 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+use std::sync::mpsc;
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use pipewire as pw;
+use pw::properties::properties;
+use pw::spa::param::ParamType;
+use pw::spa::pod::{Value, ValueArray};
+use pw::spa::utils::result::AsyncSeq;
+use pw::types::ObjectType;
+use tokio::sync::mpsc::UnboundedSender;
+use vl_global::audio_devices::{AvailableDevices, DeviceInfo};
+use vl_global::vl_config::{DuckingConfig, LinuxConfig};
+use vl_linux_backend::events::client::{
+    DesiredLink, LinkSource, LinkState, LinkedPorts, PortMapping,
+};
+
+use crate::link_store::LinkStore;
+use crate::piper::PiperTTSManager;
+use crate::virtual_source::{
+    self, MICROPHONE_MIX_INPUT_NAME, TTS_MIX_INPUT_NAME,
+    VIRTUAL_SOURCE_NAME,
+};
+
+/// How long RPC handlers wait for the PipeWire thread to answer
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct NodeInfo {
+    name: String,
+    description: Option<String>,
+    media_class: Option<String>,
+    application_name: Option<String>,
+}
+
+struct PortInfo {
+    node_id: u32,
+    name: String,
+    is_output: bool,
+    channel: Option<String>,
+}
+
+struct LinkInfo {
+    output_port: u32,
+    input_port: u32,
+}
+
+/// What the registry told us about the graph, plus the links we own
+#[derive(Default)]
+struct Graph {
+    nodes: HashMap<u32, NodeInfo>,
+    ports: HashMap<u32, PortInfo>,
+    /// Every link in the graph, keyed by link ID
+    links: HashMap<u32, LinkInfo>,
+    /// Links we created, keyed by (output port, input port). Dropping
+    /// a proxy destroys its link.
+    owned_links: HashMap<(u32, u32), pw::link::Link>,
+}
+
+impl Graph {
+    /// Every node with a `node.name`, sorted by ID. Names are not
+    /// unique: two streams of an app can share one.
+    fn node_ids(&self, name: &str) -> Vec<u32> {
+        let mut ids: Vec<u32> = self
+            .nodes
+            .iter()
+            .filter(|(_, node)| node.name == name)
+            .map(|(id, _)| *id)
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    fn has_node(&self, name: &str) -> bool {
+        self.nodes.values().any(|node| node.name == name)
+    }
+
+    /// Every (output node, input node) pair between two names
+    fn node_pairs(
+        &self,
+        output_node: &str,
+        input_node: &str,
+    ) -> Vec<(u32, u32)> {
+        let inputs = self.node_ids(input_node);
+        self.node_ids(output_node)
+            .into_iter()
+            .flat_map(|output| {
+                inputs.iter().map(move |input| (output, *input))
+            })
+            .collect()
+    }
+
+    /// Ports of a node, sorted by ID so their order is stable
+    fn ports_of(&self, node_id: u32, is_output: bool) -> Vec<u32> {
+        let mut ports: Vec<u32> = self
+            .ports
+            .iter()
+            .filter(|(_, port)| {
+                port.node_id == node_id && port.is_output == is_output
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        ports.sort();
+        ports
+    }
+
+    /// Nodes with input ports are input devices, nodes with output
+    /// ports are output devices. A node can be both.
+    fn devices(&self) -> AvailableDevices {
+        let mut devices = AvailableDevices::default();
+        let mut ids: Vec<&u32> = self.nodes.keys().collect();
+        ids.sort();
+        for id in ids {
+            let node = &self.nodes[id];
+            let device = |channels: usize| DeviceInfo {
+                id: *id,
+                name: node.name.clone(),
+                description: node.description.clone(),
+                media_class: node.media_class.clone(),
+                application_name: node.application_name.clone(),
+                channels: channels as u32,
+            };
+            let inputs = self.ports_of(*id, false).len();
+            if inputs > 0 {
+                devices.input_devices.push(device(inputs));
+            }
+            let outputs = self.ports_of(*id, true).len();
+            if outputs > 0 {
+                devices.output_devices.push(device(outputs));
+            }
+        }
+        devices
+    }
+
+    fn port_channel(&self, port_id: u32) -> Option<&str> {
+        self.ports.get(&port_id)?.channel.as_deref()
+    }
+
+    /// Find the port of a node by audio channel or by port name
+    fn find_port(&self, ports: &[u32], channel: &str) -> Option<u32> {
+        ports.iter().copied().find(|id| {
+            self.ports.get(id).is_some_and(|port| {
+                port.channel.as_deref() == Some(channel)
+                    || port.name == channel
+            })
+        })
+    }
+
+    /// Pair the output ports of a node with the input ports of
+    /// another. A single port on either side is paired with every
+    /// port on the other side, so mono goes to FL and FR. Otherwise
+    /// ports are paired by audio channel, falling back to their order
+    /// when no channel matches.
+    fn pair_ports(
+        &self,
+        output_node: u32,
+        input_node: u32,
+        mapping: Option<&[PortMapping]>,
+    ) -> Result<Vec<(u32, u32)>, String> {
+        let outputs = self.ports_of(output_node, true);
+        let inputs = self.ports_of(input_node, false);
+
+        if let Some(mapping) = mapping {
+            return mapping
+                .iter()
+                .map(|map| {
+                    let output = self
+                        .find_port(&outputs, &map.output_channel)
+                        .ok_or(format!(
+                            "Output port not found: {}",
+                            map.output_channel
+                        ))?;
+                    let input = self
+                        .find_port(&inputs, &map.input_channel)
+                        .ok_or(format!(
+                            "Input port not found: {}",
+                            map.input_channel
+                        ))?;
+                    Ok((output, input))
+                })
+                .collect();
+        }
+
+        if outputs.len() == 1 || inputs.len() == 1 {
+            let pairs = outputs
+                .iter()
+                .flat_map(|output| {
+                    inputs.iter().map(move |input| (*output, *input))
+                })
+                .collect();
+            return Ok(pairs);
+        }
+
+        let by_channel: Vec<(u32, u32)> = outputs
+            .iter()
+            .filter_map(|output| {
+                let channel = self.port_channel(*output)?;
+                let input = inputs.iter().find(|input| {
+                    self.port_channel(**input) == Some(channel)
+                })?;
+                Some((*output, *input))
+            })
+            .collect();
+        if !by_channel.is_empty() {
+            return Ok(by_channel);
+        }
+        Ok(outputs.into_iter().zip(inputs).collect())
+    }
+
+    fn is_linked(&self, output_port: u32, input_port: u32) -> bool {
+        self.owned_links.contains_key(&(output_port, input_port))
+            || self.links.values().any(|link| {
+                link.output_port == output_port
+                    && link.input_port == input_port
+            })
+    }
+
+    fn linked_ports(
+        &self,
+        output_port: u32,
+        input_port: u32,
+    ) -> LinkedPorts {
+        let name = |id: u32| {
+            self.ports
+                .get(&id)
+                .map(|port| port.name.clone())
+                .unwrap_or_else(|| id.to_string())
+        };
+        LinkedPorts {
+            output_port: name(output_port),
+            input_port: name(input_port),
+        }
+    }
+}
+
+/// How the backend handles the audio besides the desired links
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoutingConfig {
+    /// Keep the session manager from linking the TTS to the default
+    /// sink
+    pub detached_from_default_sink: bool,
+    /// Capture device mixed with the TTS into the virtual source
+    pub microphone: Option<String>,
+    /// 1.0 leaves the audio unchanged
+    pub microphone_gain: f32,
+    pub tts_gain: f32,
+    pub ducking: DuckingConfig,
+}
+
+impl Default for RoutingConfig {
+    fn default() -> Self {
+        Self {
+            detached_from_default_sink: false,
+            microphone: None,
+            microphone_gain: 1.0,
+            tts_gain: 1.0,
+            ducking: DuckingConfig::default(),
+        }
+    }
+}
+
+impl RoutingConfig {
+    pub fn from_linux(linux: &LinuxConfig) -> Self {
+        Self {
+            detached_from_default_sink: linux
+                .detach_from_default_sink,
+            microphone: linux
+                .mix_microphone
+                .clone()
+                .filter(|name| !name.is_empty()),
+            microphone_gain: f32::from(linux.mix_microphone_gain)
+                / 100.0,
+            tts_gain: f32::from(linux.mix_tts_gain) / 100.0,
+            ducking: linux.ducking.clone(),
+        }
+    }
+}
+
+/// `node.name` of what a desired link starts from
+fn source_node_name(source: LinkSource) -> String {
+    match source {
+        LinkSource::Tts => PiperTTSManager::get_handle_name(),
+        LinkSource::Mix => VIRTUAL_SOURCE_NAME.to_owned(),
+    }
+}
+
+/// Requests handled by the PipeWire thread
+enum Command {
+    AddDesiredLink {
+        link: DesiredLink,
+        reply: mpsc::Sender<Result<Vec<LinkedPorts>, String>>,
+    },
+    RemoveDesiredLink {
+        source: LinkSource,
+        target_device: String,
+        reply: mpsc::Sender<Result<(), String>>,
+    },
+    SetDesiredLinks {
+        links: Vec<DesiredLink>,
+        reply: mpsc::Sender<Vec<LinkState>>,
+    },
+    Links {
+        reply: mpsc::Sender<Vec<LinkState>>,
+    },
+    Devices {
+        reply: mpsc::Sender<AvailableDevices>,
+    },
+    SetRouting(RoutingConfig),
+    SetDucking(bool),
+    Quit,
+}
+
+struct GraphState {
+    core: pw::core::Core,
+    registry: pw::registry::Registry,
+    graph: Graph,
+    routing: RoutingConfig,
+    tts_mix_input: pw::node::Node,
+    microphone_mix_input: pw::node::Node,
+    /// `node.name` of the default sink, from the `default` metadata
+    default_sink: Option<String>,
+    default_metadata: Option<(
+        pw::metadata::Metadata,
+        pw::metadata::MetadataListener,
+    )>,
+    /// Devices the TTS or the mix should be linked to whenever they
+    /// exist
+    desired_links: Vec<DesiredLink>,
+    /// Sync sent to the server to know when a burst of objects ended
+    pending_sync: Option<AsyncSeq>,
+    /// Playback streams of other apps, bound to change their volume
+    streams: HashMap<u32, Stream>,
+    /// Set while an utterance plays
+    ducking: bool,
+    /// Streams whose volume was lowered, with their channel volumes
+    /// from before
+    ducked_streams: HashMap<u32, Vec<f32>>,
+    /// Told every time a node or a port is added or removed
+    device_changes: UnboundedSender<()>,
+}
+
+/// Playback stream of another app
+struct Stream {
+    // Dropped before the node it listens to
+    _listener: pw::node::NodeListener,
+    node: pw::node::Node,
+    /// Last `channelVolumes` the stream reported, `None` until it
+    /// does. Volume controls change these rather than `volume`.
+    channel_volumes: Option<Vec<f32>>,
+}
+
+/// Value of `default.audio.sink`, like `{ "name": "alsa_output..." }`
+#[derive(serde::Deserialize)]
+struct DefaultNode {
+    name: String,
+}
+
+impl GraphState {
+    fn link_ports(
+        &mut self,
+        output_port: u32,
+        input_port: u32,
+    ) -> bool {
+        let graph = &mut self.graph;
+        let (Some(output), Some(input)) = (
+            graph.ports.get(&output_port),
+            graph.ports.get(&input_port),
+        ) else {
+            return false;
+        };
+        let link = self.core.create_object::<pw::link::Link>(
+            "link-factory",
+            &properties! {
+                "link.output.node" => output.node_id.to_string(),
+                "link.output.port" => output_port.to_string(),
+                "link.input.node" => input.node_id.to_string(),
+                "link.input.port" => input_port.to_string(),
+                "object.linger" => "false",
+            },
+        );
+        match link {
+            Ok(link) => {
+                graph
+                    .owned_links
+                    .insert((output_port, input_port), link);
+                true
+            }
+            Err(e) => {
+                log::error!(
+                    "Failed to link port {output_port} to {input_port}: {e}"
+                );
+                false
+            }
+        }
+    }
+
+    /// Link every node named `output_node` to every node named
+    /// `input_node`
+    fn link_nodes(
+        &mut self,
+        output_node: &str,
+        input_node: &str,
+        mapping: Option<&[PortMapping]>,
+    ) -> Result<Vec<LinkedPorts>, String> {
+        for name in [output_node, input_node] {
+            if !self.graph.has_node(name) {
+                return Err(format!("Device not found: {name}"));
+            }
+        }
+
+        let mut pairs = vec![];
+        for (output_id, input_id) in
+            self.graph.node_pairs(output_node, input_node)
+        {
+            pairs.extend(
+                self.graph
+                    .pair_ports(output_id, input_id, mapping)?,
+            );
+        }
+        if pairs.is_empty() {
+            return Err(format!(
+                "No ports to link between {output_node} and {input_node}"
+            ));
+        }
+
+        let mut linked = vec![];
+        for (output_port, input_port) in pairs {
+            if self.graph.is_linked(output_port, input_port)
+                || self.link_ports(output_port, input_port)
+            {
+                linked.push(
+                    self.graph.linked_ports(output_port, input_port),
+                );
+            }
+        }
+        Ok(linked)
+    }
+
+    fn unlink_nodes(
+        &mut self,
+        output_node: &str,
+        input_node: &str,
+    ) -> Result<(), String> {
+        for name in [output_node, input_node] {
+            if !self.graph.has_node(name) {
+                return Err(format!("Device not found: {name}"));
+            }
+        }
+        let nodes = self.graph.node_pairs(output_node, input_node);
+
+        let graph = &mut self.graph;
+        let port_node = |port: u32| {
+            graph.ports.get(&port).map(|port| port.node_id)
+        };
+        let links: Vec<(u32, u32, u32)> = graph
+            .links
+            .iter()
+            .filter(|(_, link)| {
+                port_node(link.output_port)
+                    .zip(port_node(link.input_port))
+                    .is_some_and(|pair| nodes.contains(&pair))
+            })
+            .map(|(id, link)| {
+                (*id, link.output_port, link.input_port)
+            })
+            .collect();
+
+        for (link_id, output_port, input_port) in links {
+            // Our own links go away with their proxy, the others are
+            // destroyed through the registry
+            if graph
+                .owned_links
+                .remove(&(output_port, input_port))
+                .is_none()
+            {
+                self.registry.destroy_global(link_id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Links we made from `output_node` to `input_node`
+    fn links_between(
+        &self,
+        output_node: u32,
+        input_node: u32,
+    ) -> Vec<LinkedPorts> {
+        let port_node = |port: &u32| {
+            self.graph.ports.get(port).map(|port| port.node_id)
+        };
+        let mut links: Vec<(u32, u32)> = self
+            .graph
+            .owned_links
+            .keys()
+            .filter(|(output, input)| {
+                port_node(output) == Some(output_node)
+                    && port_node(input) == Some(input_node)
+            })
+            .copied()
+            .collect();
+        links.sort();
+        links
+            .into_iter()
+            .map(|(output, input)| {
+                self.graph.linked_ports(output, input)
+            })
+            .collect()
+    }
+
+    fn link_states(&self) -> Vec<LinkState> {
+        self.desired_links
+            .iter()
+            .map(|link| LinkState {
+                link: link.clone(),
+                available: self.graph.has_node(&link.target_device),
+                linked: self
+                    .graph
+                    .node_pairs(
+                        &source_node_name(link.source),
+                        &link.target_device,
+                    )
+                    .into_iter()
+                    .flat_map(|(source, target)| {
+                        self.links_between(source, target)
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// Links the backend needs for the mix, by `node.name`
+    fn internal_links(&self) -> Vec<(String, String)> {
+        let mut links = vec![
+            (
+                PiperTTSManager::get_handle_name(),
+                TTS_MIX_INPUT_NAME.to_owned(),
+            ),
+            (
+                TTS_MIX_INPUT_NAME.to_owned(),
+                VIRTUAL_SOURCE_NAME.to_owned(),
+            ),
+            (
+                MICROPHONE_MIX_INPUT_NAME.to_owned(),
+                VIRTUAL_SOURCE_NAME.to_owned(),
+            ),
+        ];
+        if let Some(microphone) = &self.routing.microphone {
+            links.push((
+                microphone.clone(),
+                MICROPHONE_MIX_INPUT_NAME.to_owned(),
+            ));
+        }
+        links
+    }
+
+    /// Make the internal and desired links that are missing and drop
+    /// the links we made that are not wanted anymore
+    fn reconcile(&mut self) {
+        let mut wanted: Vec<(
+            String,
+            String,
+            Option<Vec<PortMapping>>,
+        )> = self
+            .internal_links()
+            .into_iter()
+            .map(|(output, input)| (output, input, None))
+            .collect();
+        wanted.extend(self.desired_links.iter().map(|link| {
+            (
+                source_node_name(link.source),
+                link.target_device.clone(),
+                link.port_mapping.clone(),
+            )
+        }));
+
+        for (output, input, mapping) in &wanted {
+            if !self.graph.has_node(output)
+                || !self.graph.has_node(input)
+            {
+                continue;
+            }
+            if let Err(e) =
+                self.link_nodes(output, input, mapping.as_deref())
+            {
+                log::debug!(
+                    "Failed to link {output} to {input}: {e}"
+                );
+            }
+        }
+
+        let mut valid: Vec<(u32, u32)> = wanted
+            .iter()
+            .flat_map(|(output, input, _)| {
+                self.graph.node_pairs(output, input)
+            })
+            .collect();
+        // The link made when the TTS was reattached to the default sink
+        if !self.routing.detached_from_default_sink {
+            if let Some(sink) = &self.default_sink {
+                valid.extend(self.graph.node_pairs(
+                    &PiperTTSManager::get_handle_name(),
+                    sink,
+                ));
+            }
+        }
+        let graph = &mut self.graph;
+        let stale: Vec<(u32, u32)> = graph
+            .owned_links
+            .keys()
+            .filter(|(output, input)| {
+                let output =
+                    graph.ports.get(output).map(|port| port.node_id);
+                let input =
+                    graph.ports.get(input).map(|port| port.node_id);
+                output
+                    .zip(input)
+                    .is_none_or(|nodes| !valid.contains(&nodes))
+            })
+            .copied()
+            .collect();
+        for key in stale {
+            log::debug!("Removing stale link {key:?}");
+            graph.owned_links.remove(&key);
+        }
+    }
+
+    /// Reconcile once the registry is done announcing objects, so a
+    /// node is not linked before all of its ports exist
+    fn schedule_reconcile(&mut self) {
+        if self.pending_sync.is_some() {
+            return;
+        }
+        match self.core.sync(0) {
+            Ok(seq) => self.pending_sync = Some(seq),
+            Err(e) => {
+                log::error!("Failed to sync with PipeWire: {e}");
+                self.reconcile();
+            }
+        }
+    }
+
+    fn on_done(&mut self, id: u32, seq: AsyncSeq) {
+        if id == pw::core::PW_ID_CORE
+            && self.pending_sync == Some(seq)
+        {
+            self.pending_sync = None;
+            self.reconcile();
+        }
+    }
+
+    fn add_desired_link(
+        &mut self,
+        link: DesiredLink,
+    ) -> Result<Vec<LinkedPorts>, String> {
+        let linked = self.link_nodes(
+            &source_node_name(link.source),
+            &link.target_device,
+            link.port_mapping.as_deref(),
+        )?;
+        self.desired_links.retain(|desired| {
+            desired.source != link.source
+                || desired.target_device != link.target_device
+        });
+        self.desired_links.push(link);
+        Ok(linked)
+    }
+
+    fn remove_desired_link(
+        &mut self,
+        source: LinkSource,
+        target_device: &str,
+    ) -> Result<(), String> {
+        self.desired_links.retain(|desired| {
+            desired.source != source
+                || desired.target_device != target_device
+        });
+        // Nothing to unlink from a device that is gone
+        if !self.graph.has_node(target_device) {
+            return Ok(());
+        }
+        self.unlink_nodes(&source_node_name(source), target_device)
+    }
+
+    fn is_tts_port(&self, port_id: u32) -> bool {
+        let tts =
+            self.graph.node_ids(&PiperTTSManager::get_handle_name());
+        self.graph
+            .ports
+            .get(&port_id)
+            .is_some_and(|port| tts.contains(&port.node_id))
+    }
+
+    /// Destroy a link from the TTS that the backend did not make
+    fn detach_link(&self, link_id: u32) {
+        let Some(link) = self.graph.links.get(&link_id) else {
+            return;
+        };
+        if !self.is_tts_port(link.output_port)
+            || self
+                .graph
+                .owned_links
+                .contains_key(&(link.output_port, link.input_port))
+        {
+            return;
+        }
+        log::debug!("Detaching the TTS from link {link_id}");
+        self.registry.destroy_global(link_id);
+    }
+
+    fn set_routing(&mut self, routing: RoutingConfig) {
+        if routing.microphone_gain != self.routing.microphone_gain {
+            self.set_gain(
+                MICROPHONE_MIX_INPUT_NAME,
+                routing.microphone_gain,
+            );
+        }
+        if routing.tts_gain != self.routing.tts_gain {
+            self.set_gain(TTS_MIX_INPUT_NAME, routing.tts_gain);
+        }
+        let detached = routing.detached_from_default_sink;
+        let detached_changed =
+            detached != self.routing.detached_from_default_sink;
+        let ducking_changed = routing.ducking != self.routing.ducking;
+        self.routing = routing;
+        if ducking_changed {
+            self.update_ducking();
+        }
+        self.reconcile();
+        if !detached_changed {
+            return;
+        }
+
+        if detached {
+            let links: Vec<u32> =
+                self.graph.links.keys().copied().collect();
+            for link_id in links {
+                self.detach_link(link_id);
+            }
+            return;
+        }
+
+        // The session manager does not relink a stream by itself
+        let Some(default_sink) = self.default_sink.clone() else {
+            log::warn!(
+                "No default sink known to reattach the TTS to"
+            );
+            return;
+        };
+        if let Err(e) = self.link_nodes(
+            &PiperTTSManager::get_handle_name(),
+            &default_sink,
+            None,
+        ) {
+            log::error!(
+                "Failed to reattach the TTS to {default_sink}: {e}"
+            );
+        }
+    }
+
+    fn set_ducking(&mut self, ducking: bool) {
+        if ducking != self.ducking {
+            self.ducking = ducking;
+            self.update_ducking();
+        }
+    }
+
+    /// Volume of a stream in percent, lowered while an utterance
+    /// plays
+    fn stream_level(&self, stream_id: u32) -> u8 {
+        let config = &self.routing.ducking;
+        if !self.ducking || !config.enabled {
+            return 100;
+        }
+        let application_name = self
+            .graph
+            .nodes
+            .get(&stream_id)
+            .and_then(|node| node.application_name.as_deref());
+        config.level_for(application_name)
+    }
+
+    /// Set the volume of every stream to its level
+    fn update_ducking(&mut self) {
+        let stream_ids: Vec<u32> =
+            self.streams.keys().copied().collect();
+        for stream_id in stream_ids {
+            self.duck_stream(stream_id);
+        }
+    }
+
+    /// Lower the channel volumes of a stream to its level, relative
+    /// to the ones it had before, or give those back
+    fn duck_stream(&mut self, stream_id: u32) {
+        let level = self.stream_level(stream_id);
+        let Some(stream) = self.streams.get(&stream_id) else {
+            return;
+        };
+        let original = match self.ducked_streams.get(&stream_id) {
+            Some(original) => original.clone(),
+            None if level >= 100 => return,
+            // Ducked once the stream reports its volumes
+            None => match &stream.channel_volumes {
+                Some(volumes) => volumes.clone(),
+                None => return,
+            },
+        };
+        let scale = f32::from(level.min(100)) / 100.0;
+        let volumes =
+            original.iter().map(|volume| volume * scale).collect();
+        if let Err(e) = virtual_source::set_prop(
+            &stream.node,
+            pw::spa::sys::SPA_PROP_channelVolumes,
+            Value::ValueArray(ValueArray::Float(volumes)),
+        ) {
+            log::error!(
+                "Failed to set the volume of {stream_id}: {e}"
+            );
+            return;
+        }
+        if level >= 100 {
+            self.ducked_streams.remove(&stream_id);
+        } else {
+            self.ducked_streams.insert(stream_id, original);
+        }
+    }
+
+    /// A stream reported its channel volumes. While it is ducked
+    /// those are our own lowered ones, so the ones saved before are
+    /// kept.
+    fn on_stream_volumes(
+        &mut self,
+        stream_id: u32,
+        volumes: Vec<f32>,
+    ) {
+        let Some(stream) = self.streams.get_mut(&stream_id) else {
+            return;
+        };
+        stream.channel_volumes = Some(volumes);
+        if !self.ducked_streams.contains_key(&stream_id) {
+            self.duck_stream(stream_id);
+        }
+    }
+
+    fn set_gain(&self, mix_input: &str, gain: f32) {
+        let node = if mix_input == TTS_MIX_INPUT_NAME {
+            &self.tts_mix_input
+        } else {
+            &self.microphone_mix_input
+        };
+        if let Err(e) = virtual_source::set_gain(node, gain) {
+            log::error!("Failed to set the gain of {mix_input}: {e}");
+        }
+    }
+
+    fn on_global(
+        &mut self,
+        global: &pw::registry::GlobalObject<
+            &pw::spa::utils::dict::DictRef,
+        >,
+    ) {
+        let Some(props) = global.props else {
+            return;
+        };
+        let parse_id = |key: &str| -> Option<u32> {
+            props.get(key)?.parse().ok()
+        };
+        match global.type_ {
+            ObjectType::Node => {
+                let Some(name) = props.get("node.name") else {
+                    return;
+                };
+                let prop =
+                    |key: &str| props.get(key).map(str::to_owned);
+                self.graph.nodes.insert(
+                    global.id,
+                    NodeInfo {
+                        name: name.to_owned(),
+                        description: prop("node.description")
+                            .or_else(|| prop("node.nick")),
+                        media_class: prop("media.class"),
+                        application_name: prop("application.name"),
+                    },
+                );
+            }
+            ObjectType::Port => {
+                let Some(node_id) = parse_id("node.id") else {
+                    return;
+                };
+                self.graph.ports.insert(
+                    global.id,
+                    PortInfo {
+                        node_id,
+                        name: props
+                            .get("port.name")
+                            .unwrap_or_default()
+                            .to_owned(),
+                        is_output: props.get("port.direction")
+                            == Some("out"),
+                        channel: props
+                            .get("audio.channel")
+                            .map(str::to_owned),
+                    },
+                );
+            }
+            ObjectType::Link => {
+                let (Some(output_port), Some(input_port)) = (
+                    parse_id("link.output.port"),
+                    parse_id("link.input.port"),
+                ) else {
+                    return;
+                };
+                self.graph.links.insert(
+                    global.id,
+                    LinkInfo {
+                        output_port,
+                        input_port,
+                    },
+                );
+                if self.routing.detached_from_default_sink {
+                    self.detach_link(global.id);
+                }
+                return;
+            }
+            _ => return,
+        }
+        _ = self.device_changes.send(());
+        self.schedule_reconcile();
+    }
+
+    fn on_global_remove(&mut self, id: u32) {
+        self.streams.remove(&id);
+        self.ducked_streams.remove(&id);
+        let graph = &mut self.graph;
+        if graph.nodes.remove(&id).is_some() {
+            _ = self.device_changes.send(());
+        }
+        if graph.ports.remove(&id).is_some() {
+            graph.owned_links.retain(|(output, input), _| {
+                *output != id && *input != id
+            });
+            _ = self.device_changes.send(());
+        }
+        if let Some(link) = graph.links.remove(&id) {
+            // One of our links was destroyed by someone else, make
+            // it again
+            let key = (link.output_port, link.input_port);
+            if graph.owned_links.remove(&key).is_some() {
+                self.schedule_reconcile();
+            }
+        }
+    }
+
+    fn on_command(
+        &mut self,
+        command: Command,
+        mainloop: &pw::main_loop::MainLoop,
+    ) {
+        match command {
+            Command::AddDesiredLink { link, reply } => {
+                _ = reply.send(self.add_desired_link(link));
+            }
+            Command::RemoveDesiredLink {
+                source,
+                target_device,
+                reply,
+            } => {
+                _ = reply.send(
+                    self.remove_desired_link(source, &target_device),
+                );
+            }
+            Command::SetDesiredLinks { links, reply } => {
+                self.desired_links = links;
+                self.reconcile();
+                _ = reply.send(self.link_states());
+            }
+            Command::Links { reply } => {
+                _ = reply.send(self.link_states());
+            }
+            Command::Devices { reply } => {
+                _ = reply.send(self.graph.devices());
+            }
+            Command::SetRouting(routing) => self.set_routing(routing),
+            Command::SetDucking(ducking) => self.set_ducking(ducking),
+            Command::Quit => {
+                // Don't leave the other apps lowered
+                self.set_ducking(false);
+                mainloop.quit()
+            }
+        }
+    }
+}
+
+/// Keep a playback stream of another app, to duck it. Its channel
+/// volumes are followed, so ducking can give them back.
+fn bind_stream(
+    state: &Rc<RefCell<GraphState>>,
+    global: &pw::registry::GlobalObject<
+        &pw::spa::utils::dict::DictRef,
+    >,
+) {
+    let is_stream =
+        state.borrow().graph.nodes.get(&global.id).is_some_and(
+            |node| {
+                node.media_class.as_deref()
+                    == Some("Stream/Output/Audio")
+                    && node.name != PiperTTSManager::get_handle_name()
+            },
+        );
+    if !is_stream {
+        return;
+    }
+    let node: pw::node::Node = match state
+        .borrow()
+        .registry
+        .bind(global)
+    {
+        Ok(node) => node,
+        Err(e) => {
+            log::error!("Failed to bind stream {}: {e}", global.id);
+            return;
+        }
+    };
+    let stream_id = global.id;
+    let weak_state: Weak<RefCell<GraphState>> = Rc::downgrade(state);
+    let listener = node
+        .add_listener_local()
+        .param(move |_seq, id, _index, _next, param| {
+            if id != ParamType::Props {
+                return;
+            }
+            let volumes = param.and_then(|param| {
+                virtual_source::float_array_prop(
+                    param,
+                    pw::spa::sys::SPA_PROP_channelVolumes,
+                )
+            });
+            if let (Some(volumes), Some(state)) =
+                (volumes, weak_state.upgrade())
+            {
+                state
+                    .borrow_mut()
+                    .on_stream_volumes(stream_id, volumes);
+            }
+        })
+        .register();
+    node.subscribe_params(&[ParamType::Props]);
+    state.borrow_mut().streams.insert(
+        stream_id,
+        Stream {
+            _listener: listener,
+            node,
+            channel_volumes: None,
+        },
+    );
+}
+
+/// Follow the default sink through the `default` metadata object
+fn bind_default_metadata(
+    state: &Rc<RefCell<GraphState>>,
+    global: &pw::registry::GlobalObject<
+        &pw::spa::utils::dict::DictRef,
+    >,
+) {
+    let is_default = global.type_ == ObjectType::Metadata
+        && global.props.is_some_and(|props| {
+            props.get("metadata.name") == Some("default")
+        });
+    if !is_default {
+        return;
+    }
+    let metadata: pw::metadata::Metadata =
+        match state.borrow().registry.bind(global) {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                log::error!(
+                    "Failed to bind the default metadata: {e}"
+                );
+                return;
+            }
+        };
+    let weak_state: Weak<RefCell<GraphState>> = Rc::downgrade(state);
+    let listener = metadata
+        .add_listener_local()
+        .property(move |_subject, key, _type, value| {
+            if key != Some("default.audio.sink") {
+                return 0;
+            }
+            if let Some(state) = weak_state.upgrade() {
+                state.borrow_mut().default_sink = value
+                    .and_then(|value| {
+                        serde_json::from_str::<DefaultNode>(value)
+                            .ok()
+                    })
+                    .map(|node| node.name);
+            }
+            0
+        })
+        .register();
+    state.borrow_mut().default_metadata = Some((metadata, listener));
+}
+
+/// Run the PipeWire graph until [`Command::Quit`] arrives
+fn run(
+    commands: pw::channel::Receiver<Command>,
+    device_changes: UnboundedSender<()>,
+) -> Result<(), pw::Error> {
+    pw::init();
+    let mainloop = pw::main_loop::MainLoop::new(None)?;
+    let context = pw::context::Context::new(&mainloop)?;
+    let core = context.connect(None)?;
+    let registry = core.get_registry()?;
+
+    let _virtual_source = virtual_source::create(&core)?;
+    let tts_mix_input = virtual_source::create_mix_input(
+        &core,
+        TTS_MIX_INPUT_NAME,
+        "VoiceLift Mix (TTS)",
+    )?;
+    let microphone_mix_input = virtual_source::create_mix_input(
+        &core,
+        MICROPHONE_MIX_INPUT_NAME,
+        "VoiceLift Mix (Microphone)",
+    )?;
+
+    let state = Rc::new(RefCell::new(GraphState {
+        core: core.clone(),
+        registry: core.get_registry()?,
+        graph: Graph::default(),
+        routing: RoutingConfig::default(),
+        tts_mix_input,
+        microphone_mix_input,
+        default_sink: None,
+        default_metadata: None,
+        desired_links: vec![],
+        pending_sync: None,
+        streams: HashMap::new(),
+        ducking: false,
+        ducked_streams: HashMap::new(),
+        device_changes,
+    }));
+
+    let done_state = state.clone();
+    let _core_listener = core
+        .add_listener_local()
+        .done(move |id, seq| done_state.borrow_mut().on_done(id, seq))
+        .register();
+
+    let global_state = state.clone();
+    let removed_state = state.clone();
+    let _listener = registry
+        .add_listener_local()
+        .global(move |global| {
+            global_state.borrow_mut().on_global(global);
+            bind_stream(&global_state, global);
+            bind_default_metadata(&global_state, global);
+        })
+        .global_remove(move |id| {
+            removed_state.borrow_mut().on_global_remove(id)
+        })
+        .register();
+
+    let command_mainloop = mainloop.clone();
+    let _commands =
+        commands.attach(mainloop.loop_(), move |command| {
+            state.borrow_mut().on_command(command, &command_mainloop);
+        });
+
+    mainloop.run();
+    Ok(())
+}
+
+/// Thread that owns our PipeWire objects: the virtual source and
+/// every link the backend creates
+pub struct PwGraph {
+    commands: Mutex<pw::channel::Sender<Command>>,
+    thread: Mutex<Option<JoinHandle<()>>>,
+    /// Where the desired links are persisted, `None` to keep them in
+    /// memory only
+    store: Option<LinkStore>,
+}
+
+impl PwGraph {
+    /// Start the graph with the desired links saved in `store`.
+    /// `device_changes` is told when a node or a port is added or
+    /// removed.
+    pub fn spawn(
+        store: Option<LinkStore>,
+        device_changes: UnboundedSender<()>,
+    ) -> Self {
+        let (commands, receiver) = pw::channel::channel();
+        let thread = thread::Builder::new()
+            .name("pipewire-graph".to_owned())
+            .spawn(move || {
+                if let Err(e) = run(receiver, device_changes) {
+                    log::error!("PipeWire graph stopped: {e}");
+                }
+            })
+            .expect("Failed to spawn the PipeWire graph thread");
+        let graph = Self {
+            commands: Mutex::new(commands),
+            thread: Mutex::new(Some(thread)),
+            store,
+        };
+        if let Some(store) = &graph.store {
+            let links = store.load();
+            log::info!("Restoring {} desired links", links.len());
+            if let Err(e) = graph.send(Command::SetDesiredLinks {
+                links,
+                reply: mpsc::channel().0,
+            }) {
+                log::error!("{e}");
+            }
+        }
+        graph
+    }
+
+    fn send(&self, command: Command) -> Result<(), String> {
+        self.commands
+            .lock()
+            .map_err(|_| "Failed to lock the PipeWire graph")?
+            .send(command)
+            .map_err(|_| "The PipeWire graph stopped".to_owned())
+    }
+
+    /// Send a command and wait for its reply
+    fn request<T>(
+        &self,
+        command: impl FnOnce(mpsc::Sender<T>) -> Command,
+    ) -> Result<T, String> {
+        let (reply, result) = mpsc::channel();
+        self.send(command(reply))?;
+        result.recv_timeout(REPLY_TIMEOUT).map_err(|e| {
+            format!("PipeWire graph did not answer: {e}")
+        })
+    }
+
+    /// Save the desired links, so they survive a restart
+    fn persist(&self) {
+        let Some(store) = &self.store else {
+            return;
+        };
+        let result = self.links().and_then(|states| {
+            let links: Vec<DesiredLink> =
+                states.into_iter().map(|state| state.link).collect();
+            store.save(&links)
+        });
+        if let Err(e) = result {
+            log::error!("Failed to save the desired links: {e}");
+        }
+    }
+
+    /// Link the TTS to a device now and every time it reappears.
+    /// Returns the port pairs that are linked.
+    pub fn add_desired_link(
+        &self,
+        link: DesiredLink,
+    ) -> Result<Vec<LinkedPorts>, String> {
+        let linked = self.request(|reply| {
+            Command::AddDesiredLink { link, reply }
+        })??;
+        self.persist();
+        Ok(linked)
+    }
+
+    /// Unlink the TTS or the mix from a device and forget about it
+    pub fn remove_desired_link(
+        &self,
+        source: LinkSource,
+        target_device: String,
+    ) -> Result<(), String> {
+        let result =
+            self.request(|reply| Command::RemoveDesiredLink {
+                source,
+                target_device,
+                reply,
+            })?;
+        self.persist();
+        result
+    }
+
+    /// Replace every desired link
+    pub fn set_desired_links(
+        &self,
+        links: Vec<DesiredLink>,
+    ) -> Result<Vec<LinkState>, String> {
+        let states = self.request(|reply| {
+            Command::SetDesiredLinks { links, reply }
+        })?;
+        self.persist();
+        Ok(states)
+    }
+
+    /// The desired links and what is linked for each of them
+    pub fn links(&self) -> Result<Vec<LinkState>, String> {
+        self.request(|reply| Command::Links { reply })
+    }
+
+    /// Every node with ports, by direction
+    pub fn devices(&self) -> Result<AvailableDevices, String> {
+        self.request(|reply| Command::Devices { reply })
+    }
+
+    /// Apply the routing options of the config, like the microphone
+    /// mixed into the virtual source
+    pub fn set_routing(
+        &self,
+        routing: RoutingConfig,
+    ) -> Result<(), String> {
+        self.send(Command::SetRouting(routing))
+    }
+
+    /// Lower the volume of the other apps, or restore it
+    pub fn set_ducking(&self, ducking: bool) -> Result<(), String> {
+        self.send(Command::SetDucking(ducking))
+    }
+
+    /// Remove our objects from the graph and stop the thread
+    pub fn shutdown(&self) {
+        if self.send(Command::Quit).is_err() {
+            return;
+        }
+        let thread = match self.thread.lock() {
+            Ok(mut thread) => thread.take(),
+            Err(_) => None,
+        };
+        if let Some(thread) = thread {
+            if thread.join().is_err() {
+                log::error!("PipeWire graph thread panicked");
+            }
+        }
+        log::info!("Removed the PipeWire objects of the backend");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Graph with a node per entry, with its output and input ports
+    /// in order. Port IDs start at node ID * 10.
+    fn graph(nodes: &[(u32, &[&str], &[&str])]) -> Graph {
+        let mut graph = Graph::default();
+        for (node_id, outputs, inputs) in nodes {
+            graph.nodes.insert(
+                *node_id,
+                NodeInfo {
+                    name: format!("node{node_id}"),
+                    description: None,
+                    media_class: None,
+                    application_name: None,
+                },
+            );
+            let ports = outputs
+                .iter()
+                .map(|channel| (true, channel))
+                .chain(inputs.iter().map(|channel| (false, channel)));
+            for (index, (is_output, channel)) in ports.enumerate() {
+                let direction =
+                    if is_output { "output" } else { "input" };
+                graph.ports.insert(
+                    node_id * 10 + index as u32,
+                    PortInfo {
+                        node_id: *node_id,
+                        name: format!("{direction}_{channel}"),
+                        is_output,
+                        channel: Some(channel.to_string()),
+                    },
+                );
+            }
+        }
+        graph
+    }
+
+    fn mapping(output: &str, input: &str) -> PortMapping {
+        PortMapping {
+            output_channel: output.to_owned(),
+            input_channel: input.to_owned(),
+        }
+    }
+
+    #[test]
+    fn mono_goes_to_every_channel() {
+        let graph =
+            graph(&[(1, &["MONO"], &[]), (2, &[], &["FL", "FR"])]);
+        assert_eq!(
+            graph.pair_ports(1, 2, None),
+            Ok(vec![(10, 20), (10, 21)])
+        );
+    }
+
+    #[test]
+    fn every_channel_goes_to_mono() {
+        let graph =
+            graph(&[(1, &["FL", "FR"], &[]), (2, &[], &["MONO"])]);
+        assert_eq!(
+            graph.pair_ports(1, 2, None),
+            Ok(vec![(10, 20), (11, 20)])
+        );
+    }
+
+    #[test]
+    fn pairs_by_channel() {
+        let graph = graph(&[
+            (1, &["FL", "FR"], &[]),
+            (2, &[], &["FR", "FL"]),
+        ]);
+        assert_eq!(
+            graph.pair_ports(1, 2, None),
+            Ok(vec![(10, 21), (11, 20)])
+        );
+    }
+
+    #[test]
+    fn stereo_to_surround_only_links_matching_channels() {
+        let graph = graph(&[
+            (1, &["FL", "FR"], &[]),
+            (2, &[], &["FL", "FR", "RL", "RR"]),
+        ]);
+        assert_eq!(
+            graph.pair_ports(1, 2, None),
+            Ok(vec![(10, 20), (11, 21)])
+        );
+    }
+
+    #[test]
+    fn falls_back_to_order_without_matching_channels() {
+        let graph = graph(&[
+            (1, &["AUX0", "AUX1", "AUX2"], &[]),
+            (2, &[], &["FL", "FR"]),
+        ]);
+        assert_eq!(
+            graph.pair_ports(1, 2, None),
+            Ok(vec![(10, 20), (11, 21)])
+        );
+    }
+
+    #[test]
+    fn follows_the_mapping() {
+        let graph = graph(&[
+            (1, &["FL", "FR"], &[]),
+            (2, &[], &["FL", "FR"]),
+        ]);
+        let mapping =
+            [mapping("FL", "FR"), mapping("output_FR", "FL")];
+        assert_eq!(
+            graph.pair_ports(1, 2, Some(&mapping)),
+            Ok(vec![(10, 21), (11, 20)])
+        );
+    }
+
+    #[test]
+    fn mapping_to_a_missing_port_fails() {
+        let graph = graph(&[
+            (1, &["FL", "FR"], &[]),
+            (2, &[], &["FL", "FR"]),
+        ]);
+        let mapping = [mapping("FL", "RL")];
+        assert_eq!(
+            graph.pair_ports(1, 2, Some(&mapping)),
+            Err("Input port not found: RL".to_owned())
+        );
+    }
+
+    #[test]
+    fn pairs_every_node_sharing_a_name() {
+        let mut graph = graph(&[
+            (1, &["FL"], &[]),
+            (2, &[], &["FL"]),
+            (3, &[], &["FL"]),
+        ]);
+        graph.nodes.get_mut(&3).unwrap().name = "node2".to_owned();
+        assert_eq!(graph.node_ids("node2"), vec![2, 3]);
+        assert_eq!(
+            graph.node_pairs("node1", "node2"),
+            vec![(1, 2), (1, 3)]
+        );
+        assert!(graph.node_pairs("node1", "missing").is_empty());
+    }
+
+    #[test]
+    fn nothing_to_pair_without_ports() {
+        let graph =
+            graph(&[(1, &[], &["FL"]), (2, &[], &["FL", "FR"])]);
+        assert_eq!(graph.pair_ports(1, 2, None), Ok(Vec::new()));
+    }
+}
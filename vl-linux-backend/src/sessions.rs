@@ -0,0 +1,107 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use busrt::rpc::{Rpc, RpcClient};
+use busrt::QoS;
+use tokio::time::{sleep, timeout};
+use vl_global::auth::constant_time_eq;
+use vl_linux_backend::events::server::{
+    RequestSession, ResponseSession, METHOD_SESSION,
+};
+
+use crate::event_handlers::handler::ConnectedClients;
+
+/// How often the authenticated clients are checked
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+/// A client that takes longer to answer loses its session
+const SESSION_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Whether `name` is still the client that authenticated and got
+/// `secret`. Scopes are kept by client name, and another process can
+/// register the name once the client disconnected, but it can't know
+/// the secret.
+pub async fn verify(
+    rpc: &RpcClient,
+    name: &str,
+    secret: &str,
+) -> bool {
+    let Ok(payload) = rmp_serde::to_vec_named(&RequestSession {})
+    else {
+        return false;
+    };
+    let call = rpc.call(
+        name,
+        METHOD_SESSION,
+        payload.into(),
+        QoS::Processed,
+    );
+    let reply = match timeout(SESSION_TIMEOUT, call).await {
+        Ok(Ok(reply)) => reply,
+        Ok(Err(e)) => {
+            log::debug!("{name} didn't answer {METHOD_SESSION}: {e}");
+            return false;
+        }
+        Err(_) => {
+            log::warn!("{name} timed out answering {METHOD_SESSION}");
+            return false;
+        }
+    };
+    match rmp_serde::from_slice::<ResponseSession>(reply.payload()) {
+        Ok(ResponseSession {
+            secret: Some(answer),
+        }) => constant_time_eq(&answer, secret),
+        Ok(ResponseSession { secret: None }) => false,
+        Err(e) => {
+            log::warn!(
+                "Invalid {METHOD_SESSION} reply from {name}: {e}"
+            );
+            false
+        }
+    }
+}
+
+/// Drop the session of `name`, unless it authenticated again and
+/// holds another `secret` by now
+pub fn forget(clients: &ConnectedClients, name: &str, secret: &str) {
+    match clients.write() {
+        Ok(mut clients) => {
+            if clients
+                .get(name)
+                .is_some_and(|session| session.secret == secret)
+            {
+                clients.remove(name);
+            }
+        }
+        Err(e) => log::error!("Failed to lock clients: {e}"),
+    }
+}
+
+/// Forget the sessions of the clients that disconnected, so they
+/// don't pile up between calls
+pub async fn sweep_clients(
+    rpc: Arc<RpcClient>,
+    clients: ConnectedClients,
+) {
+    loop {
+        sleep(SWEEP_INTERVAL).await;
+        let sessions: Vec<(String, String)> = match clients.read() {
+            Ok(clients) => clients
+                .iter()
+                .map(|(name, session)| {
+                    (name.clone(), session.secret.clone())
+                })
+                .collect(),
+            Err(e) => {
+                log::error!("Failed to lock clients: {e}");
+                continue;
+            }
+        };
+        for (name, secret) in sessions {
+            if verify(&rpc, &name, &secret).await {
+                continue;
+            }
+            log::info!("{name} disconnected, dropping its session");
+            forget(&clients, &name, &secret);
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use busrt::broker::Client;
+use busrt::client::AsyncClient;
+use busrt::QoS;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use vl_linux_backend::events::server::{
+    UtteranceEvent, TOPIC_UTTERANCE_CANCELLED, TOPIC_UTTERANCE_ERROR,
+    TOPIC_UTTERANCE_FINISHED, TOPIC_UTTERANCE_STARTED,
+};
+
+/// An event and the time it was emitted at, in milliseconds since
+/// the UNIX epoch
+pub type TimedEvent = (SpeechEvent, u64);
+
+static SPEECH_EVENTS: OnceLock<UnboundedSender<TimedEvent>> =
+    OnceLock::new();
+
+#[derive(Debug)]
+pub enum SpeechEvent {
+    Started(u64),
+    Finished(u64),
+    Cancelled(u64),
+    Error(u64),
+}
+
+impl SpeechEvent {
+    fn topic(&self) -> &'static str {
+        match self {
+            SpeechEvent::Started(_) => TOPIC_UTTERANCE_STARTED,
+            SpeechEvent::Finished(_) => TOPIC_UTTERANCE_FINISHED,
+            SpeechEvent::Cancelled(_) => TOPIC_UTTERANCE_CANCELLED,
+            SpeechEvent::Error(_) => TOPIC_UTTERANCE_ERROR,
+        }
+    }
+
+    fn into_payload(self, timestamp: u64) -> UtteranceEvent {
+        let utterance_id = match self {
+            SpeechEvent::Started(id)
+            | SpeechEvent::Finished(id)
+            | SpeechEvent::Cancelled(id)
+            | SpeechEvent::Error(id) => id,
+        };
+        UtteranceEvent {
+            utterance_id,
+            timestamp,
+        }
+    }
+}
+
+/// Queue an event to be published. Can be called from any thread,
+/// events are dropped if the publisher was not started.
+pub fn emit(event: SpeechEvent) {
+    if let Some(sender) = SPEECH_EVENTS.get() {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis() as u64)
+            .unwrap_or_default();
+        if let Err(e) = sender.send((event, timestamp)) {
+            log::error!("Failed to emit speech event: {e}");
+        }
+    }
+}
+
+pub fn init() -> UnboundedReceiver<TimedEvent> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    _ = SPEECH_EVENTS.set(sender);
+    receiver
+}
+
+/// Publish every emitted event on its busrt topic
+pub async fn publish_events(
+    mut client: Client,
+    mut receiver: UnboundedReceiver<TimedEvent>,
+) {
+    while let Some((event, timestamp)) = receiver.recv().await {
+        let topic = event.topic();
+        let payload = match rmp_serde::to_vec_named(
+            &event.into_payload(timestamp),
+        ) {
+            Ok(payload) => payload,
+            Err(e) => {
+                log::error!("Failed to serialize speech event: {e}");
+                continue;
+            }
+        };
+        if let Err(e) =
+            client.publish(topic, payload.into(), QoS::No).await
+        {
+            log::error!("Failed to publish {topic}: {e}");
+        }
+    }
+}
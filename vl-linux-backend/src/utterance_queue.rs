@@ -0,0 +1,684 @@
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+use rodio::buffer::SamplesBuffer;
+use rodio::Source;
+
+use vl_global::vl_config::LinuxConfig;
+use vl_linux_backend::events::client::{
+    Priority, QueueMode, SpeechParams, SpeechQueueState,
+    UtteranceInfo, DEFAULT_VOLUME,
+};
+
+use crate::loudness::LoudnessNormalizer;
+use crate::phrase_cache;
+use crate::speech_events::{self, SpeechEvent};
+use crate::voice_loader;
+use crate::{PIPERTTS_MANAGER, PW_GRAPH};
+
+/// How often the worker checks if the sink finished playing
+const PLAYBACK_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How emergency utterances are spoken, from the config
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EmergencyPolicy {
+    pub volume_floor: u8,
+    /// Times the utterance is spoken again after the first
+    pub repeat: u8,
+}
+
+impl EmergencyPolicy {
+    pub fn from_linux(linux: &LinuxConfig) -> Self {
+        Self {
+            volume_floor: linux.emergency_volume_floor,
+            repeat: linux.emergency_repeat,
+        }
+    }
+}
+
+/// How the current utterance ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Ending {
+    Played,
+    Cancelled,
+    /// Preempted and back in the queue
+    Requeued,
+}
+
+#[derive(Default)]
+struct QueueState {
+    next_id: u64,
+    current: Option<UtteranceInfo>,
+    /// Set when the current utterance is cancelled while it is
+    /// still being synthesized, so it never reaches the sink.
+    current_cancelled: bool,
+    /// Set when the current utterance was preempted by an emergency
+    /// and queued again, so it is spoken once the emergency is over
+    current_requeued: bool,
+    /// Sorted by priority, in arrival order within a priority
+    pending: VecDeque<UtteranceInfo>,
+    emergency: EmergencyPolicy,
+    /// Loudness of the played audio in LUFS, not normalized if `None`
+    loudness_target: Option<i8>,
+}
+
+/// Playback queue owned by the backend. RPC handlers only push
+/// utterances into it, a dedicated worker thread synthesizes and
+/// plays them one after another.
+#[derive(Default)]
+pub struct UtteranceQueue {
+    state: Mutex<QueueState>,
+    condvar: Condvar,
+}
+
+impl UtteranceQueue {
+    fn lock(&self) -> MutexGuard<'_, QueueState> {
+        // A poisoned queue is still consistent, every mutation is
+        // done in a single step.
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Queue a new utterance following `mode` and its priority. The
+    /// ID of `utterance` is replaced by a new one, which is returned,
+    /// or `None` if the utterance was dropped.
+    pub fn push(
+        &self,
+        mut utterance: UtteranceInfo,
+        mode: QueueMode,
+    ) -> Option<u64> {
+        let mut state = self.lock();
+        let is_busy =
+            state.current.is_some() || !state.pending.is_empty();
+
+        let is_emergency = utterance.priority == Priority::Emergency;
+
+        let mut interrupt = false;
+        match mode {
+            // Emergencies are never dropped
+            QueueMode::DropIfBusy if is_busy && !is_emergency => {
+                return None
+            }
+            QueueMode::Interrupt => {
+                cancel_pending(&mut state.pending);
+                if state.current.is_some() {
+                    state.current_cancelled = true;
+                    interrupt = true;
+                }
+            }
+            _ => {}
+        }
+
+        if is_emergency {
+            let preempted = state
+                .current
+                .as_ref()
+                .filter(|current| {
+                    current.priority != Priority::Emergency
+                })
+                .cloned();
+            if let Some(preempted) = preempted {
+                // Preempting only delays it, unless it was already
+                // cancelled or interrupted above
+                if !state.current_cancelled {
+                    insert_first(&mut state.pending, preempted);
+                    state.current_requeued = true;
+                }
+                state.current_cancelled = true;
+                interrupt = true;
+            }
+            utterance.params.volume = utterance
+                .params
+                .volume
+                .max(state.emergency.volume_floor);
+        }
+
+        state.next_id += 1;
+        let id = state.next_id;
+        utterance.id = id;
+        insert_last(&mut state.pending, utterance);
+        drop(state);
+        self.condvar.notify_one();
+
+        if interrupt {
+            stop_playback();
+        }
+        Some(id)
+    }
+
+    /// Cancel an utterance by its ID. Returns `false` if no
+    /// utterance with this ID is playing or queued.
+    pub fn cancel(&self, utterance_id: u64) -> bool {
+        let mut state = self.lock();
+        if let Some(index) = state
+            .pending
+            .iter()
+            .position(|utterance| utterance.id == utterance_id)
+        {
+            state.pending.remove(index);
+            speech_events::emit(SpeechEvent::Cancelled(utterance_id));
+            return true;
+        }
+
+        let is_current = state
+            .current
+            .as_ref()
+            .is_some_and(|utterance| utterance.id == utterance_id);
+        if !is_current {
+            return false;
+        }
+        state.current_cancelled = true;
+        drop(state);
+
+        stop_playback();
+        true
+    }
+
+    /// Drop every queued utterance and stop the current one
+    pub fn clear(&self) {
+        let mut state = self.lock();
+        cancel_pending(&mut state.pending);
+        if state.current.is_some() {
+            state.current_cancelled = true;
+        }
+        drop(state);
+
+        stop_playback();
+    }
+
+    /// Pause the current utterance, the queue waits behind it
+    pub fn pause(&self) -> Result<(), String> {
+        if self.lock().current.is_none() {
+            return Err("Nothing is being spoken".to_owned());
+        }
+        PIPERTTS_MANAGER
+            .get()
+            .ok_or("PIPERTTS_MANAGER not set")?
+            .read()
+            .map_err(|_| "Failed to lock PIPERTTS_MANAGER")?
+            .pause_speak();
+        set_ducking(false);
+        Ok(())
+    }
+
+    /// Resume the current utterance where it was paused
+    pub fn resume(&self) -> Result<(), String> {
+        let manager = PIPERTTS_MANAGER
+            .get()
+            .ok_or("PIPERTTS_MANAGER not set")?
+            .read()
+            .map_err(|_| "Failed to lock PIPERTTS_MANAGER")?;
+        if !manager.is_paused() {
+            return Err("Speech is not paused".to_owned());
+        }
+        manager.resume_speak();
+        if self.lock().current.is_some() {
+            set_ducking(true);
+        }
+        Ok(())
+    }
+
+    pub fn set_emergency_policy(&self, policy: EmergencyPolicy) {
+        self.lock().emergency = policy;
+    }
+
+    pub fn set_loudness_target(&self, target_lufs: Option<i8>) {
+        self.lock().loudness_target = target_lufs;
+    }
+
+    /// Times `utterance` is spoken in a row
+    fn repetitions(&self, utterance: &UtteranceInfo) -> u32 {
+        match utterance.priority {
+            Priority::Emergency => {
+                1 + u32::from(self.lock().emergency.repeat)
+            }
+            Priority::Normal | Priority::High => 1,
+        }
+    }
+
+    pub fn snapshot(&self) -> SpeechQueueState {
+        let state = self.lock();
+        SpeechQueueState {
+            current: state.current.clone(),
+            pending: state.pending.iter().cloned().collect(),
+        }
+    }
+
+    /// Block until there's an utterance to play and mark it as the
+    /// current one.
+    fn wait_next(&self) -> UtteranceInfo {
+        let mut state = self.lock();
+        loop {
+            if let Some(utterance) = state.pending.pop_front() {
+                state.current = Some(utterance.clone());
+                state.current_cancelled = false;
+                return utterance;
+            }
+            state = self
+                .condvar
+                .wait(state)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// Run `callback` only if the current utterance was not
+    /// cancelled. The queue stays locked while it runs, so a cancel
+    /// can't slip in between the check and the callback.
+    fn run_if_not_cancelled<F: FnOnce()>(&self, callback: F) -> bool {
+        let state = self.lock();
+        if state.current_cancelled {
+            return false;
+        }
+        callback();
+        true
+    }
+
+    fn is_current_cancelled(&self) -> bool {
+        self.lock().current_cancelled
+    }
+
+    fn has_pending(&self) -> bool {
+        !self.lock().pending.is_empty()
+    }
+
+    /// Clear the current utterance and tell how it ended
+    fn finish_current(&self) -> Ending {
+        let mut state = self.lock();
+        state.current = None;
+        let cancelled = std::mem::take(&mut state.current_cancelled);
+        if std::mem::take(&mut state.current_requeued) {
+            Ending::Requeued
+        } else if cancelled {
+            Ending::Cancelled
+        } else {
+            Ending::Played
+        }
+    }
+}
+
+/// Queue behind the utterances with the same or a higher priority
+fn insert_last(
+    pending: &mut VecDeque<UtteranceInfo>,
+    utterance: UtteranceInfo,
+) {
+    let index = pending
+        .iter()
+        .position(|pending| pending.priority < utterance.priority)
+        .unwrap_or(pending.len());
+    pending.insert(index, utterance);
+}
+
+/// Queue ahead of the utterances with the same priority
+fn insert_first(
+    pending: &mut VecDeque<UtteranceInfo>,
+    utterance: UtteranceInfo,
+) {
+    let index = pending
+        .iter()
+        .position(|pending| pending.priority <= utterance.priority)
+        .unwrap_or(pending.len());
+    pending.insert(index, utterance);
+}
+
+fn cancel_pending(pending: &mut VecDeque<UtteranceInfo>) {
+    for utterance in pending.drain(..) {
+        speech_events::emit(SpeechEvent::Cancelled(utterance.id));
+    }
+}
+
+fn stop_playback() {
+    let manager = match PIPERTTS_MANAGER.get().map(|m| m.read()) {
+        Some(Ok(manager)) => manager,
+        _ => {
+            log::error!("Failed to lock PIPERTTS_MANAGER");
+            return;
+        }
+    };
+    if let Err(e) = manager.stop_speak() {
+        log::error!("Failed to stop speaking: {e}");
+    }
+}
+
+/// Lower the other apps while the voice plays
+fn set_ducking(ducking: bool) {
+    if let Some(graph) = PW_GRAPH.get() {
+        if let Err(e) = graph.set_ducking(ducking) {
+            log::error!("Failed to set ducking: {e}");
+        }
+    }
+}
+
+/// Synthesize and play an utterance. `Started` is only sent when
+/// `first` is set, so repetitions are not announced again.
+fn play_utterance(
+    queue: &UtteranceQueue,
+    utterance: &UtteranceInfo,
+    first: bool,
+) -> Result<(), String> {
+    // Normalized voices are synthesized at the default volume, and
+    // the volume is applied over the target afterwards
+    let loudness_target = queue.lock().loudness_target;
+    let params = match loudness_target {
+        Some(_) => SpeechParams {
+            volume: DEFAULT_VOLUME,
+            ..utterance.params.clone()
+        },
+        None => utterance.params.clone(),
+    };
+    let cache_key =
+        phrase_cache::key_for(&utterance.phrase, &params)?;
+    let cached =
+        cache_key.as_deref().and_then(phrase_cache::get_buffer);
+    let is_cached = cached.is_some();
+
+    let mut sentences: Box<
+        dyn Iterator<Item = Result<SamplesBuffer<f32>, String>>,
+    > = match cached {
+        // A hit skips the synthesis, and the voice loading with it
+        Some(buf) => Box::new(std::iter::once(Ok(buf))),
+        None => {
+            let voice_id = params.voice_id();
+            let sentences = voice_loader::engine_for(voice_id)?
+                .synthesize(voice_id, &utterance.phrase, &params)
+                .map_err(|e| format!("{e}"))?;
+            Box::new(sentences.map(|sentence| {
+                let (sample_rate, samples) =
+                    sentence.map_err(|e| format!("{e}"))?;
+                Ok(SamplesBuffer::new(1, sample_rate, samples))
+            }))
+        }
+    };
+
+    // Each sentence goes to the sink as soon as it's ready, while the
+    // previous ones play. A cancel stops before the next sentence is
+    // synthesized.
+    let mut started = false;
+    let mut normalizer = loudness_target.map(|target| {
+        LoudnessNormalizer::new(
+            f64::from(target),
+            f32::from(utterance.params.volume)
+                / f32::from(DEFAULT_VOLUME),
+        )
+    });
+    let mut sample_rate = 0;
+    let mut recorded: Vec<f32> = Vec::new();
+    loop {
+        if queue.is_current_cancelled() {
+            return Ok(());
+        }
+        let buf: SamplesBuffer<f32> = match sentences.next() {
+            Some(buf) => buf?,
+            None => break,
+        };
+        if !is_cached {
+            sample_rate = buf.sample_rate();
+            recorded.extend(buf.clone());
+        }
+        // The cache keeps the audio as synthesized, so changing the
+        // target also applies to cached phrases
+        let buf = match &mut normalizer {
+            Some(normalizer) => normalizer.process(buf),
+            None => buf,
+        };
+
+        let manager = PIPERTTS_MANAGER
+            .get()
+            .ok_or("PIPERTTS_MANAGER not set")?
+            .read()
+            .map_err(|_| "Failed to lock PIPERTTS_MANAGER")?;
+        let appended = queue.run_if_not_cancelled(|| {
+            if !started {
+                log::info!("Playing audio... {}", utterance.phrase);
+                if first {
+                    speech_events::emit(SpeechEvent::Started(
+                        utterance.id,
+                    ));
+                }
+                if !manager.is_paused() {
+                    set_ducking(true);
+                }
+                started = true;
+            }
+            manager.play(buf);
+        });
+        if !appended {
+            return Ok(());
+        }
+    }
+    // Only complete utterances are cached
+    if let Some(key) = cache_key.filter(|_| !is_cached) {
+        phrase_cache::insert(&key, sample_rate, &recorded);
+    }
+    if !started {
+        return Ok(());
+    }
+
+    // Wait without holding the manager lock, so stop requests can
+    // go through while the audio plays
+    loop {
+        thread::sleep(PLAYBACK_POLL_INTERVAL);
+        let manager = PIPERTTS_MANAGER
+            .get()
+            .ok_or("PIPERTTS_MANAGER not set")?
+            .read()
+            .map_err(|_| "Failed to lock PIPERTTS_MANAGER")?;
+        if !manager.is_playing() {
+            return Ok(());
+        }
+    }
+}
+
+/// Spawn the thread that plays the queued utterances
+pub fn spawn_worker(queue: &'static UtteranceQueue) {
+    thread::Builder::new()
+        .name("utterance-queue".to_owned())
+        .spawn(move || loop {
+            let utterance = queue.wait_next();
+            let mut result = Ok(());
+            for repetition in 0..queue.repetitions(&utterance) {
+                result = play_utterance(
+                    queue,
+                    &utterance,
+                    repetition == 0,
+                );
+                if result.is_err() || queue.is_current_cancelled() {
+                    break;
+                }
+            }
+            let ending = queue.finish_current();
+            // Back to back utterances keep the other apps lowered
+            if !queue.has_pending() {
+                set_ducking(false);
+            }
+
+            let event = match result {
+                // Its events come when it is spoken again
+                _ if ending == Ending::Requeued => continue,
+                Err(e) => {
+                    log::error!(
+                        "Failed to play utterance {}: {e}",
+                        utterance.id
+                    );
+                    SpeechEvent::Error(utterance.id)
+                }
+                Ok(()) if ending == Ending::Cancelled => {
+                    SpeechEvent::Cancelled(utterance.id)
+                }
+                Ok(()) => SpeechEvent::Finished(utterance.id),
+            };
+            speech_events::emit(event);
+        })
+        .expect("Failed to spawn the utterance queue worker");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utterance(phrase: &str, priority: Priority) -> UtteranceInfo {
+        UtteranceInfo {
+            id: 0,
+            phrase: phrase.to_owned(),
+            params: SpeechParams {
+                voice_id: None,
+                speaker_id: 0,
+                pitch: 50,
+                volume: 100,
+                rate: 50,
+                appended_silence_ms: 0,
+            },
+            priority,
+        }
+    }
+
+    fn pending(queue: &UtteranceQueue) -> Vec<String> {
+        queue
+            .snapshot()
+            .pending
+            .into_iter()
+            .map(|utterance| utterance.phrase)
+            .collect()
+    }
+
+    #[test]
+    fn same_priority_keeps_arrival_order() {
+        let queue = UtteranceQueue::default();
+        for phrase in ["a", "b", "c"] {
+            queue.push(
+                utterance(phrase, Priority::Normal),
+                QueueMode::Enqueue,
+            );
+        }
+        assert_eq!(pending(&queue), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn higher_priority_goes_first() {
+        let queue = UtteranceQueue::default();
+        queue.push(
+            utterance("normal", Priority::Normal),
+            QueueMode::Enqueue,
+        );
+        queue.push(
+            utterance("high 1", Priority::High),
+            QueueMode::Enqueue,
+        );
+        queue.push(
+            utterance("emergency", Priority::Emergency),
+            QueueMode::Enqueue,
+        );
+        queue.push(
+            utterance("high 2", Priority::High),
+            QueueMode::Enqueue,
+        );
+        assert_eq!(
+            pending(&queue),
+            ["emergency", "high 1", "high 2", "normal"]
+        );
+    }
+
+    #[test]
+    fn ids_are_unique() {
+        let queue = UtteranceQueue::default();
+        let first = queue.push(
+            utterance("a", Priority::Normal),
+            QueueMode::Enqueue,
+        );
+        let second = queue
+            .push(utterance("b", Priority::High), QueueMode::Enqueue);
+        assert!(first.is_some());
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn drop_if_busy_drops_all_but_emergencies() {
+        let queue = UtteranceQueue::default();
+        queue.push(
+            utterance("a", Priority::Normal),
+            QueueMode::Enqueue,
+        );
+        let dropped = queue.push(
+            utterance("b", Priority::High),
+            QueueMode::DropIfBusy,
+        );
+        let emergency = queue.push(
+            utterance("c", Priority::Emergency),
+            QueueMode::DropIfBusy,
+        );
+        assert_eq!(dropped, None);
+        assert!(emergency.is_some());
+        assert_eq!(pending(&queue), ["c", "a"]);
+    }
+
+    #[test]
+    fn preempted_utterance_goes_back_first_in_its_priority() {
+        let queue = UtteranceQueue::default();
+        queue.push(
+            utterance("current", Priority::High),
+            QueueMode::Enqueue,
+        );
+        let current = queue.wait_next();
+        queue.push(
+            utterance("high", Priority::High),
+            QueueMode::Enqueue,
+        );
+        queue.push(
+            utterance("normal", Priority::Normal),
+            QueueMode::Enqueue,
+        );
+
+        queue.push(
+            utterance("emergency", Priority::Emergency),
+            QueueMode::Enqueue,
+        );
+
+        assert_eq!(
+            pending(&queue),
+            ["emergency", "current", "high", "normal"]
+        );
+        // Spoken again under the same ID
+        assert_eq!(queue.snapshot().pending[1].id, current.id);
+        assert!(queue.is_current_cancelled());
+        assert_eq!(queue.finish_current(), Ending::Requeued);
+    }
+
+    #[test]
+    fn interrupting_emergency_drops_the_current_utterance() {
+        let queue = UtteranceQueue::default();
+        queue.push(
+            utterance("current", Priority::Normal),
+            QueueMode::Enqueue,
+        );
+        queue.wait_next();
+        queue.push(
+            utterance("next", Priority::Normal),
+            QueueMode::Enqueue,
+        );
+
+        queue.push(
+            utterance("emergency", Priority::Emergency),
+            QueueMode::Interrupt,
+        );
+
+        assert_eq!(pending(&queue), ["emergency"]);
+        assert_eq!(queue.finish_current(), Ending::Cancelled);
+    }
+
+    #[test]
+    fn emergencies_do_not_preempt_each_other() {
+        let queue = UtteranceQueue::default();
+        queue.push(
+            utterance("first", Priority::Emergency),
+            QueueMode::Enqueue,
+        );
+        queue.wait_next();
+        queue.push(
+            utterance("second", Priority::Emergency),
+            QueueMode::Enqueue,
+        );
+
+        assert_eq!(pending(&queue), ["second"]);
+        assert_eq!(queue.finish_current(), Ending::Played);
+    }
+}
@@ -0,0 +1,125 @@
+use std::io::Cursor;
+
+use pipewire as pw;
+use pw::properties::properties;
+use pw::spa::param::ParamType;
+use pw::spa::pod::deserialize::PodDeserializer;
+use pw::spa::pod::serialize::PodSerializer;
+use pw::spa::pod::{
+    Object, Pod, Property, PropertyFlags, Value, ValueArray,
+};
+use pw::spa::utils::SpaTypes;
+
+/// `node.name` of the virtual source
+pub const VIRTUAL_SOURCE_NAME: &str = "voicelift_microphone";
+/// Name shown to the user by apps like Discord, Jitsi or OBS
+pub const VIRTUAL_SOURCE_DESCRIPTION: &str = "VoiceLift Microphone";
+
+/// `node.name` of the mix input the TTS goes through
+pub const TTS_MIX_INPUT_NAME: &str = "voicelift_mix_tts";
+/// `node.name` of the mix input the microphone goes through
+pub const MICROPHONE_MIX_INPUT_NAME: &str =
+    "voicelift_mix_microphone";
+
+/// Create the PipeWire source that apps can select as a microphone.
+/// The node lives as long as the returned proxy.
+pub fn create(
+    core: &pw::core::Core,
+) -> Result<pw::node::Node, pw::Error> {
+    let node = core.create_object::<pw::node::Node>(
+        "adapter",
+        &properties! {
+            "factory.name" => "support.null-audio-sink",
+            "node.name" => VIRTUAL_SOURCE_NAME,
+            "node.description" => VIRTUAL_SOURCE_DESCRIPTION,
+            "media.class" => "Audio/Source/Virtual",
+            "audio.position" => "MONO",
+            "object.linger" => "false",
+        },
+    )?;
+    log::info!("Created virtual source {VIRTUAL_SOURCE_DESCRIPTION}");
+    Ok(node)
+}
+
+/// Create one input of the mix: a mono sink whose monitor carries
+/// what it receives scaled by its volume, see [`set_gain`]
+pub fn create_mix_input(
+    core: &pw::core::Core,
+    name: &str,
+    description: &str,
+) -> Result<pw::node::Node, pw::Error> {
+    core.create_object::<pw::node::Node>(
+        "adapter",
+        &properties! {
+            "factory.name" => "support.null-audio-sink",
+            "node.name" => name,
+            "node.description" => description,
+            "media.class" => "Audio/Sink",
+            "node.virtual" => "true",
+            // Never picked as the default sink
+            "priority.session" => "0",
+            "audio.position" => "MONO",
+            "monitor.channel-volumes" => "true",
+            "object.linger" => "false",
+        },
+    )
+}
+
+/// Set the volume of a mix input, 1.0 leaves the audio unchanged
+pub fn set_gain(
+    node: &pw::node::Node,
+    gain: f32,
+) -> Result<(), String> {
+    set_prop(
+        node,
+        pw::spa::sys::SPA_PROP_channelVolumes,
+        Value::ValueArray(ValueArray::Float(vec![gain])),
+    )
+}
+
+/// Set a single `Props` value of a node, like its volume
+pub fn set_prop(
+    node: &pw::node::Node,
+    key: u32,
+    value: Value,
+) -> Result<(), String> {
+    let props = Value::Object(Object {
+        type_: SpaTypes::ObjectParamProps.as_raw(),
+        id: ParamType::Props.as_raw(),
+        properties: vec![Property {
+            key,
+            flags: PropertyFlags::empty(),
+            value,
+        }],
+    });
+    let bytes =
+        PodSerializer::serialize(Cursor::new(Vec::new()), &props)
+            .map_err(|e| format!("Failed to serialize props: {e:?}"))?
+            .0
+            .into_inner();
+    let pod = Pod::from_bytes(&bytes).ok_or("Invalid props pod")?;
+    node.set_param(ParamType::Props, 0, pod);
+    Ok(())
+}
+
+/// A float array value of a `Props` param, like the volume of each
+/// channel of a stream
+pub fn float_array_prop(param: &Pod, key: u32) -> Option<Vec<f32>> {
+    let (_, props) =
+        PodDeserializer::deserialize_any_from(param.as_bytes())
+            .ok()?;
+    let Value::Object(props) = props else {
+        return None;
+    };
+    props
+        .properties
+        .into_iter()
+        .find_map(|prop| match prop.value {
+            Value::ValueArray(ValueArray::Float(values))
+                if prop.key == key =>
+            {
+                Some(values)
+            }
+            _ => None,
+        })
+}
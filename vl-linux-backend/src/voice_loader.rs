@@ -0,0 +1,374 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::time::sleep;
+use vl_global::vl_config::{
+    ConfigManager, LinuxConfig, VoiceConfig, VoiceEngine,
+    DEFAULT_VOICE_ID,
+};
+use vl_linux_backend::engine::{EngineCapabilities, SynthesisEngine};
+use vl_linux_backend::events::client::{
+    SpeakerInfo, VoiceInfo, VoiceStatus,
+};
+
+use crate::piper::{PiperEngine, PiperTTSManager};
+use crate::pw_graph::RoutingConfig;
+use crate::utterance_queue::EmergencyPolicy;
+use crate::{
+    ESPEAK_ENGINE, PIPERTTS_MANAGER, PW_GRAPH, UTTERANCE_QUEUE,
+};
+
+/// How often the config file is checked for changes
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+static PIPER_ENGINE: PiperEngine = PiperEngine;
+
+/// Every engine, with or without voices
+fn engines() -> Vec<&'static dyn SynthesisEngine> {
+    let mut engines: Vec<&'static dyn SynthesisEngine> =
+        vec![&PIPER_ENGINE];
+    if let Some(espeak) = ESPEAK_ENGINE.get() {
+        engines.push(espeak);
+    }
+    engines
+}
+
+pub fn voice_status() -> Result<VoiceStatus, String> {
+    let mut voices = vec![];
+    for engine in engines() {
+        let name = engine.capabilities().name;
+        let engine_voices =
+            engine.list_voices().map_err(|e| format!("{e}"))?;
+        voices.extend(engine_voices.into_iter().map(|voice| {
+            VoiceInfo {
+                id: voice.id,
+                engine: name.clone(),
+                model_path: voice.source,
+                language: voice.language,
+                loaded: voice.loaded,
+            }
+        }));
+    }
+    voices.sort_by(|a, b| a.id.cmp(&b.id));
+
+    Ok(VoiceStatus { voices })
+}
+
+/// Engines with at least one configured voice
+pub fn loaded_engines() -> Result<Vec<EngineCapabilities>, String> {
+    let mut loaded = vec![];
+    for engine in engines() {
+        let voices =
+            engine.list_voices().map_err(|e| format!("{e}"))?;
+        if !voices.is_empty() {
+            loaded.push(engine.capabilities());
+        }
+    }
+    Ok(loaded)
+}
+
+/// Engine that speaks with a voice. Voice IDs are unique across
+/// engines, see [`LinuxConfig::voices`].
+pub fn engine_for(
+    voice_id: &str,
+) -> Result<&'static dyn SynthesisEngine, String> {
+    let is_piper = PIPERTTS_MANAGER
+        .get()
+        .ok_or("PIPERTTS_MANAGER not set")?
+        .read()
+        .map_err(|_| "Failed to lock PIPERTTS_MANAGER")?
+        .voice_path(voice_id)
+        .is_some();
+    if is_piper {
+        return Ok(&PIPER_ENGINE);
+    }
+    match ESPEAK_ENGINE.get() {
+        Some(espeak) if espeak.voice_name(voice_id).is_some() => {
+            Ok(espeak)
+        }
+        _ => Err(format!("Unknown voice: {voice_id}")),
+    }
+}
+
+/// Size and modification time of a file, empty when it is missing
+fn file_version(path: &Path) -> String {
+    let Ok(metadata) = path.metadata() else {
+        return String::new();
+    };
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|modified| modified.as_nanos())
+        .unwrap_or_default();
+    format!("{}:{modified}", metadata.len())
+}
+
+/// What the audio of a voice depends on besides the speech
+/// parameters: the model of Piper voices, the voice name of
+/// espeak-ng voices.
+///
+/// Piper models are identified by their path, size and modification
+/// time, so a model replaced at the same path gets new cache keys.
+pub fn voice_source(voice_id: &str) -> Result<String, String> {
+    let manager = PIPERTTS_MANAGER
+        .get()
+        .ok_or("PIPERTTS_MANAGER not set")?
+        .read()
+        .map_err(|_| "Failed to lock PIPERTTS_MANAGER")?;
+    if let Some(config_path) = manager.voice_path(voice_id) {
+        // `voice.onnx.json` configures `voice.onnx`
+        let model_path = config_path.with_extension("");
+        return Ok(format!(
+            "{}|{}|{}",
+            config_path.display(),
+            file_version(config_path),
+            file_version(&model_path)
+        ));
+    }
+    ESPEAK_ENGINE
+        .get()
+        .and_then(|espeak| espeak.voice_name(voice_id))
+        .map(|voice_name| format!("espeak-ng:{voice_name}"))
+        .ok_or(format!("Unknown voice: {voice_id}"))
+}
+
+/// Language code of a Piper model, from its model config
+pub fn model_language(model_path: &Path) -> Option<String> {
+    let file = File::open(model_path).ok()?;
+    let model_config: serde_json::Value =
+        serde_json::from_reader(BufReader::new(file)).ok()?;
+    model_config
+        .get("language")?
+        .get("code")?
+        .as_str()
+        .map(str::to_owned)
+}
+
+/// Read the Linux section of the config file
+pub fn linux_config(
+    config_manager: &ConfigManager,
+) -> Result<LinuxConfig, String> {
+    let config = config_manager.read().map_err(|e| format!("{e}"))?;
+    config
+        .linux
+        .ok_or("The Linux config section was not found".to_owned())
+}
+
+/// Fail if `voice_id` is not a configured voice
+pub fn check_voice(voice_id: &str) -> Result<(), String> {
+    engine_for(voice_id).map(|_| ())
+}
+
+/// Read the speakers of a voice from the `speaker_id_map` of its
+/// model config
+pub fn list_speakers(
+    voice_id: &str,
+) -> Result<Vec<SpeakerInfo>, String> {
+    if !engine_for(voice_id)?.capabilities().multi_speaker {
+        return Ok(vec![]);
+    }
+    let path = PIPERTTS_MANAGER
+        .get()
+        .ok_or("PIPERTTS_MANAGER not set")?
+        .read()
+        .map_err(|_| "Failed to lock PIPERTTS_MANAGER")?
+        .voice_path(voice_id)
+        .ok_or(format!("Unknown voice: {voice_id}"))?
+        .to_path_buf();
+
+    let file = File::open(&path)
+        .map_err(|e| format!("Failed to open {path:?}: {e}"))?;
+    let model_config: serde_json::Value =
+        serde_json::from_reader(BufReader::new(file))
+            .map_err(|e| format!("Failed to parse {path:?}: {e}"))?;
+
+    let mut speakers: Vec<SpeakerInfo> = model_config
+        .get("speaker_id_map")
+        .and_then(|map| map.as_object())
+        .map(|map| {
+            map.iter()
+                .filter_map(|(name, id)| {
+                    Some(SpeakerInfo {
+                        id: id.as_i64()?,
+                        name: name.clone(),
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    speakers.sort_by_key(|speaker| speaker.id);
+
+    Ok(speakers)
+}
+
+/// Load a voice if it's not loaded yet.
+///
+/// Loading a model is slow, call this from a blocking context.
+pub fn ensure_loaded(voice_id: &str) -> Result<(), String> {
+    let lock =
+        PIPERTTS_MANAGER.get().ok_or("PIPERTTS_MANAGER not set")?;
+
+    let path = {
+        let manager = lock
+            .read()
+            .map_err(|_| "Failed to lock PIPERTTS_MANAGER")?;
+        if manager.is_loaded(voice_id) {
+            return Ok(());
+        }
+        manager
+            .voice_path(voice_id)
+            .ok_or(format!("Unknown voice: {voice_id}"))?
+            .to_path_buf()
+    };
+
+    if !path.exists() {
+        return Err(format!(
+            "Piper model path does not exist: {path:?}"
+        ));
+    }
+
+    // Load outside the lock, so the loaded voices keep working
+    // while the new one loads
+    let model = PiperTTSManager::load_model(&path)
+        .map_err(|e| format!("{e}"))?;
+
+    let inserted = lock
+        .write()
+        .map_err(|_| "Failed to lock PIPERTTS_MANAGER")?
+        .insert_voice(voice_id, path.clone(), model)
+        .map_err(|e| format!("{e}"))?;
+    if !inserted {
+        return Err(format!(
+            "Voice {voice_id} changed while it was loading"
+        ));
+    }
+    log::info!("Loaded voice {voice_id} from {path:?}");
+
+    Ok(())
+}
+
+/// Replace the known voices, each with its engine, and load the
+/// default one right away. Other voices are loaded on first use.
+///
+/// Loading a model is slow, call this from a blocking context.
+pub fn apply_voices(
+    voices: Vec<VoiceConfig>,
+) -> Result<VoiceStatus, String> {
+    let mut voice_paths: HashMap<String, PathBuf> = HashMap::new();
+    let mut espeak_voices: HashMap<String, String> = HashMap::new();
+    for voice in voices {
+        match voice.engine {
+            VoiceEngine::Piper => {
+                voice_paths
+                    .insert(voice.id, PathBuf::from(voice.voice));
+            }
+            VoiceEngine::EspeakNg => {
+                espeak_voices.insert(voice.id, voice.voice);
+            }
+        }
+    }
+    if let Some(espeak) = ESPEAK_ENGINE.get() {
+        espeak.set_voices(espeak_voices);
+    }
+
+    let has_default = voice_paths.contains_key(DEFAULT_VOICE_ID);
+
+    PIPERTTS_MANAGER
+        .get()
+        .ok_or("PIPERTTS_MANAGER not set")?
+        .write()
+        .map_err(|_| "Failed to lock PIPERTTS_MANAGER")?
+        .set_voice_paths(voice_paths);
+
+    if has_default {
+        ensure_loaded(DEFAULT_VOICE_ID)?;
+    } else {
+        log::warn!(
+            "No Piper model configured, running without voice"
+        );
+    }
+
+    voice_status()
+}
+
+fn modified_at(path: &Path) -> Option<SystemTime> {
+    path.metadata().and_then(|meta| meta.modified()).ok()
+}
+
+/// Reload the voices, the routing and the playback settings whenever
+/// they change in the config file
+pub async fn watch_config(mut config_manager: ConfigManager) {
+    let config_path = config_manager.get_config_path().to_path_buf();
+    let mut last_modified = modified_at(&config_path);
+    let last_linux = linux_config(&config_manager).ok();
+    let mut last_voices = last_linux
+        .as_ref()
+        .and_then(|linux| linux.voices().ok())
+        .unwrap_or_default();
+    let mut last_routing = last_linux
+        .as_ref()
+        .map(RoutingConfig::from_linux)
+        .unwrap_or_default();
+
+    loop {
+        sleep(CONFIG_POLL_INTERVAL).await;
+        let modified = modified_at(&config_path);
+        if modified == last_modified {
+            continue;
+        }
+        last_modified = modified;
+
+        if let Err(e) = config_manager.reload() {
+            log::error!("Failed to reload config: {e}");
+            continue;
+        }
+        let linux = match linux_config(&config_manager) {
+            Ok(linux) => linux,
+            Err(e) => {
+                log::error!("{e}");
+                continue;
+            }
+        };
+        if let Some(queue) = UTTERANCE_QUEUE.get() {
+            queue.set_emergency_policy(EmergencyPolicy::from_linux(
+                &linux,
+            ));
+            queue.set_loudness_target(linux.loudness_target_lufs);
+        }
+        let routing = RoutingConfig::from_linux(&linux);
+        if routing != last_routing {
+            last_routing = routing.clone();
+            if let Some(graph) = PW_GRAPH.get() {
+                if let Err(e) = graph.set_routing(routing) {
+                    log::error!("{e}");
+                }
+            }
+        }
+
+        let voices = match linux.voices() {
+            Ok(voices) => voices,
+            Err(e) => {
+                log::error!("Keeping the voices: {e}");
+                continue;
+            }
+        };
+        if voices == last_voices {
+            continue;
+        }
+        last_voices = voices.clone();
+
+        log::info!("Voices changed in the config, reloading");
+        let result =
+            tokio::task::spawn_blocking(move || apply_voices(voices))
+                .await;
+        match result {
+            Ok(Err(e)) => log::error!("Failed to reload voices: {e}"),
+            Err(e) => log::error!("Failed to reload voices: {e}"),
+            Ok(Ok(_)) => {}
+        }
+    }
+}
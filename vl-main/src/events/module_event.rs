@@ -1,32 +1,36 @@
-use std::sync::Arc;
-
-use async_lock::RwLock;
 use bevy::{
     ecs::{
         event::{Event, EventReader},
-        system::{Res, ResMut},
+        system::ResMut,
     },
     tasks::block_on,
-    time::Time,
 };
 use bevy_tokio_tasks::TokioTasksRuntime;
-use vl_global::{
-    audio_devices::{
-        AudioDeviceType, AudioDevices, AudioDevicesComparison,
-    },
-    vl_config::ConfigManager,
-};
+use std::path::PathBuf;
+use vl_global::audio_devices::{AudioDeviceType, AvailableDevices};
 
-use crate::{
-    modules::module_manager::ModuleManager,
-    ui::screen_manager::ScreenManager,
-};
+use crate::modules::module_manager::ModuleManager;
 
 #[derive(Event, Debug)]
 pub enum ModuleEvent {
     LoadModule(String),
     UpdateDeviceSelection(UpdateDeviceSelectionEvent),
     Speak(String),
+    StopSpeaking,
+    /// Pause the current utterance without dropping it
+    PauseSpeaking,
+    ResumeSpeaking,
+    /// Write the audio of a text into a file instead of playing it
+    ExportAudio(ExportAudioEvent),
+    /// Reload the voices after their models changed in the config
+    ReloadVoices,
+    FetchSpeakers,
+    /// Sent by the TTS module while an utterance is being spoken
+    UtteranceStatus(UtteranceStatusEvent),
+    /// Sent by the device module when devices appear or disappear
+    DeviceListUpdated(AvailableDevices),
+    /// The device rules in the config were changed and saved
+    DeviceRulesChanged,
 }
 
 #[derive(Debug)]
@@ -36,9 +40,33 @@ pub struct UpdateDeviceSelectionEvent {
     pub name: String,
 }
 
+#[derive(Debug)]
+pub struct ExportAudioEvent {
+    pub text: String,
+    /// The format is picked from the extension
+    pub path: PathBuf,
+}
+
 #[derive(Debug)]
 pub struct LinkAllEvent;
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UtteranceStatus {
+    Started,
+    Finished,
+    Cancelled,
+    /// The backend logs why
+    Error,
+}
+
+#[derive(Debug)]
+pub struct UtteranceStatusEvent {
+    pub utterance_id: u64,
+    /// Milliseconds since the UNIX epoch
+    pub timestamp: u64,
+    pub status: UtteranceStatus,
+}
+
 pub fn initialize_module_manager(
     mut module_manager: ResMut<ModuleManager>,
     runtime: ResMut<TokioTasksRuntime>,
@@ -56,12 +84,12 @@ pub fn initialize_module_manager(
                 let result = device_module.start().await;
 
                 if let Err(e) = result {
-                    log::error!("{e}");
                     let error = "Device Module error".to_owned();
-                    module_manager.error(error);
+                    module_manager.start_error(error, &e);
                 }
             }
         }
+        module_manager.fetch_available_devices().await;
         module_manager.relink_all_devices().await;
 
         // Enable tts Module
@@ -74,12 +102,12 @@ pub fn initialize_module_manager(
                 let result = tts_module.start().await;
 
                 if let Err(e) = result {
-                    log::error!("{e}");
                     let error = "TTS Module error".to_owned();
-                    module_manager.error(error);
+                    module_manager.start_error(error, &e);
                 }
             }
         }
+        module_manager.fetch_speakers().await;
     });
 }
 
@@ -106,89 +134,43 @@ pub fn module_event_handler(
                 ModuleEvent::Speak(text) => {
                     module_manager.speak(text.to_string()).await
                 }
+                ModuleEvent::StopSpeaking => {
+                    module_manager.stop_speaking().await
+                }
+                ModuleEvent::PauseSpeaking => {
+                    module_manager.pause_speaking().await
+                }
+                ModuleEvent::ResumeSpeaking => {
+                    module_manager.resume_speaking().await
+                }
+                ModuleEvent::ExportAudio(e) => {
+                    module_manager
+                        .export_audio(e.text.clone(), e.path.clone())
+                        .await
+                }
+                ModuleEvent::ReloadVoices => {
+                    module_manager.reload_voices().await
+                }
+                ModuleEvent::FetchSpeakers => {
+                    module_manager.fetch_speakers().await
+                }
+                ModuleEvent::UtteranceStatus(e) => {
+                    module_manager.update_utterance_status(e)
+                }
+                ModuleEvent::DeviceListUpdated(devices) => {
+                    module_manager
+                        .update_available_devices(devices.clone())
+                        .await
+                }
+                ModuleEvent::DeviceRulesChanged => {
+                    refresh_device_selection(&mut module_manager)
+                        .await
+                }
             }
         }
     });
 }
 
-//; System for activating repeating tasks in the background
-pub fn module_manager_ticker(
-    mut module_manager: ResMut<ModuleManager>,
-    time: Res<Time>,
-    tokio: ResMut<TokioTasksRuntime>,
-    _screen: ResMut<ScreenManager>,
-) {
-    let config: std::sync::Arc<
-        async_lock::RwLock<vl_global::vl_config::ConfigManager>,
-    > = module_manager.config.clone();
-    let timer = &mut module_manager._timer;
-    timer.tick(time.delta());
-
-    if timer.finished() {
-        let runtime = tokio.runtime();
-        runtime.block_on(async {
-            get_available_devices(module_manager, config).await;
-        });
-    }
-}
-
-async fn get_available_devices(
-    mut module_manager: ResMut<'_, ModuleManager>,
-    config: Arc<RwLock<ConfigManager>>,
-) {
-    let config_result = config.read().await.read();
-    if let Err(e) = config_result {
-        log::error!("{e}");
-        return;
-    }
-    let config = config_result.unwrap();
-    let selected_device_module =
-        module_manager.selected_device_module.clone();
-    if selected_device_module.is_none() {
-        return;
-    }
-    let module = selected_device_module.unwrap();
-    let module = module.write().await;
-
-    if !module.is_started() {
-        return;
-    }
-
-    let devices = module.get_devices().await;
-
-    // for some reason this module don't get dropped here if you don't order it to.
-    // if you remove this line, the program will just hang forever lol
-    drop(module);
-
-    if let Err(e) = devices {
-        log::error!("{e}");
-        return;
-    }
-    let devices = devices.unwrap();
-
-    let comparison =
-        AudioDevices::compare_lists(&devices, &config.devices);
-
-    // Verify if there are new connections that should be remade
-    // For example: If a screen was suddenly reopened
-    let mut needs_relinking = false;
-    if let Some(current_devices) =
-        &module_manager.available_devices.clone()
-    {
-        if AudioDevicesComparison::are_there_reconnected_devices(
-            current_devices,
-            &comparison,
-        ) {
-            needs_relinking = true;
-        }
-    }
-
-    module_manager.available_devices = Some(comparison);
-    if needs_relinking {
-        module_manager.relink_all_devices().await;
-    }
-}
-
 /// Modify the configuration by adding or removing a device based on the `event` information
 /// and then save the updated configuration.
 pub async fn handler_update_device_selection_event(
@@ -222,6 +204,18 @@ pub async fn handler_update_device_selection_event(
         return;
     }
 
+    refresh_device_selection(module_manager).await;
+}
+
+/// Redo the device comparison after the selection in the config
+/// changed and link what is selected now
+async fn refresh_device_selection(
+    module_manager: &mut ResMut<'_, ModuleManager>,
+) {
     module_manager.reload_config();
+    // The selection changed, so the comparison has to be redone
+    if let Some(devices) = module_manager.known_devices.clone() {
+        module_manager.update_available_devices(devices).await;
+    }
     module_manager.relink_all_devices().await;
 }
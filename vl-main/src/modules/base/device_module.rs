@@ -1,5 +1,5 @@
 use busrt::async_trait;
-use vl_global::audio_devices::AudioDevices;
+use vl_global::audio_devices::AvailableDevices;
 
 use crate::modules::base::i_module::IModule;
 
@@ -13,7 +13,7 @@ pub const MODULE_TYPE: &str = "Device Module";
 
 #[async_trait]
 pub trait DeviceModule: IModule {
-    async fn get_devices(&self) -> anyhow::Result<AudioDevices>;
+    async fn get_devices(&self) -> anyhow::Result<AvailableDevices>;
 
     fn get_module_type(&self) -> &'static str {
         MODULE_TYPE
@@ -32,6 +32,18 @@ pub trait DeviceModule: IModule {
         )
     }
 
+    /// Replace the devices the module keeps linked, including the
+    /// ones that are not available right now
+    async fn set_desired_links(
+        &self,
+        _target_devices: Vec<String>,
+    ) -> anyhow::Result<()> {
+        panic!(
+            "{}",
+            is_capable_of_linking_error(self.is_capable_of_linking())
+        )
+    }
+
     async fn unlink_device(
         &self,
         _target_device: String,
@@ -1,3 +1,4 @@
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use async_lock::RwLock;
@@ -8,17 +9,72 @@ use crate::modules::base::i_module::IModule;
 
 pub const MODULE_TYPE: &str = "TTS Module";
 
+/// Audio file written by [`TtsModule::synthesize_to_file`]
+#[derive(Debug, Clone)]
+pub struct ExportedAudio {
+    pub path: PathBuf,
+    pub duration_ms: u64,
+    pub sample_rate: u32,
+}
+
+/// Speaker of a multi-speaker voice
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Speaker {
+    pub id: i64,
+    pub name: String,
+}
+
 /// Responsible for managing TTS
 #[async_trait]
 pub trait TtsModule: IModule {
+    /// Queue `text` to be spoken and return right away with the ID
+    /// of the utterance, if the module keeps track of them.
+    /// `voice_id` picks one of the configured voices, `None` uses
+    /// the default one.
     async fn speak(
         &self,
         text: String,
+        voice_id: Option<String>,
         config: Arc<RwLock<ConfigManager>>,
-    ) -> anyhow::Result<()>;
+    ) -> anyhow::Result<Option<u64>>;
 
     async fn stop_speaking(&self) -> anyhow::Result<()>;
 
+    /// Pause the current utterance, keeping it and the queue
+    async fn pause_speaking(&self) -> anyhow::Result<()> {
+        anyhow::bail!("This module can't pause speech")
+    }
+
+    /// Resume the utterance paused by [`TtsModule::pause_speaking`]
+    async fn resume_speaking(&self) -> anyhow::Result<()> {
+        anyhow::bail!("This module can't resume speech")
+    }
+
+    /// Synthesize `text` into an audio file instead of playing it.
+    /// The format is picked from the extension of `path`.
+    async fn synthesize_to_file(
+        &self,
+        _text: String,
+        _voice_id: Option<String>,
+        _path: PathBuf,
+        _config: Arc<RwLock<ConfigManager>>,
+    ) -> anyhow::Result<ExportedAudio> {
+        anyhow::bail!("This module can't export audio")
+    }
+
+    /// Reload the voices after their models changed in the config
+    async fn reload_voices(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Speakers of a voice, empty if it has a single speaker
+    async fn list_speakers(
+        &self,
+        _voice_id: Option<String>,
+    ) -> anyhow::Result<Vec<Speaker>> {
+        Ok(vec![])
+    }
+
     fn get_module_type(&self) -> &'static str {
         MODULE_TYPE
     }
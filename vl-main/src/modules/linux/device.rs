@@ -1,19 +1,19 @@
 use busrt::rpc::Rpc;
 use busrt::{QoS, async_trait};
-use vl_global::audio_devices::AudioDevices;
+use vl_global::audio_devices::AvailableDevices;
 use vl_linux_backend::events::client::{
     self, METHOD_GET_DEVICES, METHOD_LINK_DEVICES,
-    METHOD_UNLINK_DEVICES,
+    METHOD_SET_DESIRED_LINKS, METHOD_UNLINK_DEVICES,
 };
 
 use crate::modules::base::device_module::DeviceModule;
-use crate::modules::linux::BROKER_NAME;
 use crate::modules::linux::error::LinuxModuleError;
 use crate::modules::linux::linux_module::LinuxModule;
+use crate::modules::linux::{BROKER_NAME, rpc_error_message};
 
 #[async_trait]
 impl DeviceModule for LinuxModule {
-    async fn get_devices(&self) -> anyhow::Result<AudioDevices> {
+    async fn get_devices(&self) -> anyhow::Result<AvailableDevices> {
         // call the method with no confirm
         if let Some(client) = &self._client {
             let result = client
@@ -28,12 +28,10 @@ impl DeviceModule for LinuxModule {
                 )
                 .await
                 .map_err(|e| {
-                    let empty_str = "empty_data";
-                    let data =
-                        e.data().unwrap_or(empty_str.as_bytes());
-                    String::from_utf8(data.to_vec())
-                })
-                .unwrap();
+                    LinuxModuleError::FailedToGetDevices(
+                        rpc_error_message(&e),
+                    )
+                })?;
 
             let devices: client::ResponseDevices =
                 rmp_serde::from_slice(result.payload())?;
@@ -60,7 +58,9 @@ impl DeviceModule for LinuxModule {
                     METHOD_LINK_DEVICES,
                     rmp_serde::to_vec_named(
                         &client::RequestDeviceLinkage {
+                            source: client::LinkSource::Tts,
                             target_device: input_device,
+                            port_mapping: None,
                         },
                     )?
                     .into(),
@@ -68,19 +68,73 @@ impl DeviceModule for LinuxModule {
                 )
                 .await
                 .map_err(|e| {
-                    let empty_str = "empty_data";
-                    let data =
-                        e.data().unwrap_or(empty_str.as_bytes());
-                    String::from_utf8(data.to_vec())
-                })
-                .unwrap();
+                    LinuxModuleError::FailedToLink(rpc_error_message(
+                        &e,
+                    ))
+                })?;
 
             let response: client::ResponseDeviceLinkage =
                 rmp_serde::from_slice(result.payload())?;
             // Throws error if the result is not successful
-            response
+            let linked = response
                 .result
                 .map_err(LinuxModuleError::FailedToLink)?;
+            for ports in linked {
+                log::debug!(
+                    "Linked {} -> {}",
+                    ports.output_port,
+                    ports.input_port
+                );
+            }
+            Ok(())
+        } else {
+            Err(LinuxModuleError::BackendServiceNotStarted.into())
+        }
+    }
+
+    async fn set_desired_links(
+        &self,
+        target_devices: Vec<String>,
+    ) -> anyhow::Result<()> {
+        if let Some(client) = &self._client {
+            let links = target_devices
+                .into_iter()
+                .map(|target_device| client::DesiredLink {
+                    source: client::LinkSource::Tts,
+                    target_device,
+                    port_mapping: None,
+                })
+                .collect();
+            let result = client
+                .call(
+                    BROKER_NAME,
+                    METHOD_SET_DESIRED_LINKS,
+                    rmp_serde::to_vec_named(
+                        &client::RequestSetDesiredLinks { links },
+                    )?
+                    .into(),
+                    QoS::Processed,
+                )
+                .await
+                .map_err(|e| {
+                    LinuxModuleError::FailedToLink(rpc_error_message(
+                        &e,
+                    ))
+                })?;
+
+            let response: client::ResponseSetDesiredLinks =
+                rmp_serde::from_slice(result.payload())?;
+            // Throws error if the result is not successful
+            let states = response
+                .result
+                .map_err(LinuxModuleError::FailedToLink)?;
+            for state in states {
+                log::debug!(
+                    "{}: {} ports linked",
+                    state.link.target_device,
+                    state.linked.len()
+                );
+            }
             Ok(())
         } else {
             Err(LinuxModuleError::BackendServiceNotStarted.into())
@@ -99,6 +153,7 @@ impl DeviceModule for LinuxModule {
                     METHOD_UNLINK_DEVICES,
                     rmp_serde::to_vec_named(
                         &client::RequestDeviceUnLinkage {
+                            source: client::LinkSource::Tts,
                             target_device: input_device,
                         },
                     )?
@@ -107,12 +162,10 @@ impl DeviceModule for LinuxModule {
                 )
                 .await
                 .map_err(|e| {
-                    let empty_str = "empty_data";
-                    let data =
-                        e.data().unwrap_or(empty_str.as_bytes());
-                    String::from_utf8(data.to_vec())
-                })
-                .unwrap();
+                    LinuxModuleError::FailedToUnlink(
+                        rpc_error_message(&e),
+                    )
+                })?;
 
             let response: client::ResponseDeviceUnLinkage =
                 rmp_serde::from_slice(result.payload())?;
@@ -7,6 +7,12 @@ pub enum LinuxModuleError {
         "Failed to connect into the Linux Backend Socket. Reason: {0}"
     )]
     FailedToConnectIntoSocket(String),
+    #[error(
+        "Incompatible Linux Backend: {0}. Install the same version of VoiceLift and of the backend."
+    )]
+    IncompatibleBackend(String),
+    #[error("Failed to authenticate with the Linux Backend: {0}")]
+    FailedToAuthenticate(String),
     #[error("Linux Backend Service was not started")]
     BackendServiceNotStarted,
     #[error("Failed to get devices: {0}")]
@@ -17,4 +23,10 @@ pub enum LinuxModuleError {
     FailedToUnlink(String),
     #[error("Failed to initialize speaking: {0}")]
     FailedToSpeak(String),
+    #[error("Failed to pause or resume speaking: {0}")]
+    FailedToPause(String),
+    #[error("Failed to load voice: {0}")]
+    FailedToLoadVoice(String),
+    #[error("Failed to export audio: {0}")]
+    FailedToExportAudio(String),
 }
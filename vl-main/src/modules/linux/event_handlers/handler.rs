@@ -1,3 +1,5 @@
+use std::sync::{Arc, Mutex};
+
 use bevy::ecs::event::EventWriter;
 use bevy::ecs::resource::Resource;
 use bevy::ecs::system::ResMut;
@@ -7,23 +9,38 @@ use busrt::{
     async_trait,
     rpc::{RpcEvent, RpcHandlers, RpcResult},
 };
-use vl_linux_backend::events::server::{self, ResponseServerEvent};
+use vl_linux_backend::events::server::{
+    self, RequestDeviceListUpdated, ResponseServerEvent,
+    ResponseSession, UtteranceEvent,
+};
 
-use crate::events::module_event::ModuleEvent;
+use crate::events::module_event::{
+    ModuleEvent, UtteranceStatus, UtteranceStatusEvent,
+};
 
-#[derive(Resource)]
+/// Receives the events sent by the backend and forwards them to
+/// Bevy. The RPC client and the Bevy resource are clones sharing the
+/// same pending events.
+#[derive(Resource, Clone)]
 pub(crate) struct LinuxModuleEventHandler {
-    pending_events: Vec<ModuleEvent>,
+    pending_events: Arc<Mutex<Vec<ModuleEvent>>>,
+    /// Secret the backend gave us when we authenticated
+    session_secret: Arc<Mutex<Option<String>>>,
 }
 
 pub fn linux_module_event_handler_update(
-    mut handler: ResMut<LinuxModuleEventHandler>,
+    handler: ResMut<LinuxModuleEventHandler>,
     mut event_w: EventWriter<ModuleEvent>,
 ) {
-    if handler.pending_events.is_empty() {
-        return;
-    }
-    while let Some(event) = handler.pending_events.pop() {
+    let mut pending_events = match handler.pending_events.lock() {
+        Ok(pending_events) => pending_events,
+        Err(e) => {
+            log::error!("Failed to lock pending events: {e}");
+            return;
+        }
+    };
+    // Keep the order the backend sent them in
+    for event in pending_events.drain(..) {
         event_w.write(event);
     }
 }
@@ -31,9 +48,94 @@ pub fn linux_module_event_handler_update(
 impl LinuxModuleEventHandler {
     pub fn new() -> Self {
         Self {
-            pending_events: vec![],
+            pending_events: Arc::new(Mutex::new(vec![])),
+            session_secret: Arc::new(Mutex::new(None)),
         }
     }
+
+    pub fn set_session_secret(&self, secret: String) {
+        match self.session_secret.lock() {
+            Ok(mut session_secret) => *session_secret = Some(secret),
+            Err(e) => {
+                log::error!("Failed to lock session secret: {e}")
+            }
+        }
+    }
+
+    /// Answer the backend asking if we are the client that
+    /// authenticated
+    fn session(&self, event: &RpcEvent) -> RpcResult {
+        if event.sender() != server::SESSIONS_CLIENT {
+            log::warn!(
+                "Refused to give the session to {}",
+                event.sender()
+            );
+            return Err(RpcError::method(None));
+        }
+        let secret = match self.session_secret.lock() {
+            Ok(session_secret) => session_secret.clone(),
+            Err(e) => {
+                log::error!("Failed to lock session secret: {e}");
+                None
+            }
+        };
+        Ok(Some(rmp_serde::to_vec_named(&ResponseSession {
+            secret,
+        })?))
+    }
+
+    fn push_event(&self, event: ModuleEvent) {
+        match self.pending_events.lock() {
+            Ok(mut pending_events) => pending_events.push(event),
+            Err(e) => {
+                log::error!("Failed to lock pending events: {e}")
+            }
+        }
+    }
+
+    /// Turn speech lifecycle frames into [`ModuleEvent`]s. Returns
+    /// `false` if the frame was published on another topic.
+    fn handle_utterance_frame(&self, frame: &Frame) -> bool {
+        let topic = frame.topic().unwrap_or_default();
+        if !matches!(
+            topic,
+            server::TOPIC_UTTERANCE_STARTED
+                | server::TOPIC_UTTERANCE_FINISHED
+                | server::TOPIC_UTTERANCE_CANCELLED
+                | server::TOPIC_UTTERANCE_ERROR
+        ) {
+            return false;
+        }
+
+        let event: UtteranceEvent =
+            match rmp_serde::from_slice(frame.payload()) {
+                Ok(event) => event,
+                Err(e) => {
+                    log::error!("Failed to deserialize {topic}: {e}");
+                    return true;
+                }
+            };
+        let status = match topic {
+            server::TOPIC_UTTERANCE_STARTED => {
+                UtteranceStatus::Started
+            }
+            server::TOPIC_UTTERANCE_FINISHED => {
+                UtteranceStatus::Finished
+            }
+            server::TOPIC_UTTERANCE_CANCELLED => {
+                UtteranceStatus::Cancelled
+            }
+            _ => UtteranceStatus::Error,
+        };
+        self.push_event(ModuleEvent::UtteranceStatus(
+            UtteranceStatusEvent {
+                utterance_id: event.utterance_id,
+                timestamp: event.timestamp,
+                status,
+            },
+        ));
+        true
+    }
 }
 
 #[async_trait]
@@ -44,10 +146,19 @@ impl RpcHandlers for LinuxModuleEventHandler {
         let parse_method = event.parse_method()?;
         let event_name = parse_method.to_owned();
         log::debug!("Handling Event: {}", event_name);
+        if parse_method == server::METHOD_SESSION {
+            return self.session(&event);
+        }
 
         let mut method_not_found = false;
         match parse_method {
-            server::METHOD_DEVICE_LIST_UPDATED => {}
+            server::METHOD_DEVICE_LIST_UPDATED => {
+                let request: RequestDeviceListUpdated =
+                    rmp_serde::from_slice(event.payload())?;
+                self.push_event(ModuleEvent::DeviceListUpdated(
+                    request.devices,
+                ));
+            }
             _ => {
                 method_not_found = true;
             }
@@ -75,6 +186,9 @@ impl RpcHandlers for LinuxModuleEventHandler {
     }
     // handle broadcast notifications and topic publications
     async fn handle_frame(&self, frame: Frame) {
+        if self.handle_utterance_frame(&frame) {
+            return;
+        }
         println!(
             "Got non-RPC frame from {}: {:?} {:?} {}",
             frame.sender(),
@@ -1,33 +1,135 @@
+use busrt::QoS;
 use busrt::async_trait;
+use busrt::client::AsyncClient;
 use busrt::ipc::{Client, Config};
-use busrt::rpc::RpcClient;
+use busrt::rpc::{Rpc, RpcClient};
 use std::fmt::Debug;
+use vl_global::auth;
+use vl_global::vl_config::{self, ConfigManager};
+use vl_linux_backend::events::client::{
+    GrantedSession, METHOD_AUTHENTICATE, METHOD_HELLO,
+    PROTOCOL_VERSION, RequestAuthenticate, RequestHello,
+    ResponseAuthenticate, ResponseHello,
+};
+use vl_linux_backend::events::server::TOPIC_UTTERANCE_ALL;
 
 use crate::modules::base::i_module::IModule;
 use crate::modules::linux::error::LinuxModuleError;
-use crate::modules::linux::event_handlers;
+use crate::modules::linux::event_handlers::handler::LinuxModuleEventHandler;
+use crate::modules::linux::{BROKER_NAME, rpc_error_message};
 
 /// Resposible for linking devices in Linux with the help of a backend (vl-linux-backend)
 pub struct LinuxModule {
     pub(super) _client: Option<RpcClient>,
+    event_handler: LinuxModuleEventHandler,
 }
 
 impl LinuxModule {
-    async fn new_client() -> anyhow::Result<RpcClient> {
-        let name = "voice-lift-device.client";
+    async fn new_client(
+        handlers: LinuxModuleEventHandler,
+    ) -> anyhow::Result<RpcClient> {
+        let name = auth::client_name("voice-lift-device.client")?;
+        let linux = ConfigManager::read_only()?.linux;
+        let socket_path = vl_config::socket_path(linux.as_ref());
         // create a new client instance
-        let config = Config::new("/tmp/voicelift.sock", name);
-        let client = Client::connect(&config).await.map_err(|e| {
-            LinuxModuleError::FailedToConnectIntoSocket(e.to_string())
-        })?;
-        // create RPC with no handlers
-        let handlers =
-            event_handlers::handler::LinuxModuleEventHandler::new();
+        let config =
+            Config::new(&socket_path.to_string_lossy(), &name);
+        let mut client =
+            Client::connect(&config).await.map_err(|e| {
+                LinuxModuleError::FailedToConnectIntoSocket(
+                    e.to_string(),
+                )
+            })?;
+        // speech lifecycle events are published on topics
+        client.subscribe(TOPIC_UTTERANCE_ALL, QoS::No).await?;
 
         Ok(RpcClient::new(client, handlers))
     }
-    pub async fn new() -> Self {
-        Self { _client: None }
+    /// Refuse backends that speak another protocol version, their
+    /// responses would fail to deserialize
+    async fn hello(client: &RpcClient) -> anyhow::Result<()> {
+        let result = client
+            .call(
+                BROKER_NAME,
+                METHOD_HELLO,
+                rmp_serde::to_vec_named(&RequestHello {
+                    protocol_version: PROTOCOL_VERSION,
+                })?
+                .into(),
+                QoS::Processed,
+            )
+            .await
+            // Backends older than the handshake don't know the method
+            .map_err(|e| {
+                LinuxModuleError::IncompatibleBackend(format!(
+                    "no answer to hello ({})",
+                    rpc_error_message(&e)
+                ))
+            })?;
+        let response: ResponseHello =
+            rmp_serde::from_slice(result.payload()).map_err(|e| {
+                LinuxModuleError::IncompatibleBackend(e.to_string())
+            })?;
+        let backend = response
+            .result
+            .map_err(LinuxModuleError::IncompatibleBackend)?;
+        if backend.protocol_version != PROTOCOL_VERSION {
+            return Err(LinuxModuleError::IncompatibleBackend(
+                format!(
+                    "version {} speaks protocol {}, expected {}",
+                    backend.backend_version,
+                    backend.protocol_version,
+                    PROTOCOL_VERSION
+                ),
+            )
+            .into());
+        }
+        log::info!(
+            "Linux Backend version {}",
+            backend.backend_version
+        );
+        Ok(())
+    }
+
+    /// Send the token of the auth file, every other call is refused
+    /// until the backend accepts it
+    async fn authenticate(
+        client: &RpcClient,
+    ) -> anyhow::Result<GrantedSession> {
+        let token = auth::client_token()?;
+        let result = client
+            .call(
+                BROKER_NAME,
+                METHOD_AUTHENTICATE,
+                rmp_serde::to_vec_named(&RequestAuthenticate {
+                    token,
+                })?
+                .into(),
+                QoS::Processed,
+            )
+            .await
+            .map_err(|e| {
+                LinuxModuleError::FailedToAuthenticate(
+                    rpc_error_message(&e),
+                )
+            })?;
+        let response: ResponseAuthenticate =
+            rmp_serde::from_slice(result.payload())?;
+        let session = response
+            .result
+            .map_err(LinuxModuleError::FailedToAuthenticate)?;
+        log::info!(
+            "Authenticated with the Linux Backend as {:?}",
+            session.scope
+        );
+        Ok(session)
+    }
+
+    pub async fn new(event_handler: LinuxModuleEventHandler) -> Self {
+        Self {
+            _client: None,
+            event_handler,
+        }
     }
 }
 
@@ -38,7 +140,12 @@ impl IModule for LinuxModule {
     }
 
     async fn start(&mut self) -> anyhow::Result<()> {
-        self._client = Some(Self::new_client().await?);
+        let client =
+            Self::new_client(self.event_handler.clone()).await?;
+        Self::hello(&client).await?;
+        let session = Self::authenticate(&client).await?;
+        self.event_handler.set_session_secret(session.secret);
+        self._client = Some(client);
         Ok(())
     }
 }
@@ -4,3 +4,26 @@ pub(crate) mod linux_module;
 pub(crate) mod tts;
 const BROKER_NAME: &str = ".broker";
 pub(crate) mod event_handlers;
+
+use busrt::rpc::RpcError;
+use vl_linux_backend::events::client::{
+    RPC_ERROR_CODE_UNAUTHORIZED, Unauthorized,
+};
+
+/// Readable message of a call the backend failed or refused
+pub(crate) fn rpc_error_message(e: &RpcError) -> String {
+    if e.code() == RPC_ERROR_CODE_UNAUTHORIZED {
+        let unauthorized = e.data().and_then(|data| {
+            rmp_serde::from_slice::<Unauthorized>(data).ok()
+        });
+        if let Some(unauthorized) = unauthorized {
+            return format!(
+                "{} needs the {:?} scope",
+                unauthorized.method, unauthorized.required
+            );
+        }
+    }
+    let data =
+        e.data().map(String::from_utf8_lossy).unwrap_or_default();
+    format!("RPC error {}: {data}", e.code())
+}
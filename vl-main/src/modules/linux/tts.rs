@@ -1,34 +1,56 @@
 use async_lock::RwLock;
 use busrt::rpc::Rpc;
 use busrt::{QoS, async_trait};
+use std::path::PathBuf;
 use std::sync::Arc;
 use vl_global::vl_config::ConfigManager;
 use vl_linux_backend::error::LinuxBackendError;
 use vl_linux_backend::events::client::{
-    self, METHOD_SPEAK, METHOD_STOP_SPEAK,
+    self, AudioFileFormat, METHOD_LIST_SPEAKERS, METHOD_PAUSE_SPEAK,
+    METHOD_RELOAD_VOICE, METHOD_RESUME_SPEAK, METHOD_SPEAK,
+    METHOD_STOP_SPEAK, METHOD_SYNTHESIZE_TO_FILE, Priority,
+    QueueMode, SpeechParams,
 };
 
-use crate::modules::base::tts_module::TtsModule;
-use crate::modules::linux::BROKER_NAME;
+use crate::modules::base::tts_module::{
+    ExportedAudio, Speaker, TtsModule,
+};
 use crate::modules::linux::error::LinuxModuleError;
 use crate::modules::linux::linux_module::LinuxModule;
+use crate::modules::linux::{BROKER_NAME, rpc_error_message};
+
+/// Build the speech parameters from the Linux section of the config
+async fn speech_params(
+    voice_id: Option<String>,
+    config: Arc<RwLock<ConfigManager>>,
+) -> anyhow::Result<SpeechParams> {
+    let config_lock = config.read().await;
+    let config = config_lock.read()?;
+    drop(config_lock);
+    if config.linux.is_none() {
+        return Err(LinuxBackendError::ConfigSectionNotFound.into());
+    }
+    let linux_config = config.linux.unwrap();
+
+    Ok(SpeechParams {
+        voice_id,
+        speaker_id: linux_config.speaker_id,
+        pitch: linux_config.pitch,
+        volume: linux_config.volume,
+        rate: linux_config.rate,
+        appended_silence_ms: linux_config.appended_silence_ms,
+    })
+}
 
 #[async_trait]
 impl TtsModule for LinuxModule {
     async fn speak(
         &self,
         text: String,
+        voice_id: Option<String>,
         config: Arc<RwLock<ConfigManager>>,
-    ) -> anyhow::Result<()> {
-        let config_lock = config.read().await;
-        let config = config_lock.read()?;
-        drop(config_lock);
-        if config.linux.is_none() {
-            return Err(
-                LinuxBackendError::ConfigSectionNotFound.into()
-            );
-        }
-        let linux_config = config.linux.unwrap();
+    ) -> anyhow::Result<Option<u64>> {
+        let params = speech_params(voice_id, config).await?;
 
         if let Some(client) = &self._client {
             let result = client
@@ -37,28 +59,28 @@ impl TtsModule for LinuxModule {
                     METHOD_SPEAK,
                     rmp_serde::to_vec_named(&client::RequestTTS {
                         phrase: text,
-                        pitch: linux_config.pitch,
-                        volume: linux_config.volume,
+                        params,
+                        // Waits for what is already being spoken
+                        mode: QueueMode::Enqueue,
+                        priority: Priority::Normal,
                     })?
                     .into(),
                     QoS::Processed,
                 )
                 .await
                 .map_err(|e| {
-                    let empty_str = "empty_data";
-                    let data =
-                        e.data().unwrap_or(empty_str.as_bytes());
-                    String::from_utf8(data.to_vec())
-                })
-                .unwrap();
+                    LinuxModuleError::FailedToSpeak(
+                        rpc_error_message(&e),
+                    )
+                })?;
 
             let response: client::ResponseTTS =
                 rmp_serde::from_slice(result.payload())?;
             // Throws error if the result is not successful
-            response
+            let utterance_id = response
                 .result
                 .map_err(LinuxModuleError::FailedToSpeak)?;
-            Ok(())
+            Ok(utterance_id)
         } else {
             Err(LinuxModuleError::BackendServiceNotStarted.into())
         }
@@ -78,12 +100,10 @@ impl TtsModule for LinuxModule {
                 )
                 .await
                 .map_err(|e| {
-                    let empty_str = "empty_data";
-                    let data =
-                        e.data().unwrap_or(empty_str.as_bytes());
-                    String::from_utf8(data.to_vec())
-                })
-                .unwrap();
+                    LinuxModuleError::FailedToSpeak(
+                        rpc_error_message(&e),
+                    )
+                })?;
 
             let response: client::ResponseStopTTS =
                 rmp_serde::from_slice(result.payload())?;
@@ -96,4 +116,192 @@ impl TtsModule for LinuxModule {
             Err(LinuxModuleError::BackendServiceNotStarted.into())
         }
     }
+
+    async fn pause_speaking(&self) -> anyhow::Result<()> {
+        if let Some(client) = &self._client {
+            let result = client
+                .call(
+                    BROKER_NAME,
+                    METHOD_PAUSE_SPEAK,
+                    rmp_serde::to_vec_named(
+                        &client::RequestPauseTTS {},
+                    )?
+                    .into(),
+                    QoS::Processed,
+                )
+                .await
+                .map_err(|e| {
+                    LinuxModuleError::FailedToPause(
+                        rpc_error_message(&e),
+                    )
+                })?;
+
+            let response: client::ResponsePauseTTS =
+                rmp_serde::from_slice(result.payload())?;
+            // Throws error if the result is not successful
+            response
+                .result
+                .map_err(LinuxModuleError::FailedToPause)?;
+            Ok(())
+        } else {
+            Err(LinuxModuleError::BackendServiceNotStarted.into())
+        }
+    }
+
+    async fn resume_speaking(&self) -> anyhow::Result<()> {
+        if let Some(client) = &self._client {
+            let result = client
+                .call(
+                    BROKER_NAME,
+                    METHOD_RESUME_SPEAK,
+                    rmp_serde::to_vec_named(
+                        &client::RequestResumeTTS {},
+                    )?
+                    .into(),
+                    QoS::Processed,
+                )
+                .await
+                .map_err(|e| {
+                    LinuxModuleError::FailedToPause(
+                        rpc_error_message(&e),
+                    )
+                })?;
+
+            let response: client::ResponseResumeTTS =
+                rmp_serde::from_slice(result.payload())?;
+            // Throws error if the result is not successful
+            response
+                .result
+                .map_err(LinuxModuleError::FailedToPause)?;
+            Ok(())
+        } else {
+            Err(LinuxModuleError::BackendServiceNotStarted.into())
+        }
+    }
+
+    async fn reload_voices(&self) -> anyhow::Result<()> {
+        if let Some(client) = &self._client {
+            let result = client
+                .call(
+                    BROKER_NAME,
+                    METHOD_RELOAD_VOICE,
+                    rmp_serde::to_vec_named(
+                        &client::RequestReloadVoice {
+                            model_path: None,
+                        },
+                    )?
+                    .into(),
+                    QoS::Processed,
+                )
+                .await
+                .map_err(|e| {
+                    LinuxModuleError::FailedToLoadVoice(
+                        rpc_error_message(&e),
+                    )
+                })?;
+
+            let response: client::ResponseReloadVoice =
+                rmp_serde::from_slice(result.payload())?;
+            // Throws error if the result is not successful
+            response
+                .result
+                .map_err(LinuxModuleError::FailedToLoadVoice)?;
+            Ok(())
+        } else {
+            Err(LinuxModuleError::BackendServiceNotStarted.into())
+        }
+    }
+
+    async fn list_speakers(
+        &self,
+        voice_id: Option<String>,
+    ) -> anyhow::Result<Vec<Speaker>> {
+        if let Some(client) = &self._client {
+            let result = client
+                .call(
+                    BROKER_NAME,
+                    METHOD_LIST_SPEAKERS,
+                    rmp_serde::to_vec_named(
+                        &client::RequestListSpeakers { voice_id },
+                    )?
+                    .into(),
+                    QoS::Processed,
+                )
+                .await
+                .map_err(|e| {
+                    LinuxModuleError::FailedToLoadVoice(
+                        rpc_error_message(&e),
+                    )
+                })?;
+
+            let response: client::ResponseListSpeakers =
+                rmp_serde::from_slice(result.payload())?;
+            // Throws error if the result is not successful
+            let speakers = response
+                .result
+                .map_err(LinuxModuleError::FailedToLoadVoice)?;
+            Ok(speakers
+                .into_iter()
+                .map(|speaker| Speaker {
+                    id: speaker.id,
+                    name: speaker.name,
+                })
+                .collect())
+        } else {
+            Err(LinuxModuleError::BackendServiceNotStarted.into())
+        }
+    }
+
+    async fn synthesize_to_file(
+        &self,
+        text: String,
+        voice_id: Option<String>,
+        path: PathBuf,
+        config: Arc<RwLock<ConfigManager>>,
+    ) -> anyhow::Result<ExportedAudio> {
+        let params = speech_params(voice_id, config).await?;
+        let format =
+            match path.extension().and_then(|ext| ext.to_str()) {
+                Some("ogg") => AudioFileFormat::Ogg,
+                _ => AudioFileFormat::Wav,
+            };
+
+        if let Some(client) = &self._client {
+            let result = client
+                .call(
+                    BROKER_NAME,
+                    METHOD_SYNTHESIZE_TO_FILE,
+                    rmp_serde::to_vec_named(
+                        &client::RequestSynthesizeToFile {
+                            phrase: text,
+                            params,
+                            path: path.display().to_string(),
+                            format,
+                        },
+                    )?
+                    .into(),
+                    QoS::Processed,
+                )
+                .await
+                .map_err(|e| {
+                    LinuxModuleError::FailedToExportAudio(
+                        rpc_error_message(&e),
+                    )
+                })?;
+
+            let response: client::ResponseSynthesizeToFile =
+                rmp_serde::from_slice(result.payload())?;
+            // Throws error if the result is not successful
+            let audio = response
+                .result
+                .map_err(LinuxModuleError::FailedToExportAudio)?;
+            Ok(ExportedAudio {
+                path: PathBuf::from(audio.path),
+                duration_ms: audio.duration_ms,
+                sample_rate: audio.sample_rate,
+            })
+        } else {
+            Err(LinuxModuleError::BackendServiceNotStarted.into())
+        }
+    }
 }
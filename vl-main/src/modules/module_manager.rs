@@ -1,26 +1,32 @@
 use super::base::device_module::DeviceModule;
 use super::base::tts_module::TtsModule;
 #[cfg(target_os = "linux")]
+use super::linux::error::LinuxModuleError;
+#[cfg(target_os = "linux")]
+use super::linux::event_handlers::handler::LinuxModuleEventHandler;
+#[cfg(target_os = "linux")]
 use super::linux::linux_module;
+use crate::events::module_event::{
+    UtteranceStatus, UtteranceStatusEvent,
+};
 use crate::manager::Manager;
 use crate::modules::base::device_module;
 use crate::modules::base::i_module::IModule;
 use crate::modules::base::module::Module;
 use crate::modules::base::module::ModuleType;
 use crate::modules::base::tts_module;
+use crate::modules::base::tts_module::Speaker;
 use async_lock::RwLock;
 use bevy::ecs::resource::Resource;
-use bevy::time::Timer;
-use bevy::time::TimerMode;
 use bevy_egui::egui;
 use egui_file_dialog::FileDialog;
 use egui_notify::Toasts;
 use futures::executor;
+use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::Duration;
-use vl_global::audio_devices::AudioDeviceStatus;
-use vl_global::audio_devices::AudioDeviceType;
+use vl_global::audio_devices::AudioDevices;
 use vl_global::audio_devices::AudioDevicesComparison;
+use vl_global::audio_devices::AvailableDevices;
 use vl_global::vl_config::ConfigError;
 use vl_global::vl_config::ConfigManager;
 use vl_global::vl_config::VlConfig;
@@ -31,13 +37,26 @@ pub struct ModuleManager {
     pub file_dialog: Arc<RwLock<FileDialog>>,
     pub(super) toast: Toasts,
     pending_error_messages: Vec<String>,
+    pending_info_messages: Vec<String>,
     pub(crate) modules: Vec<Module>,
     pub(crate) selected_device_module:
         Option<Arc<RwLock<dyn DeviceModule>>>,
     pub(crate) selected_tts_module:
         Option<Arc<RwLock<dyn TtsModule>>>,
-    pub(crate) _timer: Timer,
     pub available_devices: Option<AudioDevicesComparison>,
+    /// Last device list reported by the device module
+    pub known_devices: Option<AvailableDevices>,
+    /// Utterance the TTS module is speaking right now
+    pub speaking_utterance: Option<u64>,
+    /// The utterance being spoken is paused
+    pub speech_paused: bool,
+    /// Voice used by [`ModuleManager::speak`], `None` for the
+    /// default one
+    pub selected_voice: Option<String>,
+    /// Speakers of the default voice
+    pub speakers: Vec<Speaker>,
+    #[cfg(target_os = "linux")]
+    linux_event_handler: LinuxModuleEventHandler,
 }
 
 impl Default for ModuleManager {
@@ -57,21 +76,28 @@ impl ModuleManager {
             config: app_config,
             toast: Toasts::default(),
             pending_error_messages: vec![],
+            pending_info_messages: vec![],
             modules: Vec::new(),
             selected_device_module: None,
             selected_tts_module: None,
-            _timer: Timer::new(
-                Duration::from_secs(1),
-                TimerMode::Repeating,
-            ),
             available_devices: None,
+            known_devices: None,
+            speaking_utterance: None,
+            speech_paused: false,
+            selected_voice: None,
+            speakers: Vec::new(),
+            #[cfg(target_os = "linux")]
+            linux_event_handler: LinuxModuleEventHandler::new(),
         }
     }
 
     pub async fn initialize(&mut self) -> &mut Self {
         #[cfg(target_os = "linux")]
         {
-            let module = linux_module::LinuxModule::new().await;
+            let module = linux_module::LinuxModule::new(
+                self.linux_event_handler.clone(),
+            )
+            .await;
             let linux_module = Arc::new(RwLock::new(module));
 
             let tts = Module::TtsModule(linux_module.clone());
@@ -191,12 +217,34 @@ impl ModuleManager {
     pub fn error(&mut self, text: String) {
         self.pending_error_messages.push(text);
     }
+    /// Show `text` for a module that failed to start, unless the
+    /// backend is incompatible: the user has to update then, and the
+    /// error says so
+    pub fn start_error(&mut self, text: String, e: &anyhow::Error) {
+        log::error!("{e}");
+        #[cfg(target_os = "linux")]
+        if let Some(e @ LinuxModuleError::IncompatibleBackend(_)) =
+            e.downcast_ref::<LinuxModuleError>()
+        {
+            self.error(e.to_string());
+            return;
+        }
+        self.error(text);
+    }
+    pub fn info(&mut self, text: String) {
+        self.pending_info_messages.push(text);
+    }
     pub fn _throw_error_message(&mut self, ctx: &mut egui::Context) {
         for error in &self.pending_error_messages {
             self.toast.error(error);
             log::error!("{error}");
         }
         self.pending_error_messages.clear();
+        for info in &self.pending_info_messages {
+            self.toast.info(info);
+            log::info!("{info}");
+        }
+        self.pending_info_messages.clear();
         self.toast.show(ctx);
     }
 
@@ -289,23 +337,74 @@ impl ModuleManager {
             return;
         }
 
+        // The module keeps these linked by itself, even when they
+        // come back while the UI is closed
         if let Some(devices_comparison) = &self.available_devices {
-            if let Some(devices) =
-                devices_comparison.0.get(&AudioDeviceType::INPUT)
-            {
-                if let Some(available_and_selected) = devices
-                    .get(&AudioDeviceStatus::SelectedAndAvailable)
-                {
-                    for device in available_and_selected {
-                        if let Err(e) = module
-                            .link_device(device.to_string())
-                            .await
-                        {
-                            log::error!("{e}");
-                        }
-                    }
-                }
+            let mut devices = devices_comparison.selected_devices();
+            // A source selected in the output list, by hand or by a
+            // rule, has no input ports to link the speech to
+            if let Some(known) = &self.known_devices {
+                devices
+                    .retain(|device| !known.is_source_only(device));
+            }
+            if let Err(e) = module.set_desired_links(devices).await {
+                log::error!("{e}");
+            }
+        }
+    }
+
+    /// Ask the device module for the current devices. Afterwards the
+    /// module pushes every change by itself.
+    pub async fn fetch_available_devices(&mut self) {
+        let Some(module) = self.selected_device_module.clone() else {
+            return;
+        };
+        let module = module.read().await;
+        if !module.is_started() {
+            return;
+        }
+        let devices = module.get_devices().await;
+        drop(module);
+
+        match devices {
+            Ok(devices) => {
+                self.update_available_devices(devices).await
+            }
+            Err(e) => log::error!("{e}"),
+        }
+    }
+
+    /// Compare `devices` with the selected ones and relink the devices
+    /// that came back.
+    pub async fn update_available_devices(
+        &mut self,
+        devices: AvailableDevices,
+    ) {
+        let config = self.config.read().await.read();
+        let config = match config {
+            Ok(config) => config,
+            Err(e) => {
+                log::error!("{e}");
+                return;
             }
+        };
+        let comparison =
+            AudioDevices::compare_lists(&devices, &config.devices);
+
+        // Verify if there are new connections that should be remade
+        // For example: If a screen was suddenly reopened
+        let needs_relinking =
+            self.available_devices.as_ref().is_some_and(|current| {
+                AudioDevicesComparison::are_there_reconnected_devices(
+                    current,
+                    &comparison,
+                )
+            });
+
+        self.available_devices = Some(comparison);
+        self.known_devices = Some(devices);
+        if needs_relinking {
+            self.relink_all_devices().await;
         }
     }
 
@@ -323,22 +422,166 @@ impl ModuleManager {
         }
     }
     /// Order the current tts module to play an audio from the text.
+    /// The module only queues the text, so this returns before the
+    /// audio is played.
     /// `WARNING: This should be called within a Tokio Runtime`
     pub async fn speak(&mut self, text: String) {
+        if let Some(tts_module) = &self.selected_tts_module {
+            let module = tts_module.read().await;
+            let voice_id = self.selected_voice.clone();
+            match module
+                .speak(text, voice_id, self.config.clone())
+                .await
+            {
+                Ok(Some(utterance_id)) => {
+                    log::debug!("Queued utterance {utterance_id}")
+                }
+                Ok(None) => log::debug!("Utterance was dropped"),
+                Err(e) => log::error!(
+                    "Error while trying to reproduce TTS {e}"
+                ),
+            }
+        }
+    }
+
+    /// Order the current tts module to write the audio of `text` into
+    /// `path` instead of playing it.
+    /// `WARNING: This should be called within a Tokio Runtime`
+    pub async fn export_audio(
+        &mut self,
+        text: String,
+        path: PathBuf,
+    ) {
+        let Some(tts_module) = self.selected_tts_module.clone()
+        else {
+            return;
+        };
+        let module = tts_module.read().await;
+        let voice_id = self.selected_voice.clone();
+        let result = module
+            .synthesize_to_file(
+                text,
+                voice_id,
+                path,
+                self.config.clone(),
+            )
+            .await;
+        drop(module);
+        match result {
+            Ok(audio) => self.info(format!(
+                "Áudio exportado: {} ({:.1} s)",
+                audio.path.display(),
+                audio.duration_ms as f32 / 1000.
+            )),
+            Err(e) => self.error(format!("{e}")),
+        }
+    }
+
+    /// Order the current tts module to stop speaking and drop
+    /// everything it had queued.
+    /// `WARNING: This should be called within a Tokio Runtime`
+    pub async fn stop_speaking(&mut self) {
         if let Some(tts_module) = &self.selected_tts_module {
             let module = tts_module.read().await;
             if let Err(e) = module.stop_speaking().await {
                 log::error!(
                     "Error while trying to stop the current audio {e}"
                 );
-            };
-            if let Err(e) =
-                module.speak(text, self.config.clone()).await
-            {
-                log::error!(
-                    "Error while trying to reproduce TTS {e}"
+            }
+            self.speech_paused = false;
+        }
+    }
+
+    /// `WARNING: This should be called within a Tokio Runtime`
+    pub async fn pause_speaking(&mut self) {
+        if let Some(tts_module) = &self.selected_tts_module {
+            let module = tts_module.read().await;
+            match module.pause_speaking().await {
+                Ok(()) => self.speech_paused = true,
+                Err(e) => log::error!(
+                    "Error while trying to pause the current audio {e}"
+                ),
+            }
+        }
+    }
+
+    /// `WARNING: This should be called within a Tokio Runtime`
+    pub async fn resume_speaking(&mut self) {
+        if let Some(tts_module) = &self.selected_tts_module {
+            let module = tts_module.read().await;
+            match module.resume_speaking().await {
+                Ok(()) => self.speech_paused = false,
+                Err(e) => log::error!(
+                    "Error while trying to resume the current audio {e}"
+                ),
+            }
+        }
+    }
+
+    /// Ask the current tts module for the speakers of the default
+    /// voice
+    /// `WARNING: This should be called within a Tokio Runtime`
+    pub async fn fetch_speakers(&mut self) {
+        if let Some(tts_module) = &self.selected_tts_module {
+            let module = tts_module.read().await;
+            match module.list_speakers(None).await {
+                Ok(speakers) => self.speakers = speakers,
+                Err(e) => {
+                    log::error!("Error while listing speakers {e}")
+                }
+            }
+        }
+    }
+
+    /// Order the current tts module to reload its voices, then fetch
+    /// the speakers of the new default voice.
+    /// `WARNING: This should be called within a Tokio Runtime`
+    pub async fn reload_voices(&mut self) {
+        if let Some(tts_module) = &self.selected_tts_module {
+            let module = tts_module.read().await;
+            if let Err(e) = module.reload_voices().await {
+                drop(module);
+                self.error(format!("{e}"));
+                return;
+            }
+        }
+        self.fetch_speakers().await;
+    }
+
+    pub fn is_speaking(&self) -> bool {
+        self.speaking_utterance.is_some()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.speech_paused
+    }
+
+    pub fn update_utterance_status(
+        &mut self,
+        event: &UtteranceStatusEvent,
+    ) {
+        log::debug!(
+            "Utterance {} {:?} at {}",
+            event.utterance_id,
+            event.status,
+            event.timestamp
+        );
+        match &event.status {
+            UtteranceStatus::Started => {
+                self.speaking_utterance = Some(event.utterance_id);
+                return;
+            }
+            UtteranceStatus::Error => {
+                self.error(
+                    "Failed to speak, see the backend log".to_owned(),
                 );
             }
+            UtteranceStatus::Finished
+            | UtteranceStatus::Cancelled => {}
+        }
+        if self.speaking_utterance == Some(event.utterance_id) {
+            self.speaking_utterance = None;
+            self.speech_paused = false;
         }
     }
 }
@@ -349,11 +592,8 @@ impl Manager for ModuleManager {
         {
             use bevy::app::Update;
 
-            use crate::modules::linux::event_handlers::handler::{
-                LinuxModuleEventHandler,
-                linux_module_event_handler_update,
-            };
-            app.insert_resource(LinuxModuleEventHandler::new());
+            use crate::modules::linux::event_handlers::handler::linux_module_event_handler_update;
+            app.insert_resource(self.linux_event_handler.clone());
             app.add_systems(
                 Update,
                 linux_module_event_handler_update,
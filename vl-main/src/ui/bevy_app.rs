@@ -6,7 +6,7 @@ use crate::{
     events::{
         module_event::{
             ModuleEvent, initialize_module_manager,
-            module_event_handler, module_manager_ticker,
+            module_event_handler,
         },
         screen_event::ScreenEvent,
     },
@@ -52,10 +52,7 @@ pub fn run() {
     app.insert_resource(screen_manager);
     app.add_event::<ModuleEvent>();
     app.add_systems(Startup, initialize_module_manager);
-    app.add_systems(
-        Update,
-        (module_event_handler, module_manager_ticker),
-    );
+    app.add_systems(Update, module_event_handler);
     app.add_plugins(TokioTasksPlugin::default());
     app.add_plugins(
         DefaultPlugins
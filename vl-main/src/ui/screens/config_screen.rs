@@ -1,4 +1,5 @@
 
+use std::collections::HashMap;
 use std::sync::Arc;
 use anyhow::Ok;
 use async_lock::RwLock;
@@ -18,12 +19,22 @@ use egui_taffy::taffy::Style;
 use egui_taffy::{TuiBuilderLogic, taffy, tui};
 use futures::executor;
 use vl_global::audio_devices::AudioDeviceStatus;
+use vl_global::audio_devices::AudioDeviceType;
+use vl_global::audio_devices::AvailableDevices;
+use vl_global::audio_devices::DeviceInfo;
+use vl_global::audio_devices::DescriptionRegex;
+use vl_global::audio_devices::DeviceRule;
 use egui_extras::{Column, TableBuilder};
 use vl_global::vl_config::VlConfig;
+use vl_global::vl_config::DEFAULT_VOICE_ID;
+use vl_global::vl_config::LinuxConfig;
+use vl_global::vl_config::VoiceConfig;
+use vl_global::vl_config::VoiceEngine;
 use crate::events::module_event::ModuleEvent;
 use crate::events::module_event::UpdateDeviceSelectionEvent;
 use crate::modules::base::i_module::IModule;
 use crate::modules::base::module::Module;
+use crate::modules::base::tts_module::Speaker;
 use crate::modules::module_manager::ModuleManager;
 use crate::ui::screens::ScreenParameters;
 
@@ -33,6 +44,32 @@ use super::main_screen::MainScreen;
 
 #[derive(Default)]
 pub struct ConfigScreen {
+    /// ID typed for a new alternative voice
+    new_voice_id: String,
+    /// Voice the file dialog picks a model for, `None` for the default voice
+    picking_voice: Option<String>,
+    /// Rule being typed for each device type
+    new_rules: HashMap<AudioDeviceType, DeviceRule>,
+    /// App typed for a new ducking level
+    new_ducking_app: String,
+    /// espeak-ng voice name typed for a new espeak-ng voice
+    new_espeak_voice: String,
+}
+
+/// Loudness picked when the normalization is turned on
+const DEFAULT_LOUDNESS_TARGET_LUFS: i8 = -18;
+
+/// Applications whose capture streams the browser shortcut selects
+const BROWSER_APPLICATIONS: [&str; 3] = ["Firefox", "Chromium", "Google Chrome"];
+
+fn rule_kind_name(rule: &DeviceRule) -> &'static str {
+    match rule {
+        DeviceRule::Name(_) => "Nome",
+        DeviceRule::ApplicationName(_) => "Aplicativo",
+        DeviceRule::DescriptionGlob(_) => "Descrição (glob)",
+        DeviceRule::DescriptionRegex(_) => "Descrição (regex)",
+        DeviceRule::MediaClass(_) => "Classe de mídia",
+    }
 }
 
 
@@ -102,9 +139,10 @@ impl Screen for ConfigScreen {
 
                             self.show_modules_widget(&mut module_manager, ui, config, &mut tokio);
                             
-                            self.show_linux_tts_widget(ui, file_dialog.clone(), config);
+                            let known_devices = module_manager.known_devices.clone().unwrap_or_default();
+                            self.show_linux_tts_widget(ui, file_dialog.clone(), config, &module_manager.speakers, &known_devices, &mut module_event_w);
                             
-                            self.show_devices_widget(ui, &mut module_manager, &mut module_event_w);
+                            self.show_devices_widget(ui, config, &mut module_manager, &mut module_event_w);
                             Ok(())
                         },
                     ).unwrap();
@@ -118,8 +156,9 @@ impl Screen for ConfigScreen {
 
 impl ConfigScreen{
     pub fn show_devices_widget(
-        &self,
+        &mut self,
         ui: &mut egui::Ui,
+        config: &mut VlConfig,
         module_manager: &mut ResMut<'_, ModuleManager>,
         module_event_w: &mut EventWriter<'_, ModuleEvent>,
     ){
@@ -127,6 +166,7 @@ impl ConfigScreen{
             return;
         }
         let comparison = module_manager.available_devices.clone().unwrap();
+        let known_devices = module_manager.known_devices.clone().unwrap_or_default();
         tui(ui, ui.id().with("devices_panel")).reserve_available_space().style(Style{
             flex_direction: taffy::FlexDirection::Row,
             min_size: taffy::Size {
@@ -159,6 +199,9 @@ impl ConfigScreen{
                 ).add_with_border(|tui|{
                     tui.ui(|ui|{
                         ui.heading(str_device_type.to_string());
+                        if self.show_device_rules(ui, config, &device_type) {
+                            module_event_w.write(ModuleEvent::DeviceRulesChanged);
+                        }
                         let text_height = egui::TextStyle::Body
                             .resolve(ui.style())
                             .size
@@ -179,14 +222,28 @@ impl ConfigScreen{
 
                         table.body(|mut body| {
                             for (status, devices) in status {
-                                let mut devices = devices.clone();
-                                devices.sort();
-                                for device in devices {
+                                // The config stores node names, show the friendly name of the devices that are available
+                                let mut devices: Vec<(String, Option<&DeviceInfo>)> = devices.iter().map(|name| {
+                                    (name.clone(), known_devices.find(&device_type, name))
+                                }).collect();
+                                devices.sort_by_key(|(name, info)| {
+                                    info.map_or(name.clone(), |info| info.display_name().to_owned())
+                                });
+                                for (device, info) in devices {
                                     body.row(text_height, |mut row| {
                                         let mut selected: bool = status.is_selected();
                                         
                                         row.col(|ui| {
-                                            let checkbox = ui.checkbox(&mut selected, "");
+                                            // Only a rule change unselects a device selected by a rule
+                                            let by_rule = status == AudioDeviceStatus::MatchedByRule;
+                                            // Nothing can be linked to a microphone, it can only be unselected
+                                            let source_only = !selected && known_devices.is_source_only(&device);
+                                            let checkbox = ui.add_enabled(!by_rule && !source_only, egui::Checkbox::without_text(&mut selected))
+                                                .on_disabled_hover_text(if by_rule {
+                                                    "Selecionado por uma regra"
+                                                } else {
+                                                    "Este dispositivo não recebe áudio"
+                                                });
                                             if checkbox.changed(){
                                                 module_event_w.write(ModuleEvent::UpdateDeviceSelection(UpdateDeviceSelectionEvent{
                                                      selected, device_type: device_type.clone(), name: device.clone() 
@@ -194,11 +251,21 @@ impl ConfigScreen{
                                             }
                                         });
                                         row.col(|ui| {
-                                        let mut text = RichText::new(device);
+                                            let display_name = info.map_or(device.as_str(), |info| info.display_name());
+                                            let mut text = RichText::new(display_name);
                                             if status == AudioDeviceStatus::SelectedButNotAvailable {
                                                 text = text.color(Color32::from_rgb(64, 64, 64));
                                             }
-                                            ui.label(text);
+                                            let label = ui.label(text);
+                                            match info {
+                                                Some(info) => label.on_hover_text(format!(
+                                                    "{}\n{} - {} canais",
+                                                    info.name,
+                                                    info.media_class.as_deref().unwrap_or("?"),
+                                                    info.channels,
+                                                )),
+                                                None => label.on_hover_text(device.as_str()),
+                                            };
                                         });
                                     });
                                 }
@@ -210,13 +277,99 @@ impl ConfigScreen{
         });
     }
 
+    /// Rules that select devices of `device_type`. Returns whether
+    /// they changed.
+    fn show_device_rules(
+        &mut self,
+        ui: &mut egui::Ui,
+        config: &mut VlConfig,
+        device_type: &AudioDeviceType,
+    ) -> bool {
+        let mut changed = false;
+        let rules = config.devices.rules_mut(device_type);
+        egui::CollapsingHeader::new("Regras")
+            .id_salt(ui.id().with(("device_rules", device_type)))
+            .show(ui, |ui| {
+                let mut remove = None;
+                for (index, rule) in rules.iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        if ui.small_button(egui_material_icons::icons::ICON_DELETE).clicked() {
+                            remove = Some(index);
+                        }
+                        ui.label(format!("{}: {}", rule_kind_name(rule), rule.value()));
+                    });
+                }
+                if let Some(index) = remove {
+                    rules.remove(index);
+                    changed = true;
+                }
+
+                let new_rule = self
+                    .new_rules
+                    .entry(device_type.clone())
+                    .or_insert_with(|| DeviceRule::ApplicationName(String::new()));
+                ui.horizontal(|ui| {
+                    let value = new_rule.value().to_owned();
+                    egui::ComboBox::from_id_salt(ui.id().with(("new_rule_kind", device_type)))
+                        .selected_text(rule_kind_name(new_rule))
+                        .show_ui(ui, |ui| {
+                            for kind in [
+                                DeviceRule::Name(value.clone()),
+                                DeviceRule::ApplicationName(value.clone()),
+                                DeviceRule::DescriptionGlob(value.clone()),
+                                DeviceRule::DescriptionRegex(DescriptionRegex::new(value.clone())),
+                                DeviceRule::MediaClass(value.clone()),
+                            ] {
+                                let name = rule_kind_name(&kind);
+                                let selected = name == rule_kind_name(new_rule);
+                                if ui.selectable_label(selected, name).clicked() {
+                                    *new_rule = kind;
+                                }
+                            }
+                        });
+                    let mut value = value;
+                    if ui.text_edit_singleline(&mut value).changed() {
+                        new_rule.set_value(value);
+                    }
+                    let validation = new_rule.validate();
+                    let add = ui.add_enabled(validation.is_ok(), egui::Button::new("Adicionar"));
+                    let add = match &validation {
+                        Err(e) if !new_rule.value().is_empty() => add.on_disabled_hover_text(e.as_str()),
+                        _ => add,
+                    };
+                    if add.clicked() && !rules.contains(new_rule) {
+                        rules.push(new_rule.clone());
+                        new_rule.set_value(String::new());
+                        changed = true;
+                    }
+                });
+
+                if *device_type == AudioDeviceType::INPUT
+                    && ui.button("Sempre ligar a capturas do Firefox/Chromium").clicked()
+                {
+                    for application in BROWSER_APPLICATIONS {
+                        let rule = DeviceRule::ApplicationName(application.to_owned());
+                        if !rules.contains(&rule) {
+                            rules.push(rule);
+                            changed = true;
+                        }
+                    }
+                }
+            });
+        changed
+    }
+
     pub fn show_linux_tts_widget(
-        &self,
+        &mut self,
         ui: &mut egui::Ui,
         file_dialog: Arc<RwLock<FileDialog>>,
         config: &mut vl_global::vl_config::VlConfig,
+        speakers: &[Speaker],
+        known_devices: &AvailableDevices,
+        module_event_w: &mut EventWriter<ModuleEvent>,
     ){
         let mut file_dialog_guard = executor::block_on(file_dialog.write());
+        let mut voices_changed = false;
 
         if let Some(linux) = &mut config.linux {
             ui.heading("Configurações do Linux Module");
@@ -229,15 +382,251 @@ impl ConfigScreen{
                 //         file_dialog.initial_directory(path.to_path_buf());
                 //     }
                 // }
+                self.picking_voice = None;
                 file_dialog_guard.pick_file();
             }
+
+            ui.label("Vozes alternativas");
+            let mut removed_voice = None;
+            for voice in &linux.voices {
+                let engine = match voice.engine {
+                    VoiceEngine::Piper => "Piper",
+                    VoiceEngine::EspeakNg => "espeak-ng",
+                };
+                ui.horizontal(|ui| {
+                    ui.label(format!("{} ({engine}): {}", voice.id, voice.voice));
+                    if ui.button(egui_material_icons::icons::ICON_DELETE).clicked() {
+                        removed_voice = Some(voice.id.clone());
+                    }
+                });
+            }
+            if let Some(voice_id) = removed_voice {
+                linux.voices.retain(|voice| voice.id != voice_id);
+                voices_changed = true;
+            }
+            // IDs are unique across engines
+            let voice_id = self.new_voice_id.trim().to_owned();
+            let valid_id = !voice_id.is_empty()
+                && voice_id != DEFAULT_VOICE_ID
+                && !linux.voices.iter().any(|voice| voice.id == voice_id);
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut self.new_voice_id);
+                if ui.add_enabled(valid_id, egui::Button::new("Adicionar voz")).clicked() {
+                    self.picking_voice = Some(voice_id.clone());
+                    file_dialog_guard.pick_file();
+                }
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Voz do espeak-ng (ex.: pt-br)");
+                ui.text_edit_singleline(&mut self.new_espeak_voice);
+                let voice_name = self.new_espeak_voice.trim().to_owned();
+                let valid = valid_id && !voice_name.is_empty();
+                if ui.add_enabled(valid, egui::Button::new("Adicionar voz espeak-ng")).clicked() {
+                    linux.voices.push(VoiceConfig {
+                        id: voice_id.clone(),
+                        engine: VoiceEngine::EspeakNg,
+                        voice: voice_name,
+                    });
+                    self.new_voice_id.clear();
+                    self.new_espeak_voice.clear();
+                    voices_changed = true;
+                }
+            });
+
             if let Some(path) = file_dialog_guard.take_picked() {
                 let path = path.to_path_buf();
                 if linux.validate_piper_tts_model(&path){
-                    linux.piper_tts_model = path.display().to_string();
+                    let model_path = path.display().to_string();
+                    match self.picking_voice.take() {
+                        Some(voice_id) => {
+                            linux.voices.push(VoiceConfig {
+                                id: voice_id,
+                                engine: VoiceEngine::Piper,
+                                voice: model_path,
+                            });
+                            self.new_voice_id.clear();
+                        }
+                        None => linux.piper_tts_model = model_path,
+                    }
+                    voices_changed = true;
                 }
             }
+
+            Self::show_speech_params(ui, linux, speakers);
+
+            ui.checkbox(&mut linux.detach_from_default_sink, "Tocar a voz apenas nos dispositivos selecionados")
+                .on_hover_text("Desliga a voz da saída padrão do sistema");
+
+            Self::show_mix_params(ui, linux, known_devices);
+
+            self.show_ducking_params(ui, linux);
+        }
+
+        if voices_changed {
+            module_event_w.write(ModuleEvent::ReloadVoices);
+        }
+    }
+
+    /// Draw the speaker, pitch, volume, rate, silence, emergency and
+    /// loudness controls
+    fn show_speech_params(
+        ui: &mut egui::Ui,
+        linux: &mut LinuxConfig,
+        speakers: &[Speaker],
+    ){
+        egui::Grid::new("speech_params").num_columns(2).show(ui, |ui| {
+            ui.label("Locutor");
+            if speakers.is_empty() {
+                ui.add(egui::DragValue::new(&mut linux.speaker_id).range(0..=i64::MAX));
+            } else {
+                let selected_text = speakers
+                    .iter()
+                    .find(|speaker| speaker.id == linux.speaker_id)
+                    .map(|speaker| speaker.name.clone())
+                    .unwrap_or_else(|| linux.speaker_id.to_string());
+                egui::ComboBox::from_id_salt("speaker_selector")
+                    .selected_text(selected_text)
+                    .show_ui(ui, |ui| {
+                        for speaker in speakers {
+                            ui.selectable_value(
+                                &mut linux.speaker_id,
+                                speaker.id,
+                                format!("{} ({})", speaker.name, speaker.id),
+                            );
+                        }
+                    });
+            }
+            ui.end_row();
+
+            ui.label("Tom");
+            ui.add(egui::Slider::new(&mut linux.pitch, 0..=100));
+            ui.end_row();
+
+            ui.label("Volume");
+            ui.add(egui::Slider::new(&mut linux.volume, 0..=255));
+            ui.end_row();
+
+            ui.label("Velocidade");
+            ui.add(egui::Slider::new(&mut linux.rate, 0..=100));
+            ui.end_row();
+
+            ui.label("Silêncio entre frases");
+            ui.add(egui::DragValue::new(&mut linux.appended_silence_ms).range(0..=5000).suffix(" ms"));
+            ui.end_row();
+
+            ui.label("Volume mínimo de emergência");
+            ui.add(egui::Slider::new(&mut linux.emergency_volume_floor, 0..=255));
+            ui.end_row();
+
+            ui.label("Repetições de emergência");
+            ui.add(egui::DragValue::new(&mut linux.emergency_repeat).range(0..=10));
+            ui.end_row();
+
+            ui.label("Normalizar volume");
+            ui.horizontal(|ui| {
+                let mut normalize = linux.loudness_target_lufs.is_some();
+                if ui.checkbox(&mut normalize, "").changed() {
+                    linux.loudness_target_lufs = normalize.then_some(DEFAULT_LOUDNESS_TARGET_LUFS);
+                }
+                if let Some(target) = &mut linux.loudness_target_lufs {
+                    ui.add(egui::Slider::new(target, -30..=-10).suffix(" LUFS"));
+                }
+            });
+            ui.end_row();
+        });
+    }
+
+    /// Draw the microphone mixed into the virtual microphone and the
+    /// gain of each source of the mix
+    fn show_mix_params(
+        ui: &mut egui::Ui,
+        linux: &mut LinuxConfig,
+        known_devices: &AvailableDevices,
+    ){
+        // Capture devices, the virtual microphone itself left out
+        let microphones: Vec<&DeviceInfo> = known_devices
+            .output_devices
+            .iter()
+            .filter(|device| {
+                device.media_class.as_deref() == Some("Audio/Source")
+            })
+            .collect();
+
+        ui.label("Microfone virtual");
+        egui::Grid::new("mix_params").num_columns(2).show(ui, |ui| {
+            ui.label("Microfone");
+            let selected_text = match &linux.mix_microphone {
+                None => "Nenhum".to_owned(),
+                Some(name) => microphones
+                    .iter()
+                    .find(|device| device.name == *name)
+                    .map(|device| device.display_name().to_owned())
+                    .unwrap_or_else(|| name.clone()),
+            };
+            egui::ComboBox::from_id_salt("mix_microphone_selector")
+                .selected_text(selected_text)
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut linux.mix_microphone, None, "Nenhum");
+                    for device in &microphones {
+                        ui.selectable_value(
+                            &mut linux.mix_microphone,
+                            Some(device.name.clone()),
+                            device.display_name(),
+                        );
+                    }
+                });
+            ui.end_row();
+
+            ui.label("Ganho do microfone");
+            ui.add(egui::Slider::new(&mut linux.mix_microphone_gain, 0..=200).suffix(" %"));
+            ui.end_row();
+
+            ui.label("Ganho da voz");
+            ui.add(egui::Slider::new(&mut linux.mix_tts_gain, 0..=200).suffix(" %"));
+            ui.end_row();
+        });
+    }
+
+    /// Draw how much the other apps are lowered while the voice plays,
+    /// with a level for each app
+    fn show_ducking_params(
+        &mut self,
+        ui: &mut egui::Ui,
+        linux: &mut LinuxConfig,
+    ){
+        let ducking = &mut linux.ducking;
+        ui.checkbox(&mut ducking.enabled, "Abaixar outros aplicativos enquanto fala");
+        if !ducking.enabled {
+            return;
         }
+        ui.horizontal(|ui| {
+            ui.label("Volume dos outros aplicativos");
+            ui.add(egui::Slider::new(&mut ducking.level, 0..=100).suffix(" %"));
+        });
+
+        let mut removed_app = None;
+        for (app, level) in ducking.apps.iter_mut() {
+            ui.horizontal(|ui| {
+                ui.label(app.as_str());
+                ui.add(egui::Slider::new(level, 0..=100).suffix(" %"));
+                if ui.button(egui_material_icons::icons::ICON_DELETE).clicked() {
+                    removed_app = Some(app.clone());
+                }
+            });
+        }
+        if let Some(app) = removed_app {
+            ducking.apps.remove(&app);
+        }
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.new_ducking_app)
+                .on_hover_text("Nome do aplicativo, 100 % o deixa como está");
+            let app = self.new_ducking_app.trim().to_owned();
+            if ui.add_enabled(!app.is_empty(), egui::Button::new("Adicionar aplicativo")).clicked() {
+                ducking.apps.insert(app, ducking.level);
+                self.new_ducking_app.clear();
+            }
+        });
     }
 
 
@@ -340,10 +729,13 @@ pub async fn show_module_options_widget(
     let button = ui.button(format!("Iniciar {selected_type}")); 
     if button.clicked() {
         if let Err(e) = selected_module.start().await {
-            module_manager.error(format!(
+            module_manager.start_error(format!(
                 "Failed to start {selected_name}!",
-            ));
-            log::error!("{e}");
+            ), &e);
+        } else {
+            // Further changes are pushed by the module itself
+            module_manager.fetch_available_devices().await;
+            module_manager.fetch_speakers().await;
         }
     }
 
@@ -1,6 +1,8 @@
 use core::f32;
 
+use crate::events::module_event::ExportAudioEvent;
 use crate::events::module_event::ModuleEvent;
+use crate::modules::module_manager::ModuleManager;
 use crate::ui::screens::ScreenParameters;
 use crate::ui::virtual_keyboard::Keyboard;
 use bevy::app::AppExit;
@@ -16,6 +18,7 @@ use egui_taffy::taffy::prelude::length;
 use egui_taffy::taffy::prelude::percent;
 use egui_taffy::{TuiBuilderLogic, taffy, tui};
 use futures::executor;
+use vl_global::vl_config::DEFAULT_VOICE_ID;
 
 use super::Screen;
 use super::ScreenEvent;
@@ -26,6 +29,8 @@ pub struct MainScreen {
     text: String,
     suggestion_text: Option<String>,
     keyboard_enabled: bool,
+    /// The shared file dialog is picking where to export the audio
+    exporting_audio: bool,
 }
 
 impl MainScreen {
@@ -71,6 +76,8 @@ impl MainScreen {
         &self,
         tui: &mut egui_taffy::Tui,
         button_width: f32,
+        is_speaking: bool,
+        is_paused: bool,
         mut module_event_w: EventWriter<'_, ModuleEvent>,
     ) {
         tui.style(taffy::Style {
@@ -96,6 +103,50 @@ impl MainScreen {
                     module_event_w
                         .write(ModuleEvent::Speak(self.text.clone()));
                 }
+            });
+            tui.ui(|ui| {
+                let (icon, event) = if is_paused {
+                    (
+                        egui_material_icons::icons::ICON_PLAY_ARROW,
+                        ModuleEvent::ResumeSpeaking,
+                    )
+                } else {
+                    (
+                        egui_material_icons::icons::ICON_PAUSE,
+                        ModuleEvent::PauseSpeaking,
+                    )
+                };
+                let button = Button::new(icon).corner_radius(0);
+                ui.add_enabled_ui(is_speaking, |ui| {
+                    if ui
+                        .add_sized(
+                            [button_width, ui.available_height()],
+                            button,
+                        )
+                        .clicked()
+                    {
+                        module_event_w.write(event);
+                    }
+                });
+            });
+            tui.ui(|ui| {
+                let button = Button::new(
+                    egui_material_icons::icons::ICON_STOP,
+                )
+                .corner_radius(0);
+                // Only useful while there's something being spoken
+                ui.add_enabled_ui(is_speaking, |ui| {
+                    if ui
+                        .add_sized(
+                            [button_width, ui.available_height()],
+                            button,
+                        )
+                        .clicked()
+                    {
+                        module_event_w
+                            .write(ModuleEvent::StopSpeaking);
+                    }
+                });
             })
         });
     }
@@ -140,10 +191,96 @@ impl MainScreen {
         });
     }
 
+    fn show_speaking_indicator(ui: &mut egui::Ui, is_speaking: bool) {
+        if is_speaking {
+            ui.label(format!(
+                "{} Falando...",
+                egui_material_icons::icons::ICON_GRAPHIC_EQ
+            ));
+        }
+    }
+
+    /// Voice selector, only shown when alternative voices are
+    /// configured
+    fn show_voice_selector(
+        ui: &mut egui::Ui,
+        module_manager: &mut ModuleManager,
+    ) {
+        let config_lock =
+            executor::block_on(module_manager.config.read());
+        let voice_ids: Vec<String> = match config_lock.read() {
+            Ok(config) => config
+                .linux
+                .map(|linux| {
+                    linux
+                        .voices
+                        .into_iter()
+                        .map(|voice| voice.id)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            Err(_) => vec![],
+        };
+        drop(config_lock);
+
+        // Forget voices removed from the config
+        let selected_voice = &mut module_manager.selected_voice;
+        if selected_voice
+            .as_ref()
+            .is_some_and(|voice_id| !voice_ids.contains(voice_id))
+        {
+            *selected_voice = None;
+        }
+        if voice_ids.is_empty() {
+            return;
+        }
+
+        let selected_text =
+            selected_voice.as_deref().unwrap_or(DEFAULT_VOICE_ID);
+        egui::ComboBox::from_id_salt("voice_selector")
+            .selected_text(format!(
+                "{} {selected_text}",
+                egui_material_icons::icons::ICON_RECORD_VOICE_OVER
+            ))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(
+                    selected_voice,
+                    None,
+                    DEFAULT_VOICE_ID,
+                );
+                for voice_id in voice_ids {
+                    ui.selectable_value(
+                        selected_voice,
+                        Some(voice_id.clone()),
+                        voice_id,
+                    );
+                }
+            });
+    }
+
     fn show_menu_buttons(
         &mut self,
         ui: &mut egui::Ui,
         screen_event_w: &mut EventWriter<ScreenEvent>,
+        module_manager: &mut ModuleManager,
+    ) {
+        let is_speaking = module_manager.is_speaking();
+        ui.horizontal(|ui| {
+            self.show_settings_menu(
+                ui,
+                screen_event_w,
+                module_manager,
+            );
+            Self::show_voice_selector(ui, module_manager);
+            Self::show_speaking_indicator(ui, is_speaking);
+        });
+    }
+
+    fn show_settings_menu(
+        &mut self,
+        ui: &mut egui::Ui,
+        screen_event_w: &mut EventWriter<ScreenEvent>,
+        module_manager: &mut ModuleManager,
     ) {
         ui.menu_button(
             egui_material_icons::icons::ICON_SETTINGS,
@@ -155,6 +292,20 @@ impl MainScreen {
                 if keyboard.clicked() {
                     self.keyboard_enabled = !self.keyboard_enabled;
                 }
+                let export = ui.add_enabled(
+                    !self.text.is_empty(),
+                    Button::new(format!(
+                        "{} Exportar áudio...",
+                        egui_material_icons::icons::ICON_SAVE
+                    )),
+                );
+                if export.clicked() {
+                    self.exporting_audio = true;
+                    executor::block_on(
+                        module_manager.file_dialog.write(),
+                    )
+                    .save_file();
+                }
                 let preferences = ui.button(format!(
                     "{} Preferências...",
                     egui_material_icons::icons::ICON_SETTINGS
@@ -170,6 +321,31 @@ impl MainScreen {
             },
         );
     }
+
+    /// Draw the save dialog of "Export audio" and send the export
+    /// once a path is picked
+    fn show_export_dialog(
+        &mut self,
+        ctx: &mut egui::Context,
+        module_manager: &mut ModuleManager,
+        module_event_w: &mut EventWriter<ModuleEvent>,
+    ) {
+        if !self.exporting_audio {
+            return;
+        }
+        let mut file_dialog =
+            executor::block_on(module_manager.file_dialog.write());
+        file_dialog.update(ctx);
+        if let Some(path) = file_dialog.take_picked() {
+            self.exporting_audio = false;
+            module_event_w.write(ModuleEvent::ExportAudio(
+                ExportAudioEvent {
+                    text: self.text.clone(),
+                    path,
+                },
+            ));
+        }
+    }
 }
 
 impl Screen for MainScreen {
@@ -194,6 +370,22 @@ impl Screen for MainScreen {
                         .module_event_w
                         .write(ModuleEvent::Speak(self.text.clone()));
                 }
+                // Toggle the pause of the current utterance
+                KeyCode::F7 | KeyCode::MediaPlayPause
+                    if params.module_manager.is_speaking() =>
+                {
+                    let event = if params.module_manager.is_paused() {
+                        ModuleEvent::ResumeSpeaking
+                    } else {
+                        ModuleEvent::PauseSpeaking
+                    };
+                    params.module_event_w.write(event);
+                }
+                KeyCode::F8 => {
+                    params
+                        .module_event_w
+                        .write(ModuleEvent::StopSpeaking);
+                }
                 _ => {}
             }
         }
@@ -210,7 +402,18 @@ impl Screen for MainScreen {
         let mut work_area = params.work_area;
         work_area.y = 0.;
 
-        self.show_menu_buttons(ui, &mut params.screen_event_w);
+        let is_speaking = params.module_manager.is_speaking();
+        let is_paused = params.module_manager.is_paused();
+        self.show_menu_buttons(
+            ui,
+            &mut params.screen_event_w,
+            &mut params.module_manager,
+        );
+        self.show_export_dialog(
+            params.ctx,
+            &mut params.module_manager,
+            &mut params.module_event_w,
+        );
         let keyboard = params.keyboard.clone();
 
         tui(ui, ui.id().with("demo"))
@@ -253,6 +456,8 @@ impl Screen for MainScreen {
                     self.show_run_button(
                         tui,
                         button_width,
+                        is_speaking,
+                        is_paused,
                         params.module_event_w,
                     );
                 });